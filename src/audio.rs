@@ -1,18 +1,280 @@
-use rodio::{Decoder, OutputStreamHandle, Sink, Source};
-use std::sync::{Arc, Mutex};
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+use rodio::source::UniformSourceIterator;
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Source};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Format every sample is converted to on load, so the mixer never resamples
+const MIXER_CHANNELS: u16 = 2;
+const MIXER_SAMPLE_RATE: u32 = 44100;
+
+// Oldest voice gets stolen once this many hits overlap
+const MAX_VOICES: usize = 32;
+
+// How many output samples the mixer renders between checks for new voices.
+// 64 samples = 32 stereo frames, well under one device buffer.
+const COMMAND_POLL_INTERVAL: usize = 64;
+
+// Released hold loops fade out over ~60ms instead of cutting off with a click
+const LOOP_FADE_OUT_SAMPLES: f32 = MIXER_SAMPLE_RATE as f32 * MIXER_CHANNELS as f32 * 0.06;
+
+/// A sound decoded once into interleaved PCM in the mixer's format
+#[derive(Clone)]
+pub struct SoundSample {
+    samples: Arc<Vec<f32>>,
+}
+
+impl SoundSample {
+    fn load(path: &str) -> Option<Self> {
+        let data = std::fs::read(path).ok()?;
+        let decoder = Decoder::new(std::io::Cursor::new(data)).ok()?;
+        let converted = UniformSourceIterator::<_, f32>::new(decoder, MIXER_CHANNELS, MIXER_SAMPLE_RATE);
+        let samples: Vec<f32> = converted.collect();
+        if samples.is_empty() {
+            return None;
+        }
+        Some(Self {
+            samples: Arc::new(samples),
+        })
+    }
+}
+
+struct Voice {
+    samples: Arc<Vec<f32>>,
+    position: usize,
+    volume: f32,
+}
+
+/// Identifies a hold loop: (lane, note index)
+pub type LoopKey = (usize, usize);
+
+struct LoopVoice {
+    key: LoopKey,
+    samples: Arc<Vec<f32>>,
+    position: usize,
+    volume: f32,
+    // Set once released; counts down to 0 and then the loop is dropped
+    fade_gain: Option<f32>,
+}
+
+enum MixerCommand {
+    Play(Voice),
+    StartLoop(LoopVoice),
+    StopLoop(LoopKey),
+    PauseLoops,
+    ResumeLoops,
+    StopAllLoops,
+    SetVolume(f32),
+}
+
+/// Single persistent source that sums every active one-shot voice and hold loop.
+/// Commands arrive over a channel so the game thread never blocks the audio thread.
+struct Mixer {
+    commands: Receiver<MixerCommand>,
+    voices: Vec<Voice>,
+    loops: Vec<LoopVoice>,
+    loops_paused: bool,
+    volume: f32,
+    until_poll: usize,
+    disconnected: bool,
+}
+
+impl Mixer {
+    fn new(commands: Receiver<MixerCommand>) -> Self {
+        Self {
+            commands,
+            voices: Vec::with_capacity(MAX_VOICES),
+            loops: Vec::new(),
+            loops_paused: false,
+            volume: 1.0,
+            until_poll: 0,
+            disconnected: false,
+        }
+    }
+
+    fn poll_commands(&mut self) {
+        loop {
+            match self.commands.try_recv() {
+                Ok(MixerCommand::Play(voice)) => {
+                    if self.voices.len() >= MAX_VOICES {
+                        self.voices.remove(0);
+                    }
+                    self.voices.push(voice);
+                }
+                Ok(MixerCommand::StartLoop(voice)) => {
+                    // Restarting a key that is still fading out replaces it
+                    self.loops.retain(|l| l.key != voice.key);
+                    self.loops.push(voice);
+                }
+                Ok(MixerCommand::StopLoop(key)) => {
+                    for l in self.loops.iter_mut().filter(|l| l.key == key) {
+                        l.fade_gain.get_or_insert(1.0);
+                    }
+                }
+                Ok(MixerCommand::PauseLoops) => self.loops_paused = true,
+                Ok(MixerCommand::ResumeLoops) => self.loops_paused = false,
+                Ok(MixerCommand::StopAllLoops) => {
+                    self.loops.clear();
+                    self.loops_paused = false;
+                }
+                Ok(MixerCommand::SetVolume(volume)) => self.volume = volume,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    // Loops never end on their own, so drop them with the owner
+                    self.disconnected = true;
+                    self.loops.clear();
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for Mixer {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        // Only poll on frame boundaries so channels never get swapped
+        if self.until_poll == 0 {
+            self.poll_commands();
+            self.until_poll = COMMAND_POLL_INTERVAL;
+        }
+        self.until_poll -= 1;
+
+        // AudioSystem was dropped and nothing is left to play
+        if self.disconnected && self.voices.is_empty() {
+            return None;
+        }
+
+        let mut mixed = 0.0;
+        for voice in self.voices.iter_mut() {
+            mixed += voice.samples[voice.position] * voice.volume;
+            voice.position += 1;
+        }
+        self.voices.retain(|v| v.position < v.samples.len());
+
+        if !self.loops_paused {
+            for l in self.loops.iter_mut() {
+                let gain = match l.fade_gain.as_mut() {
+                    Some(g) => {
+                        *g -= 1.0 / LOOP_FADE_OUT_SAMPLES;
+                        g.max(0.0)
+                    }
+                    None => 1.0,
+                };
+                mixed += l.samples[l.position] * l.volume * gain;
+                l.position = (l.position + 1) % l.samples.len();
+            }
+            self.loops.retain(|l| l.fade_gain.is_none_or(|g| g > 0.0));
+        }
+
+        Some((mixed * self.volume).clamp(-1.0, 1.0))
+    }
+}
+
+impl Source for Mixer {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        MIXER_CHANNELS
+    }
+
+    fn sample_rate(&self) -> u32 {
+        MIXER_SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// The open output device. Holds no stream at all when no device could be opened,
+/// in which case everything plays silently instead of panicking.
+pub struct AudioOutput {
+    _stream: Option<OutputStream>,
+    handle: Option<OutputStreamHandle>,
+    device_name: Option<String>,
+}
+
+impl AudioOutput {
+    /// Open the device with the given name, falling back to the system default,
+    /// then to a silent null output. An empty name means "system default".
+    pub fn open(preferred: &str) -> Self {
+        if !preferred.is_empty() {
+            let device = rodio::cpal::default_host()
+                .output_devices()
+                .ok()
+                .and_then(|mut devices| devices.find(|d| d.name().ok().as_deref() == Some(preferred)));
+
+            match device.map(|d| OutputStream::try_from_device(&d)) {
+                Some(Ok((stream, handle))) => {
+                    return Self {
+                        _stream: Some(stream),
+                        handle: Some(handle),
+                        device_name: Some(preferred.to_string()),
+                    };
+                }
+                Some(Err(e)) => eprintln!("Failed to open audio device '{}': {}. Using default.", preferred, e),
+                None => eprintln!("Audio device '{}' not found. Using default.", preferred),
+            }
+        }
+
+        match OutputStream::try_default() {
+            Ok((stream, handle)) => Self {
+                _stream: Some(stream),
+                handle: Some(handle),
+                device_name: rodio::cpal::default_host()
+                    .default_output_device()
+                    .and_then(|d| d.name().ok()),
+            },
+            Err(e) => {
+                eprintln!("No audio output available ({}). Running without sound.", e);
+                Self {
+                    _stream: None,
+                    handle: None,
+                    device_name: None,
+                }
+            }
+        }
+    }
+
+    /// Names of every output device the default host can see
+    pub fn list_devices() -> Vec<String> {
+        rodio::cpal::default_host()
+            .output_devices()
+            .map(|devices| devices.filter_map(|d| d.name().ok()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn handle(&self) -> Option<&OutputStreamHandle> {
+        self.handle.as_ref()
+    }
+
+    /// Name of the device actually in use, None when silent
+    pub fn device_name(&self) -> Option<&str> {
+        self.device_name.as_deref()
+    }
+}
 
 pub struct AudioSystem {
-    stream_handle: OutputStreamHandle,
-    hit_sound: Option<Arc<Vec<u8>>>,
-    slider_sound: Option<Arc<Vec<u8>>>,
-    active_slider_sinks: Arc<Mutex<Vec<Sink>>>,
+    hit_sound: Option<SoundSample>,
+    slider_sound: Option<SoundSample>,
+    combo_break_sound: Option<SoundSample>,
+    keysounds: Vec<SoundSample>,
+    mixer_tx: Option<Sender<MixerCommand>>,
 }
 
 impl AudioSystem {
-    pub fn new(stream_handle: OutputStreamHandle) -> Self {
+    pub fn new(stream_handle: Option<&OutputStreamHandle>) -> Self {
         // Try to load hit.wav and slider.wav from current directory
-        let hit_sound = Self::load_sound("hit.wav");
-        let slider_sound = Self::load_sound("slider.wav");
+        let hit_sound = SoundSample::load("hit.wav");
+        let slider_sound = SoundSample::load("slider.wav");
+        // Optional, like a skin's combobreak sound
+        let combo_break_sound = SoundSample::load("combobreak.wav");
 
         if hit_sound.is_none() {
             eprintln!("Warning: hit.wav not found. Hit sounds will be silent.");
@@ -21,76 +283,113 @@ impl AudioSystem {
             eprintln!("Warning: slider.wav not found. Slider sounds will be silent.");
         }
 
+        // Start the mixer once; it lives as long as this AudioSystem
+        let (tx, rx) = mpsc::channel();
+        let mixer_tx = match stream_handle.map(|h| h.play_raw(Mixer::new(rx))) {
+            Some(Ok(())) => Some(tx),
+            Some(Err(e)) => {
+                eprintln!("Failed to start hit sound mixer: {}", e);
+                None
+            }
+            None => None,
+        };
+
         Self {
-            stream_handle,
             hit_sound,
             slider_sound,
-            active_slider_sinks: Arc::new(Mutex::new(Vec::new())),
+            combo_break_sound,
+            keysounds: Vec::new(),
+            mixer_tx,
         }
     }
 
-    fn load_sound(path: &str) -> Option<Arc<Vec<u8>>> {
-        match std::fs::read(path) {
-            Ok(data) => Some(Arc::new(data)),
-            Err(_) => None,
+    fn send(&self, command: MixerCommand) {
+        if let Some(tx) = &self.mixer_tx {
+            let _ = tx.send(command);
         }
     }
 
-    /// Play hit sound (for regular notes and LN heads/tails)
-    pub fn play_hit(&self) {
-        if let Some(sound_data) = &self.hit_sound {
-            let data = sound_data.clone();
-            let handle = self.stream_handle.clone();
-
-            // Spawn in separate thread to not block gameplay
-            std::thread::spawn(move || {
-                let cursor = std::io::Cursor::new((*data).clone());
-                if let Ok(source) = Decoder::new(cursor) {
-                    let _ = handle.play_raw(source.convert_samples::<f32>());
+    /// Queue a pre-decoded sample on the mixer
+    fn play_sample(&self, sample: &SoundSample, volume: f32) {
+        self.send(MixerCommand::Play(Voice {
+            samples: sample.samples.clone(),
+            position: 0,
+            volume,
+        }));
+    }
 
-                }
-            });
+    /// Play hit sound (for regular notes and LN heads/tails) at the note's hitsound volume
+    pub fn play_hit(&self, volume: f32) {
+        if let Some(sample) = &self.hit_sound {
+            self.play_sample(sample, volume);
         }
     }
 
-    /// Start playing slider sound (looped) for a specific lane
-    pub fn play_slider_start(&self, _lane: usize) {
-        if let Some(sound_data) = &self.slider_sound {
-            let data = sound_data.clone();
+    /// Play the combo break sound, if combobreak.wav exists
+    pub fn play_combo_break(&self) {
+        if let Some(sample) = &self.combo_break_sound {
+            self.play_sample(sample, 1.0);
+        }
+    }
 
-            if let Ok(sink) = Sink::try_new(&self.stream_handle) {
-                let cursor = std::io::Cursor::new((*data).clone());
-                if let Ok(source) = Decoder::new(cursor) {
-                    // Repeat indefinitely
-                    sink.append(source.convert_samples::<f32>().repeat_infinite());
+    /// Decode one of the chart's keysounds. Returns the index to play it with,
+    /// or None when the file can't be decoded or there is no output to play it on.
+    pub fn load_keysound(&mut self, path: &Path) -> Option<usize> {
+        self.mixer_tx.as_ref()?;
+        let sample = SoundSample::load(path.to_str()?)?;
+        self.keysounds.push(sample);
+        Some(self.keysounds.len() - 1)
+    }
 
+    pub fn play_keysound(&self, index: usize, volume: f32) {
+        if let Some(sample) = self.keysounds.get(index) {
+            self.play_sample(sample, volume);
+        }
+    }
 
-                    // Store the sink so we can stop it later
-                    if let Ok(mut sinks) = self.active_slider_sinks.lock() {
-                        sinks.push(sink);
-                    }
-                }
-            }
+    /// Play a note's keysound, or the regular hit sound when it has none
+    pub fn play_note(&self, keysound: Option<usize>, volume: f32) {
+        match keysound {
+            Some(index) => self.play_keysound(index, volume),
+            None => self.play_hit(volume),
         }
     }
 
-    /// Stop all active slider sounds
-    pub fn stop_slider(&self, _lane: usize) {
-        // For simplicity, stop the oldest active sink
-        if let Ok(mut sinks) = self.active_slider_sinks.lock() {
-            if !sinks.is_empty() {
-                let sink = sinks.remove(0);
-                sink.stop();
-            }
+    /// Overall effects level (master * effects), applied on top of per-voice volume
+    pub fn set_volume(&self, volume: f32) {
+        self.send(MixerCommand::SetVolume(volume));
+    }
+
+    /// Start the looped hold sound for one LN, at the note's hitsound volume
+    pub fn play_slider_start(&self, lane: usize, note_index: usize, volume: f32) {
+        if let Some(sample) = &self.slider_sound {
+            self.send(MixerCommand::StartLoop(LoopVoice {
+                key: (lane, note_index),
+                samples: sample.samples.clone(),
+                position: 0,
+                volume,
+                fade_gain: None,
+            }));
         }
     }
 
-    /// Stop all slider sounds (for pause, song end, etc)
+    /// Fade out the hold sound of one LN, leaving other lanes untouched
+    pub fn stop_slider(&self, lane: usize, note_index: usize) {
+        self.send(MixerCommand::StopLoop((lane, note_index)));
+    }
+
+    /// Freeze every hold loop in place (pause menu)
+    pub fn pause_sliders(&self) {
+        self.send(MixerCommand::PauseLoops);
+    }
+
+    /// Continue hold loops from where they were paused
+    pub fn resume_sliders(&self) {
+        self.send(MixerCommand::ResumeLoops);
+    }
+
+    /// Stop all slider sounds immediately (song end, quitting)
     pub fn stop_all_sliders(&self) {
-        if let Ok(mut sinks) = self.active_slider_sinks.lock() {
-            for sink in sinks.drain(..) {
-                sink.stop();
-            }
-        }
+        self.send(MixerCommand::StopAllLoops);
     }
 }
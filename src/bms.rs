@@ -0,0 +1,354 @@
+use crate::parser::{Beatmap, HitObject};
+use crate::stepmania::Timing;
+use std::collections::HashMap;
+
+/// P1 note channel (or its LN channel, 5x) to column: scratch first, then keys 1-7
+fn column_of(channel: &str) -> Option<usize> {
+    match &channel[1..] {
+        "6" => Some(0),
+        "1" => Some(1),
+        "2" => Some(2),
+        "3" => Some(3),
+        "4" => Some(4),
+        "5" => Some(5),
+        "8" => Some(6),
+        "9" => Some(7),
+        _ => None,
+    }
+}
+
+/// Two-character base 36 object id (`01`..`ZZ`), 0 for `00`
+fn object_id(s: &str) -> usize {
+    usize::from_str_radix(s, 36).unwrap_or(0)
+}
+
+struct Header {
+    title: String,
+    subtitle: String,
+    artist: String,
+    play_level: String,
+    stage_file: String,
+    bpm: f32,
+    wavs: HashMap<usize, String>,
+    bpms: HashMap<usize, f32>,
+    stops: HashMap<usize, f32>,
+    ln_object: Option<usize>,
+}
+
+/// One object on a channel: (measure, position in the measure 0..1, channel, id)
+type Event = (usize, f32, String, usize);
+
+/// Split a BMS file into header commands and channel events.
+/// `#RANDOM` blocks always take branch 1 so charts load the same way every time.
+fn parse(content: &str) -> (Header, Vec<Event>, HashMap<usize, f32>) {
+    let mut header = Header {
+        title: String::new(),
+        subtitle: String::new(),
+        artist: String::new(),
+        play_level: String::new(),
+        stage_file: String::new(),
+        bpm: 130.0,
+        wavs: HashMap::new(),
+        bpms: HashMap::new(),
+        stops: HashMap::new(),
+        ln_object: None,
+    };
+    let mut events = Vec::new();
+    let mut measure_lengths = HashMap::new();
+    let mut branches: Vec<(bool, bool)> = Vec::new(); // (a branch was taken, this branch is active)
+
+    for line in content.lines() {
+        let line = line.trim();
+        let Some(command) = line.strip_prefix('#') else { continue; };
+        let upper = command.to_uppercase();
+
+        if let Some(value) = upper.strip_prefix("IF ") {
+            let active = value.trim() == "1";
+            branches.push((active, active));
+            continue;
+        }
+        if let Some(branch) = branches.last_mut() {
+            if let Some(value) = upper.strip_prefix("ELSEIF ") {
+                let active = !branch.0 && value.trim() == "1";
+                *branch = (branch.0 || active, active);
+                continue;
+            }
+            if upper.starts_with("ELSE") {
+                *branch = (true, !branch.0);
+                continue;
+            }
+        }
+        if upper.starts_with("ENDIF") {
+            branches.pop();
+            continue;
+        }
+        if branches.iter().any(|(_, active)| !active) {
+            continue;
+        }
+
+        // Channel data: #mmmcc:objects
+        let bytes = command.as_bytes();
+        if bytes.len() > 6 && bytes[5] == b':' && bytes[..5].is_ascii() && bytes[..3].iter().all(u8::is_ascii_digit) {
+            let measure: usize = command[..3].parse().unwrap_or(0);
+            let channel = upper[3..5].to_string();
+            let data = command[6..].trim();
+            if !data.is_ascii() {
+                continue;
+            }
+
+            if channel == "02" {
+                measure_lengths.insert(measure, data.parse().unwrap_or(1.0));
+                continue;
+            }
+            let objects: Vec<&str> = (0..data.len() / 2).map(|i| &data[i * 2..i * 2 + 2]).collect();
+            for (i, object) in objects.iter().enumerate() {
+                // Channel 03 holds BPMs directly in hex, everything else references ids
+                let id = if channel == "03" {
+                    usize::from_str_radix(object, 16).unwrap_or(0)
+                } else {
+                    object_id(object)
+                };
+                if id != 0 {
+                    events.push((measure, i as f32 / objects.len() as f32, channel.clone(), id));
+                }
+            }
+            continue;
+        }
+
+        let (key, value) = command.split_once([' ', '\t']).map(|(k, v)| (k.to_uppercase(), v.trim())).unwrap_or((upper.clone(), ""));
+        match key.as_str() {
+            "TITLE" => header.title = value.to_string(),
+            "SUBTITLE" => header.subtitle = value.to_string(),
+            "ARTIST" => header.artist = value.to_string(),
+            "PLAYLEVEL" => header.play_level = value.to_string(),
+            "STAGEFILE" => header.stage_file = value.to_string(),
+            "BPM" => header.bpm = value.parse().unwrap_or(130.0),
+            "LNOBJ" => header.ln_object = Some(object_id(value)),
+            _ if key.len() == 5 && key.starts_with("WAV") => {
+                header.wavs.insert(object_id(&key[3..]), value.replace('\\', "/"));
+            }
+            _ if key.len() == 5 && key.starts_with("BPM") => {
+                if let Ok(bpm) = value.parse() {
+                    header.bpms.insert(object_id(&key[3..]), bpm);
+                }
+            }
+            _ if key.len() == 6 && key.starts_with("STOP") => {
+                if let Ok(length) = value.parse() {
+                    header.stops.insert(object_id(&key[4..]), length);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    (header, events, measure_lengths)
+}
+
+/// Difficulty list label, e.g. `Song [ANOTHER] Lv12`
+pub fn chart_label(content: &str) -> String {
+    let (header, _, _) = parse(content);
+    let mut label = header.title;
+    if !header.subtitle.is_empty() {
+        label = format!("{} {}", label, header.subtitle);
+    }
+    if !header.play_level.is_empty() {
+        label = format!("{} Lv{}", label, header.play_level);
+    }
+    label
+}
+
+/// Convert a BMS/BME chart to a beatmap with `key_count` lanes. Columns map straight to lanes
+/// (scratch first), so 7K+1 charts need 8 lanes and 5K+1 charts 6; charts are never folded onto
+/// fewer lanes, as that would drop notes. Keysounds become the notes' hit sample filenames and
+/// BGM channel objects become storyboard `Sample` events.
+pub fn parse_bms(content: &str, key_count: usize) -> Result<Beatmap, Box<dyn std::error::Error>> {
+    let (header, mut events, measure_lengths) = parse(content);
+
+    // Beat where each measure starts (4 beats per measure unless channel 02 says otherwise)
+    let last_measure = events.iter().map(|e| e.0).max().unwrap_or(0);
+    let mut measure_starts = Vec::with_capacity(last_measure + 2);
+    let mut beat = 0.0;
+    for measure in 0..=last_measure + 1 {
+        measure_starts.push(beat);
+        beat += 4.0 * measure_lengths.get(&measure).copied().unwrap_or(1.0);
+    }
+    let beat_of = |measure: usize, position: f32| {
+        let length = measure_starts[measure + 1] - measure_starts[measure];
+        measure_starts[measure] + position * length
+    };
+    events.sort_by(|a, b| beat_of(a.0, a.1).total_cmp(&beat_of(b.0, b.1)));
+
+    let mut timing = Timing { offset: 0.0, bpms: vec![(0.0, header.bpm)], stops: Vec::new(), delays: Vec::new() };
+    for (measure, position, channel, id) in &events {
+        let bpm = match channel.as_str() {
+            "03" => Some(*id as f32),
+            "08" => header.bpms.get(id).copied(),
+            _ => None,
+        };
+        if let Some(bpm) = bpm.filter(|bpm| *bpm > 0.0) {
+            timing.bpms.push((beat_of(*measure, *position), bpm));
+        }
+    }
+    timing.bpms.sort_by(|a, b| a.0.total_cmp(&b.0));
+    // Stop lengths are in 1/192 of a 4/4 measure, i.e. 48 per beat
+    for (measure, position, channel, id) in &events {
+        if channel == "09" {
+            if let Some(length) = header.stops.get(id) {
+                let beat = beat_of(*measure, *position);
+                timing.stops.push((beat, length / 48.0 * 60.0 / timing.bpm_at(beat)));
+            }
+        }
+    }
+
+    let uses_keys_6_7 = events.iter().any(|(_, _, channel, _)| {
+        (channel.starts_with('1') || channel.starts_with('5')) && column_of(channel).is_some_and(|c| c >= 6)
+    });
+    let chart_columns = if uses_keys_6_7 { 8 } else { 6 };
+    if key_count < chart_columns {
+        return Err(format!("this chart needs {} lanes (scratch + {} keys), play it in 8K mode", chart_columns, chart_columns - 1).into());
+    }
+
+    let sample_of = |id: usize| match header.wavs.get(&id) {
+        Some(file) => format!("0:0:0:0:{}", file),
+        None => String::from("0:0:0:0:"),
+    };
+
+    let mut hit_objects: Vec<HitObject> = Vec::new();
+    let mut bgm = Vec::new();
+    let mut last_note: HashMap<usize, usize> = HashMap::new(); // column -> index, for #LNOBJ
+    let mut open_lns: HashMap<usize, usize> = HashMap::new(); // column -> index
+
+    for (measure, position, channel, id) in &events {
+        let time = timing.time_at(beat_of(*measure, *position));
+        if channel == "01" {
+            bgm.push((time, *id));
+            continue;
+        }
+        let is_ln_channel = channel.starts_with('5');
+        if !channel.starts_with('1') && !is_ln_channel {
+            continue;
+        }
+        let Some(column) = column_of(channel).filter(|c| *c < chart_columns) else { continue; };
+
+        // #LNOBJ marks the end of a hold that started at the previous note in the column
+        if !is_ln_channel && header.ln_object == Some(*id) {
+            if let Some(&index) = last_note.get(&column) {
+                hit_objects[index].end_time = Some(time);
+            }
+            continue;
+        }
+        // LN channels pair up start and end objects
+        if is_ln_channel {
+            if let Some(index) = open_lns.remove(&column) {
+                hit_objects[index].end_time = Some(time);
+                continue;
+            }
+        }
+
+        hit_objects.push(HitObject { time, end_time: None, lane: column, hitsound: 0, sample: sample_of(*id) });
+        if is_ln_channel {
+            open_lns.insert(column, hit_objects.len() - 1);
+        } else {
+            last_note.insert(column, hit_objects.len() - 1);
+        }
+    }
+    hit_objects.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    let mut beatmap_events = Vec::new();
+    if !header.stage_file.is_empty() {
+        beatmap_events.push(format!("0,0,\"{}\",0,0", header.stage_file));
+    }
+    for (time, id) in bgm {
+        if let Some(file) = header.wavs.get(&id) {
+            beatmap_events.push(format!("Sample,{},0,\"{}\",100", time.round() as i64, file));
+        }
+    }
+
+    let version = if header.subtitle.is_empty() { format!("Lv{}", header.play_level) } else { header.subtitle.clone() };
+    Ok(Beatmap {
+        // Keysounded charts have no music track of their own
+        audio_filename: String::new(),
+        title: header.title.clone(),
+        title_unicode: header.title,
+        artist: header.artist.clone(),
+        artist_unicode: header.artist,
+        version,
+        key_count,
+        events: beatmap_events,
+        timing_points: timing.timing_points(),
+        hit_objects,
+        ..Beatmap::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (time, end time, lane) of every object, ordered by time then lane
+    fn objects(beatmap: &Beatmap) -> Vec<(f32, Option<f32>, usize)> {
+        let mut objects: Vec<_> = beatmap.hit_objects.iter().map(|o| (o.time, o.end_time, o.lane)).collect();
+        objects.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.2.cmp(&b.2)));
+        objects
+    }
+
+    #[test]
+    fn seven_keys_and_scratch_get_their_own_lanes() {
+        // One note per column in channel order 16, 11-15, 18, 19
+        let content = "#BPM 120\n#00116:01\n#00111:01\n#00112:01\n#00113:01\n#00114:01\n#00115:01\n#00118:01\n#00119:01\n";
+        let lanes: Vec<usize> = objects(&parse_bms(content, 8).unwrap()).iter().map(|o| o.2).collect();
+        assert_eq!(lanes, (0..8).collect::<Vec<_>>());
+
+        // 5K+1 charts use the first six lanes
+        let lanes: Vec<usize> = objects(&parse_bms("#BPM 120\n#00116:01\n#00115:01\n", 8).unwrap()).iter().map(|o| o.2).collect();
+        assert_eq!(lanes, vec![0, 5]);
+
+        // Nothing is folded away: too few lanes is an error
+        assert!(parse_bms(content, 4).is_err());
+    }
+
+    #[test]
+    fn lnobj_ends_the_hold_in_its_own_column() {
+        // Column 1: taps on beats 0 and 2, LNOBJ on beat 3. Scratch also has a note on beat 0.
+        let content = "#BPM 120\n#LNOBJ ZZ\n#WAV01 kick.wav\n#00011:01000100\n#00011:000000ZZ\n#00016:01000000\n";
+        let beatmap = parse_bms(content, 8).unwrap();
+        assert_eq!(objects(&beatmap), vec![(0.0, None, 0), (0.0, None, 1), (1000.0, Some(1500.0), 1)]);
+        assert!(beatmap.hit_objects.iter().all(|o| o.sample == "0:0:0:0:kick.wav"));
+    }
+
+    #[test]
+    fn holds_leave_neighbouring_columns_alone() {
+        // LN in column 1 from beat 0 to 2, taps in column 2 on beats 1 and 2
+        let content = "#BPM 120\n#00051:01000100\n#00012:00010100\n";
+        assert_eq!(objects(&parse_bms(content, 8).unwrap()), vec![
+            (0.0, Some(1000.0), 1),
+            (500.0, None, 2),
+            (1000.0, None, 2),
+        ]);
+    }
+
+    #[test]
+    fn random_blocks_take_branch_one() {
+        let content = "#BPM 120\n#RANDOM 2\n#IF 1\n#00111:01\n#ELSE\n#00112:01\n#ENDIF\n#IF 2\n#00113:01\n#ENDIF\n";
+        let lanes: Vec<usize> = objects(&parse_bms(content, 8).unwrap()).iter().map(|o| o.2).collect();
+        assert_eq!(lanes, vec![1]);
+    }
+
+    #[test]
+    fn bpm_channels_change_tempo() {
+        // Measure 1 switches to 240 (hex F0 on channel 03), halfway through measure 2 #BPM01 sets 60
+        let content = "#BPM 120\n#BPM01 60\n#00103:F0\n#00208:0001\n#00211:01\n#00311:01\n";
+        let times: Vec<f32> = objects(&parse_bms(content, 8).unwrap()).iter().map(|o| o.0).collect();
+        assert_eq!(times, vec![3000.0, 5500.0]);
+    }
+
+    #[test]
+    fn stops_pause_after_their_beat() {
+        // 96/192 of a measure is two beats: one second at 120 BPM
+        let content = "#BPM 120\n#STOP01 96\n#00109:01\n#00111:0101\n";
+        let beatmap = parse_bms(content, 8).unwrap();
+        let times: Vec<f32> = objects(&beatmap).iter().map(|o| o.0).collect();
+        assert_eq!(times, vec![2000.0, 4000.0]);
+        assert!(beatmap.timing_points.iter().any(|tp| tp.time == 2000.0 && tp.velocity_mult == 0.0));
+    }
+}
@@ -0,0 +1,1051 @@
+use crate::models::TimingPoint;
+use crate::parser::{find_value, slider_duration, HitObject};
+
+// Pattern types from osu!mania's converter, combined as flags
+const FORCE_STACK: u32 = 1;
+const FORCE_NOT_STACK: u32 = 1 << 1;
+const KEEP_SINGLE: u32 = 1 << 2;
+const LOW_PROBABILITY: u32 = 1 << 3;
+const GATHERED: u32 = 1 << 7;
+const MIRROR: u32 = 1 << 8;
+const REVERSE: u32 = 1 << 9;
+const CYCLE: u32 = 1 << 10;
+const STAIR: u32 = 1 << 11;
+const REVERSE_STAIR: u32 = 1 << 12;
+
+// Hitsound bits
+const WHISTLE: i32 = 2;
+const FINISH: i32 = 4;
+const CLAP: i32 = 8;
+
+// Density is the average spacing of this many recent objects
+const MAX_NOTES_FOR_DENSITY: usize = 7;
+
+// Taiko lanes in 4K: kat, don, don, kat
+const DON_LANES: [usize; 2] = [1, 2];
+const KAT_LANES: [usize; 2] = [0, 3];
+
+/// osu!'s xorshift random number generator, so converted patterns match osu!'s for the same seed
+struct FastRandom {
+    x: u32,
+    y: u32,
+    z: u32,
+    w: u32,
+}
+
+impl FastRandom {
+    fn new(seed: i32) -> Self {
+        Self { x: seed as u32, y: 842502087, z: 3579807591, w: 273326509 }
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        let t = self.x ^ (self.x << 11);
+        self.x = self.y;
+        self.y = self.z;
+        self.z = self.w;
+        self.w = self.w ^ (self.w >> 19) ^ t ^ (t >> 8);
+        self.w
+    }
+
+    /// 0.0 <= value < 1.0
+    fn next_double(&mut self) -> f64 {
+        (self.next_u32() & 0x7FFF_FFFF) as f64 / (i32::MAX as f64 + 1.0)
+    }
+
+    /// lower <= value < upper
+    fn next_range(&mut self, lower: i32, upper: i32) -> i32 {
+        (lower as f64 + self.next_double() * (upper - lower) as f64) as i32
+    }
+}
+
+enum Shape {
+    Circle,
+    Slider { spans: i32, segment: f32 }, // segment: duration of one pass in ms
+    Spinner,
+}
+
+/// A standard/taiko hit object before conversion
+struct Source {
+    x: f32,
+    y: f32,
+    time: f32,
+    end_time: f32,
+    shape: Shape,
+    hitsound: i32,
+    edge_sounds: Vec<i32>, // slider hitsound per head/repeat/tail
+    sample: String,
+}
+
+/// Notes generated for one object: (column, start, end), with end == start for taps
+#[derive(Default)]
+struct Pattern {
+    notes: Vec<(i32, f32, f32)>,
+}
+
+impl Pattern {
+    fn add(&mut self, column: i32, start: f32, end: f32) {
+        self.notes.push((column, start, end));
+    }
+
+    fn has(&self, column: i32) -> bool {
+        self.notes.iter().any(|n| n.0 == column)
+    }
+
+    /// Number of distinct columns in use
+    fn column_count(&self) -> i32 {
+        let mut columns: Vec<i32> = self.notes.iter().map(|n| n.0).collect();
+        columns.sort_unstable();
+        columns.dedup();
+        columns.len() as i32
+    }
+}
+
+/// How `Generator::find_column` picks the next candidate after a taken column
+#[derive(Clone, Copy)]
+enum Next {
+    Random,
+    Gathered, // the column to the right, wrapping around
+}
+
+/// Average spacing of recent objects (ms)
+struct Density {
+    times: Vec<f32>,
+    value: f64,
+}
+
+impl Density {
+    fn record(&mut self, time: f32) {
+        if self.times.len() == MAX_NOTES_FOR_DENSITY {
+            self.times.remove(0);
+        }
+        self.times.push(time);
+        if self.times.len() >= 2 {
+            self.value = (self.times[self.times.len() - 1] - self.times[0]) as f64 / self.times.len() as f64;
+        }
+    }
+}
+
+/// Pattern generation for one object
+struct Generator<'a> {
+    random: &'a mut FastRandom,
+    columns: i32,
+    difficulty: f64,
+    previous: &'a Pattern,
+    object: &'a Source,
+    convert_type: u32,
+}
+
+impl Generator<'_> {
+    /// 8K conversions keep column 0 as a special key that is only used on purpose
+    fn random_start(&self) -> i32 {
+        if self.columns == 8 { 1 } else { 0 }
+    }
+
+    fn column_at(&self, x: f32, allow_special: bool) -> i32 {
+        if allow_special && self.columns == 8 {
+            return ((x / (512.0 / 7.0)).floor() as i32).clamp(0, 6) + 1;
+        }
+        ((x / (512.0 / self.columns as f32)).floor() as i32).clamp(0, self.columns - 1)
+    }
+
+    fn random_column(&mut self, lower: Option<i32>, upper: Option<i32>) -> i32 {
+        let lower = lower.unwrap_or(self.random_start());
+        self.random.next_range(lower, upper.unwrap_or(self.columns))
+    }
+
+    fn note_count(&mut self, p2: f64, p3: f64, p4: f64, p5: f64) -> i32 {
+        let value = self.random.next_double();
+        if value >= 1.0 - p5 {
+            5
+        } else if value >= 1.0 - p4 {
+            4
+        } else if value >= 1.0 - p3 {
+            3
+        } else if value >= 1.0 - p2 {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// `initial` if no pattern uses it (and it isn't `exclude`), otherwise the first free
+    /// column produced by `next`. None when every column in `bounds` is taken.
+    fn find_column(&mut self, initial: i32, bounds: Option<(i32, i32)>, next: Next, exclude: Option<i32>, patterns: &[&Pattern]) -> Option<i32> {
+        let (lower, upper) = bounds.unwrap_or((self.random_start(), self.columns));
+        let valid = |column: i32| exclude != Some(column) && !patterns.iter().any(|p| p.has(column));
+        if valid(initial) {
+            return Some(initial);
+        }
+        if !(lower..upper).any(valid) {
+            return None;
+        }
+        let mut column = initial;
+        loop {
+            column = match next {
+                Next::Random => self.random.next_range(lower, upper),
+                Next::Gathered if column + 1 >= self.columns => self.random_start(),
+                Next::Gathered => column + 1,
+            };
+            if valid(column) {
+                return Some(column);
+            }
+        }
+    }
+
+    /// Hitsound of the slider node (head, repeat or tail) at `time`
+    fn node_sound(&self, time: f32) -> i32 {
+        match self.object.shape {
+            Shape::Slider { segment, .. } if segment > 0.0 => {
+                let index = ((time - self.object.time) / segment) as usize;
+                self.object.edge_sounds.get(index).copied().unwrap_or(self.object.hitsound)
+            }
+            _ => self.object.hitsound,
+        }
+    }
+
+    // === HIT CIRCLES ===
+
+    /// Returns the pattern and the stair direction for the next circle
+    fn circle(&mut self, previous_time: f32, previous_position: (f32, f32), density: f64, stair: u32, beat_length: f64, kiai: bool) -> (Pattern, u32) {
+        let object = self.object;
+        let distance = ((object.x - previous_position.0).powi(2) + (object.y - previous_position.1).powi(2)).sqrt();
+        let gap = (object.time - previous_time) as f64;
+
+        let mut convert_type = 0;
+        if gap <= 80.0 {
+            convert_type |= FORCE_NOT_STACK | KEEP_SINGLE;
+        } else if gap <= 95.0 {
+            convert_type |= FORCE_NOT_STACK | KEEP_SINGLE | stair;
+        } else if gap <= 105.0 {
+            convert_type |= FORCE_NOT_STACK | LOW_PROBABILITY;
+        } else if gap <= 125.0 {
+            convert_type |= FORCE_NOT_STACK;
+        } else if gap <= 135.0 && distance < 20.0 {
+            convert_type |= CYCLE | KEEP_SINGLE;
+        } else if gap <= 150.0 && distance < 20.0 {
+            convert_type |= FORCE_STACK | LOW_PROBABILITY;
+        } else if distance < 20.0 && density >= beat_length / 2.5 {
+            convert_type |= REVERSE | LOW_PROBABILITY;
+        } else if density < beat_length / 2.5 || kiai {
+            // Dense or kiai sections keep the full note counts
+        } else {
+            convert_type |= LOW_PROBABILITY;
+        }
+        if convert_type & KEEP_SINGLE == 0 {
+            if object.hitsound & FINISH != 0 && self.columns != 8 {
+                convert_type |= MIRROR;
+            } else if object.hitsound & CLAP != 0 {
+                convert_type |= GATHERED;
+            }
+        }
+        self.convert_type = convert_type;
+
+        let pattern = self.circle_pattern();
+        let mut stair = stair;
+        for &(column, _, _) in &pattern.notes {
+            if convert_type & STAIR != 0 && column == self.columns - 1 {
+                stair = REVERSE_STAIR;
+            }
+            if convert_type & REVERSE_STAIR != 0 && column == self.random_start() {
+                stair = STAIR;
+            }
+        }
+        (pattern, stair)
+    }
+
+    fn circle_pattern(&mut self) -> Pattern {
+        let (columns, start, time) = (self.columns, self.random_start(), self.object.time);
+        let previous = self.previous;
+        let convert_type = self.convert_type;
+        let mut pattern = Pattern::default();
+        if columns == 1 {
+            pattern.add(0, time, time);
+            return pattern;
+        }
+        let last_column = previous.notes.first().map(|n| n.0).unwrap_or(0);
+
+        // Copy the previous pattern mirrored
+        if convert_type & REVERSE != 0 && !previous.notes.is_empty() {
+            for column in start..columns {
+                if previous.has(column) {
+                    pattern.add(start + columns - column - 1, time, time);
+                }
+            }
+            return pattern;
+        }
+        // Mirror a single note, but not off the special key or the centre column
+        if convert_type & CYCLE != 0 && previous.notes.len() == 1
+            && (columns != 8 || last_column != 0)
+            && (columns % 2 == 0 || last_column != columns / 2)
+        {
+            pattern.add(start + columns - last_column - 1, time, time);
+            return pattern;
+        }
+        // Jacks: repeat the previous columns
+        if convert_type & FORCE_STACK != 0 && !previous.notes.is_empty() {
+            for column in start..columns {
+                if previous.has(column) {
+                    pattern.add(column, time, time);
+                }
+            }
+            return pattern;
+        }
+        if previous.notes.len() == 1 {
+            if convert_type & STAIR != 0 {
+                let column = if last_column + 1 == columns { start } else { last_column + 1 };
+                pattern.add(column, time, time);
+                return pattern;
+            }
+            if convert_type & REVERSE_STAIR != 0 {
+                let column = if last_column - 1 == start - 1 { columns - 1 } else { last_column - 1 };
+                pattern.add(column, time, time);
+                return pattern;
+            }
+        }
+        if convert_type & KEEP_SINGLE != 0 {
+            return self.circle_notes(1);
+        }
+
+        let difficulty = self.difficulty;
+        if convert_type & MIRROR != 0 {
+            return if difficulty > 6.5 {
+                self.mirrored_pattern(0.12, 0.38, 0.12)
+            } else if difficulty > 4.0 {
+                self.mirrored_pattern(0.12, 0.17, 0.0)
+            } else {
+                self.mirrored_pattern(0.12, 0.0, 0.0)
+            };
+        }
+        let low = convert_type & LOW_PROBABILITY != 0;
+        if difficulty > 6.5 {
+            if low { self.random_pattern(0.78, 0.42, 0.0, 0.0) } else { self.random_pattern(1.0, 0.62, 0.0, 0.0) }
+        } else if difficulty > 4.0 {
+            if low { self.random_pattern(0.35, 0.08, 0.0, 0.0) } else { self.random_pattern(0.52, 0.15, 0.0, 0.0) }
+        } else if difficulty > 2.0 {
+            if low { self.random_pattern(0.18, 0.0, 0.0, 0.0) } else { self.random_pattern(0.45, 0.0, 0.0, 0.0) }
+        } else {
+            self.random_pattern(0.0, 0.0, 0.0, 0.0)
+        }
+    }
+
+    fn circle_notes(&mut self, count: i32) -> Pattern {
+        let previous = self.previous;
+        let time = self.object.time;
+        let allow_stacking = self.convert_type & FORCE_NOT_STACK == 0;
+        let count = if allow_stacking { count } else { count.min(self.columns - self.random_start() - previous.column_count()) };
+        let next = if self.convert_type & GATHERED != 0 { Next::Gathered } else { Next::Random };
+
+        let mut pattern = Pattern::default();
+        let mut column = self.column_at(self.object.x, true);
+        for _ in 0..count {
+            let found = if allow_stacking {
+                self.find_column(column, None, next, None, &[&pattern])
+            } else {
+                self.find_column(column, None, next, None, &[&pattern, previous])
+            };
+            let Some(found) = found else { break; };
+            column = found;
+            pattern.add(column, time, time);
+        }
+        pattern
+    }
+
+    /// A clap and a finish together ask for the special key
+    fn has_special_column(&self) -> bool {
+        self.object.hitsound & CLAP != 0 && self.object.hitsound & FINISH != 0
+    }
+
+    fn random_pattern(&mut self, p2: f64, p3: f64, p4: f64, p5: f64) -> Pattern {
+        let (mut p2, mut p3, mut p4, mut p5) = (p2, p3, p4, p5);
+        match self.columns {
+            2 => (p2, p3, p4, p5) = (0.0, 0.0, 0.0, 0.0),
+            3 => (p2, p3, p4, p5) = (p2.min(0.1), 0.0, 0.0, 0.0),
+            4 => (p2, p3, p4, p5) = (p2.min(0.23), p3.min(0.04), 0.0, 0.0),
+            5 => (p3, p4, p5) = (p3.min(0.15), p4.min(0.03), 0.0),
+            _ => {}
+        }
+        if self.object.hitsound & CLAP != 0 {
+            p2 = 1.0;
+        }
+        let count = self.note_count(p2, p3, p4, p5);
+        let mut pattern = self.circle_notes(count);
+        if self.random_start() > 0 && self.has_special_column() {
+            pattern.add(0, self.object.time, self.object.time);
+        }
+        pattern
+    }
+
+    /// Notes on one half of the stage mirrored onto the other
+    fn mirrored_pattern(&mut self, centre: f64, p2: f64, p3: f64) -> Pattern {
+        if self.convert_type & FORCE_NOT_STACK != 0 {
+            return self.random_pattern(0.5 + p2 / 2.0, p2, (p2 + p3) / 2.0, p3);
+        }
+        let (mut centre, mut p2, mut p3) = (centre, p2, p3);
+        match self.columns {
+            2 => (centre, p2, p3) = (0.0, 0.0, 0.0),
+            3 => (centre, p2, p3) = (centre.min(0.03), 0.0, 0.0),
+            4 => (centre, p2, p3) = (0.0, (p2 * 2.0).min(0.2), 0.0),
+            5 => (centre, p3) = (centre.min(0.03), 0.0),
+            6 => (centre, p2, p3) = (0.0, (p2 * 2.0).min(0.5), (p3 * 2.0).min(0.15)),
+            _ => {}
+        }
+        let centre_value = self.random.next_double();
+        let count = self.note_count(p2, p3, 0.0, 0.0);
+        let add_centre = self.columns % 2 != 0 && count != 3 && centre_value > 1.0 - centre;
+
+        let (columns, start, time) = (self.columns, self.random_start(), self.object.time);
+        let limit = (if columns % 2 == 0 { columns } else { columns - 1 }) / 2;
+        let mut pattern = Pattern::default();
+        let mut column = self.random_column(None, Some(limit));
+        for _ in 0..count {
+            let Some(found) = self.find_column(column, Some((start, limit)), Next::Random, None, &[&pattern]) else { break; };
+            column = found;
+            pattern.add(column, time, time);
+            pattern.add(start + columns - column - 1, time, time);
+        }
+        if add_centre {
+            pattern.add(columns / 2, time, time);
+        }
+        if start > 0 && self.has_special_column() {
+            pattern.add(0, time, time);
+        }
+        pattern
+    }
+
+    // === SLIDERS ===
+
+    /// Sliders yield two patterns: notes ending before the slider does, then the ones ending
+    /// with it. Only the second is what the next object reacts to.
+    fn slider(&mut self, spans: i32, segment: f32, kiai: bool) -> Vec<Pattern> {
+        self.convert_type = if kiai { 0 } else { LOW_PROBABILITY };
+        let pattern = self.slider_pattern(spans, segment);
+        if pattern.notes.len() == 1 {
+            return vec![pattern];
+        }
+        let end_time = self.object.end_time;
+        let (ending, intermediate): (Vec<_>, Vec<_>) = pattern.notes.into_iter().partition(|n| (n.2 - end_time).abs() < 1.0);
+        vec![Pattern { notes: intermediate }, Pattern { notes: ending }]
+    }
+
+    fn slider_pattern(&mut self, spans: i32, segment: f32) -> Pattern {
+        let (columns, start) = (self.columns, self.object.time);
+        if columns == 1 {
+            let mut pattern = Pattern::default();
+            pattern.add(0, start, self.object.end_time);
+            return pattern;
+        }
+
+        if spans > 1 {
+            if segment <= 90.0 {
+                return self.random_holds(start, 1);
+            }
+            if segment <= 120.0 {
+                self.convert_type |= FORCE_NOT_STACK;
+                return self.slider_notes(start, spans + 1, segment);
+            }
+            if segment <= 160.0 {
+                return self.stair(start, spans, segment);
+            }
+            if segment <= 200.0 && self.difficulty > 3.0 {
+                return self.random_multiple_notes(start, spans, segment);
+            }
+            if self.object.end_time - start >= 4000.0 {
+                return self.n_random_notes(start, 0.23, 0.0, 0.0);
+            }
+            if segment > 400.0 && spans < columns - 1 - self.random_start() {
+                return self.tiled_holds(start, spans, segment);
+            }
+            return self.hold_and_normal_notes(start, spans, segment);
+        }
+
+        if segment <= 110.0 {
+            if self.previous.column_count() < columns {
+                self.convert_type |= FORCE_NOT_STACK;
+            } else {
+                self.convert_type &= !FORCE_NOT_STACK;
+            }
+            return self.slider_notes(start, if segment < 80.0 { 1 } else { 2 }, segment);
+        }
+
+        let low = self.convert_type & LOW_PROBABILITY != 0;
+        if self.difficulty > 6.5 {
+            if low { self.n_random_notes(start, 0.78, 0.3, 0.0) } else { self.n_random_notes(start, 0.85, 0.36, 0.03) }
+        } else if self.difficulty > 4.0 {
+            if low { self.n_random_notes(start, 0.43, 0.08, 0.0) } else { self.n_random_notes(start, 0.56, 0.18, 0.0) }
+        } else if self.difficulty > 2.5 {
+            if low { self.n_random_notes(start, 0.3, 0.0, 0.0) } else { self.n_random_notes(start, 0.37, 0.08, 0.0) }
+        } else if low {
+            self.n_random_notes(start, 0.17, 0.0, 0.0)
+        } else {
+            self.n_random_notes(start, 0.27, 0.0, 0.0)
+        }
+    }
+
+    /// The slider's first column, moved off the previous pattern when stacking isn't allowed
+    fn slider_start_column(&mut self) -> i32 {
+        let column = self.column_at(self.object.x, true);
+        let previous = self.previous;
+        if self.convert_type & FORCE_NOT_STACK != 0 && previous.column_count() < self.columns {
+            return self.find_column(column, None, Next::Random, None, &[previous]).unwrap_or(column);
+        }
+        column
+    }
+
+    /// `count` holds lasting the whole slider, avoiding the previous pattern while possible
+    fn random_holds(&mut self, start: f32, count: i32) -> Pattern {
+        let previous = self.previous;
+        let end = self.object.end_time;
+        let usable = self.columns - self.random_start() - previous.column_count();
+        let mut pattern = Pattern::default();
+        let mut column = self.random_column(None, None);
+        for _ in 0..usable.min(count) {
+            let Some(found) = self.find_column(column, None, Next::Random, None, &[&pattern, previous]) else { break; };
+            column = found;
+            pattern.add(column, start, end);
+        }
+        for _ in 0..count - usable {
+            let Some(found) = self.find_column(column, None, Next::Random, None, &[&pattern]) else { break; };
+            column = found;
+            pattern.add(column, start, end);
+        }
+        pattern
+    }
+
+    /// One tap per node, never twice in a row on the same column
+    fn slider_notes(&mut self, start: f32, count: i32, segment: f32) -> Pattern {
+        let mut pattern = Pattern::default();
+        let mut column = self.slider_start_column();
+        let mut time = start;
+        for _ in 0..count {
+            pattern.add(column, time, time);
+            let last = column;
+            column = self.find_column(column, None, Next::Random, Some(last), &[]).unwrap_or(column);
+            time += segment;
+        }
+        pattern
+    }
+
+    /// One tap per node, walking across the stage and bouncing off its edges
+    fn stair(&mut self, start: f32, spans: i32, segment: f32) -> Pattern {
+        let mut pattern = Pattern::default();
+        let mut column = self.column_at(self.object.x, true);
+        let mut increasing = self.random.next_double() > 0.5;
+        let mut time = start;
+        for _ in 0..=spans {
+            pattern.add(column, time, time);
+            time += segment;
+            if increasing {
+                if column >= self.columns - 1 {
+                    increasing = false;
+                    column -= 1;
+                } else {
+                    column += 1;
+                }
+            } else if column <= self.random_start() {
+                increasing = true;
+                column += 1;
+            } else {
+                column -= 1;
+            }
+        }
+        pattern
+    }
+
+    /// A chord of two taps per node
+    fn random_multiple_notes(&mut self, start: f32, spans: i32, segment: f32) -> Pattern {
+        let (columns, random_start) = (self.columns, self.random_start());
+        let legacy = (4..=8).contains(&columns) as i32;
+        let interval = self.random.next_range(1, columns - legacy);
+        let mut pattern = Pattern::default();
+        let mut column = self.column_at(self.object.x, true);
+        let mut time = start;
+        for _ in 0..=spans {
+            pattern.add(column, time, time);
+            column += interval;
+            if column >= columns - random_start {
+                column = column - columns - random_start + legacy;
+            }
+            column += random_start;
+            // Too many doubles in a row on 2K
+            if columns > 2 {
+                pattern.add(column, time, time);
+            }
+            column = self.random_column(None, None);
+            time += segment;
+        }
+        pattern
+    }
+
+    /// Holds over the whole slider, more of them on harder maps or clap/finish hitsounds
+    fn n_random_notes(&mut self, start: f32, p2: f64, p3: f64, p4: f64) -> Pattern {
+        let (mut p2, mut p3, mut p4) = (p2, p3, p4);
+        match self.columns {
+            2 => (p2, p3, p4) = (0.0, 0.0, 0.0),
+            3 => (p2, p3, p4) = (p2.min(0.1), 0.0, 0.0),
+            4 => (p2, p3, p4) = (p2.min(0.3), p3.min(0.04), 0.0),
+            5 => (p2, p3, p4) = (p2.min(0.34), p3.min(0.1), p4.min(0.03)),
+            _ => {}
+        }
+        let is_double = |sound: i32| sound & (CLAP | FINISH) != 0;
+        if self.convert_type & LOW_PROBABILITY == 0 && (is_double(self.object.hitsound) || is_double(self.node_sound(start))) {
+            p2 = 1.0;
+        }
+        let count = self.note_count(p2, p3, p4, 0.0);
+        self.random_holds(start, count)
+    }
+
+    /// A hold starting at every node, all ending together
+    fn tiled_holds(&mut self, start: f32, spans: i32, segment: f32) -> Pattern {
+        let end = start + segment * spans as f32;
+        let mut pattern = Pattern::default();
+        let mut column = self.slider_start_column();
+        let mut time = start;
+        for _ in 0..spans.min(self.columns) {
+            let Some(found) = self.find_column(column, None, Next::Random, None, &[&pattern]) else { break; };
+            column = found;
+            pattern.add(column, time, end);
+            time += segment;
+        }
+        pattern
+    }
+
+    /// A hold for the whole slider with taps on the other columns at its nodes
+    fn hold_and_normal_notes(&mut self, start: f32, spans: i32, segment: f32) -> Pattern {
+        let mut pattern = Pattern::default();
+        let hold_column = self.slider_start_column();
+        pattern.add(hold_column, start, self.object.end_time);
+
+        let mut column = self.random_column(None, None);
+        let count = if self.difficulty > 6.5 {
+            self.note_count(0.63, 0.0, 0.0, 0.0)
+        } else if self.difficulty > 4.0 {
+            self.note_count(if self.columns < 6 { 0.12 } else { 0.45 }, 0.0, 0.0, 0.0)
+        } else if self.difficulty > 2.5 {
+            self.note_count(if self.columns < 6 { 0.0 } else { 0.24 }, 0.0, 0.0, 0.0)
+        } else {
+            0
+        };
+        let count = count.min(self.columns - 1);
+        let ignore_head = self.node_sound(start) & (WHISTLE | FINISH | CLAP) == 0;
+
+        let mut time = start;
+        for node in 0..=spans {
+            if !(ignore_head && node == 0) {
+                let mut row = Pattern::default();
+                for _ in 0..count {
+                    let Some(found) = self.find_column(column, None, Next::Random, Some(hold_column), &[&row]) else { break; };
+                    column = found;
+                    row.add(column, time, time);
+                }
+                pattern.notes.extend(row.notes);
+            }
+            time += segment;
+        }
+        pattern
+    }
+
+    // === SPINNERS ===
+
+    /// A hold on a random column (a tap if the spinner is very short)
+    fn spinner(&mut self) -> Pattern {
+        let object = self.object;
+        self.convert_type = if self.previous.column_count() == self.columns { 0 } else { FORCE_NOT_STACK };
+        let end = if object.end_time - object.time >= 100.0 { object.end_time } else { object.time };
+
+        let column = if self.columns == 8 && object.hitsound & FINISH != 0 && object.end_time - object.time < 1000.0 {
+            Some(0)
+        } else if self.columns == 8 {
+            self.spinner_column(None)
+        } else {
+            self.spinner_column(Some(0))
+        };
+
+        let mut pattern = Pattern::default();
+        if let Some(column) = column {
+            pattern.add(column, object.time, end);
+        }
+        pattern
+    }
+
+    fn spinner_column(&mut self, lower: Option<i32>) -> Option<i32> {
+        let previous = self.previous;
+        let initial = self.random_column(lower, None);
+        let bounds = lower.map(|lower| (lower, self.columns));
+        if self.convert_type & FORCE_NOT_STACK != 0 {
+            self.find_column(initial, bounds, Next::Random, None, &[previous])
+        } else {
+            self.find_column(initial, bounds, Next::Random, None, &[])
+        }
+    }
+}
+
+/// Read `[HitObjects]` of a standard/taiko/catch map
+fn parse_sources(content: &str, timing_points: &[TimingPoint], slider_multiplier: f32) -> Vec<Source> {
+    let mut sources = Vec::new();
+    let mut in_hit_objects = false;
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line == "[HitObjects]" { in_hit_objects = true; continue; }
+        if in_hit_objects && line.starts_with('[') { break; }
+        if !in_hit_objects || line.is_empty() { continue; }
+
+        let p: Vec<&str> = line.split(',').collect();
+        if p.len() < 4 { continue; }
+        let x: f32 = p[0].parse().unwrap_or(0.0);
+        let y: f32 = p[1].parse().unwrap_or(0.0);
+        let time: f32 = p[2].parse().unwrap_or(0.0);
+        let obj_type: i32 = p[3].parse().unwrap_or(0);
+        let hitsound: i32 = p.get(4).and_then(|v| v.parse().ok()).unwrap_or(0);
+
+        let (shape, end_time, sample) = if obj_type & 2 != 0 && p.len() >= 8 {
+            let spans: i32 = p[6].parse::<i32>().unwrap_or(1).max(1);
+            let pixel_length: f32 = p[7].parse().unwrap_or(0.0);
+            let end_time = (time + slider_duration(time, spans as f32, pixel_length, timing_points, slider_multiplier)).floor();
+            let segment = ((end_time - time) / spans as f32).floor();
+            (Shape::Slider { spans, segment }, end_time, p.get(10).copied())
+        } else if obj_type & 8 != 0 && p.len() >= 6 {
+            (Shape::Spinner, p[5].parse().unwrap_or(time), p.get(6).copied())
+        } else if obj_type & 128 != 0 && p.len() >= 6 {
+            // endTime:hitSample
+            let (end, sample) = p[5].split_once(':').unwrap_or((p[5], ""));
+            (Shape::Spinner, end.parse().unwrap_or(time), Some(sample))
+        } else {
+            (Shape::Circle, time, p.get(5).copied())
+        };
+        let edge_sounds = match shape {
+            Shape::Slider { .. } => p.get(8).map(|e| e.split('|').map(|s| s.parse().unwrap_or(hitsound)).collect()).unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        let sample = sample.filter(|s| s.contains(':')).map(|s| s.to_string()).unwrap_or_else(|| String::from("0:0:0:0:"));
+
+        sources.push(Source { x, y, time, end_time: end_time.max(time), shape, hitsound, edge_sounds, sample });
+    }
+
+    sources.sort_by(|a, b| a.time.total_cmp(&b.time));
+    sources
+}
+
+fn beat_length_at(timing_points: &[TimingPoint], time: f32) -> f64 {
+    timing_points.iter().rev()
+        .find(|tp| tp.uninherited && tp.time <= time)
+        .or_else(|| timing_points.iter().find(|tp| tp.uninherited))
+        .map(|tp| tp.beat_length as f64)
+        .unwrap_or(500.0)
+}
+
+fn kiai_at(timing_points: &[TimingPoint], time: f32) -> bool {
+    timing_points.iter().rev().find(|tp| tp.time <= time).is_some_and(|tp| tp.effects & 1 != 0)
+}
+
+/// How busy the map is, from drain rate, approach rate and objects per second of play (0-12)
+fn conversion_difficulty(content: &str, sources: &[Source], drain_rate: f64, approach_rate: f64) -> f64 {
+    let (Some(first), Some(last)) = (sources.first(), sources.last()) else { return 0.0; };
+
+    // Break events: 2,start,end
+    let breaks: f32 = content.lines()
+        .map(|l| l.trim())
+        .filter(|l| l.starts_with("2,") || l.starts_with("Break,"))
+        .filter_map(|l| {
+            let p: Vec<&str> = l.split(',').collect();
+            Some(p.get(2)?.trim().parse::<f32>().ok()? - p.get(1)?.trim().parse::<f32>().ok()?)
+        })
+        .sum();
+    let mut drain_time = ((last.time - first.time - breaks) / 1000.0) as i32 as f64;
+    if drain_time <= 0.0 {
+        drain_time = 10000.0;
+    }
+
+    let difficulty = ((drain_rate + approach_rate.clamp(4.0, 7.0)) / 1.5 + sources.len() as f64 / drain_time * 9.0) / 38.0 * 5.0 / 1.15;
+    difficulty.min(12.0)
+}
+
+/// osu!'s seed for a map's converter random, from its difficulty settings
+fn conversion_seed(drain_rate: f64, circle_size: f64, overall_difficulty: f64, approach_rate: f64) -> i32 {
+    (drain_rate + circle_size).round_ties_even() as i32 * 20
+        + (overall_difficulty * 41.2) as i32
+        + approach_rate.round_ties_even() as i32
+}
+
+/// Turn a standard (or catch) map into `key_count` columns the way osu!mania converts it:
+/// each object becomes a pattern picked from its spacing to the previous object, its
+/// hitsounds and the map's difficulty. Sliders with repeats turn into stairs, chords or
+/// holds, spinners into holds. The random source is seeded from the difficulty settings,
+/// so the same map always converts the same way.
+pub fn convert_standard(content: &str, key_count: usize, timing_points: &[TimingPoint], slider_multiplier: f32) -> Vec<HitObject> {
+    let sources = parse_sources(content, timing_points, slider_multiplier);
+    let number = |key: &str| find_value(content, key).and_then(|v| v.parse::<f64>().ok());
+    let overall_difficulty = number("OverallDifficulty").unwrap_or(5.0);
+    let drain_rate = number("HPDrainRate").unwrap_or(5.0);
+    let circle_size = number("CircleSize").unwrap_or(5.0);
+    // Old maps have no approach rate; it used to be the overall difficulty
+    let approach_rate = number("ApproachRate").unwrap_or(overall_difficulty);
+
+    let mut random = FastRandom::new(conversion_seed(drain_rate, circle_size, overall_difficulty, approach_rate));
+    let difficulty = conversion_difficulty(content, &sources, drain_rate, approach_rate);
+    let columns = key_count as i32;
+
+    let mut objects = Vec::new();
+    let mut previous = Pattern::default();
+    let mut last_time = 0.0;
+    let mut last_position = (0.0, 0.0);
+    let mut stair = STAIR;
+    let mut density = Density { times: Vec::new(), value: i32::MAX as f64 };
+
+    for source in &sources {
+        let mut generator = Generator {
+            random: &mut random,
+            columns,
+            difficulty,
+            previous: &previous,
+            object: source,
+            convert_type: 0,
+        };
+        let patterns = match source.shape {
+            Shape::Slider { spans, segment } => {
+                let patterns = generator.slider(spans, segment, kiai_at(timing_points, source.time));
+                for node in 0..=spans {
+                    last_time = source.time + segment * node as f32;
+                    last_position = (source.x, source.y);
+                    density.record(last_time);
+                }
+                patterns
+            }
+            Shape::Spinner => {
+                let pattern = generator.spinner();
+                last_time = source.end_time;
+                last_position = (256.0, 192.0);
+                density.record(source.end_time);
+                vec![pattern]
+            }
+            Shape::Circle => {
+                density.record(source.time);
+                let beat_length = beat_length_at(timing_points, source.time);
+                let kiai = kiai_at(timing_points, source.time);
+                let (pattern, next_stair) = generator.circle(last_time, last_position, density.value, stair, beat_length, kiai);
+                stair = next_stair;
+                last_time = source.time;
+                last_position = (source.x, source.y);
+                vec![pattern]
+            }
+        };
+
+        for pattern in &patterns {
+            for &(column, start, end) in &pattern.notes {
+                objects.push(HitObject {
+                    time: start,
+                    end_time: (end > start).then_some(end),
+                    lane: column.clamp(0, columns - 1) as usize,
+                    hitsound: source.hitsound,
+                    sample: source.sample.clone(),
+                });
+            }
+        }
+        previous = patterns.into_iter().last().unwrap_or_default();
+    }
+
+    remove_overlaps(objects, key_count)
+}
+
+/// Taiko maps on a kat-don-don-kat layout: kats (clap/whistle) on the outer lanes, dons on
+/// the inner ones, alternating hands for repeated colours. Big notes take both lanes of
+/// their colour, drumrolls become holds and swells hold both don lanes. Other key counts
+/// spread the four lanes over the stage.
+pub fn convert_taiko(content: &str, key_count: usize, timing_points: &[TimingPoint], slider_multiplier: f32) -> Vec<HitObject> {
+    let mut objects = Vec::new();
+    let mut hands = [0, 0]; // next lane of each colour's pair
+
+    for source in parse_sources(content, timing_points, slider_multiplier) {
+        let is_kat = matches!(source.shape, Shape::Circle) && source.hitsound & (WHISTLE | CLAP) != 0;
+        let (pair, colour) = if is_kat { (KAT_LANES, 1) } else { (DON_LANES, 0) };
+        let lanes = if matches!(source.shape, Shape::Spinner) || source.hitsound & FINISH != 0 {
+            pair.to_vec()
+        } else {
+            let lane = pair[hands[colour]];
+            hands[colour] ^= 1;
+            vec![lane]
+        };
+        let end_time = (source.end_time > source.time).then_some(source.end_time);
+
+        for lane in lanes {
+            objects.push(HitObject {
+                time: source.time,
+                end_time,
+                lane: (lane * key_count / 4).min(key_count - 1),
+                hitsound: source.hitsound,
+                sample: source.sample.clone(),
+            });
+        }
+    }
+
+    remove_overlaps(objects, key_count)
+}
+
+/// Patterns can put a note on a lane that is still held, or two notes on one spot; drop those
+fn remove_overlaps(mut objects: Vec<HitObject>, key_count: usize) -> Vec<HitObject> {
+    objects.sort_by(|a, b| a.time.total_cmp(&b.time));
+    let mut busy_until = vec![f32::MIN; key_count];
+    objects.retain(|object| {
+        if object.time <= busy_until[object.lane] {
+            return false;
+        }
+        busy_until[object.lane] = object.end_time.unwrap_or(object.time);
+        true
+    });
+    objects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_timing_points;
+
+    /// An easy map (conversion difficulty below 2) at 120 BPM with the given objects
+    fn map(mode: i32, objects: &[&str]) -> String {
+        format!(
+            "osu file format v14\n\n[General]\nMode: {}\n\n[Difficulty]\nHPDrainRate:2\nCircleSize:4\nOverallDifficulty:2\n\
+            ApproachRate:4\nSliderMultiplier:1.4\n\n[TimingPoints]\n0,500,4,1,0,100,1,0\n\n[HitObjects]\n{}\n",
+            mode, objects.join("\n"),
+        )
+    }
+
+    fn convert(content: &str, key_count: usize) -> Vec<(f32, Option<f32>, usize)> {
+        let timing_points = parse_timing_points(content);
+        let objects = if content.contains("Mode: 1") {
+            convert_taiko(content, key_count, &timing_points, 1.4)
+        } else {
+            convert_standard(content, key_count, &timing_points, 1.4)
+        };
+        objects.iter().map(|o| (o.time, o.end_time, o.lane)).collect()
+    }
+
+    /// Circles on one spot, `gap` ms apart
+    fn circles(count: usize, gap: usize) -> Vec<String> {
+        (0..count).map(|i| format!("0,0,{},1,0,0:0:0:0:", 1000 + i * gap)).collect()
+    }
+
+    fn lanes(objects: &[(f32, Option<f32>, usize)]) -> Vec<usize> {
+        objects.iter().map(|o| o.2).collect()
+    }
+
+    #[test]
+    fn fast_random_matches_osu() {
+        // Reference values from osu!'s LegacyRandom (xorshift128 with its fixed y/z/w seeds)
+        let mut random = FastRandom::new(518);
+        let values: Vec<u32> = (0..4).map(|_| random.next_u32()).collect();
+        assert_eq!(values, vec![274366864, 2661121515, 3083908576, 4073860478]);
+
+        let mut random = FastRandom::new(518);
+        assert!((random.next_double() - 0.12776202708482742).abs() < 1e-15);
+        assert!((random.next_double() - 0.23918127035722136).abs() < 1e-15);
+
+        let mut random = FastRandom::new(1337);
+        let columns: Vec<i32> = (0..10).map(|_| random.next_range(0, 7)).collect();
+        assert_eq!(columns, vec![0, 1, 3, 6, 6, 3, 3, 1, 2, 1]);
+
+        // Negative seeds wrap like C#'s (uint) cast
+        assert_eq!(FastRandom::new(-5).next_u32(), 273319032);
+    }
+
+    #[test]
+    fn seed_comes_from_difficulty_settings() {
+        assert_eq!(conversion_seed(5.0, 4.0, 8.0, 9.0), 180 + 329 + 9);
+        // Rounding is to even, like .NET's Math.Round
+        assert_eq!(conversion_seed(4.5, 4.0, 8.0, 8.5), 160 + 329 + 8);
+    }
+
+    #[test]
+    fn spaced_circles_walk_a_staircase() {
+        // 81-95 ms apart: single notes stepping one column, turning at the edges
+        let content = map(0, &circles(8, 90).iter().map(String::as_str).collect::<Vec<_>>());
+        assert_eq!(lanes(&convert(&content, 4)), vec![0, 1, 2, 3, 2, 1, 0, 1]);
+    }
+
+    #[test]
+    fn overlapping_circles_cycle_or_stack() {
+        // 126-135 ms on one spot mirrors the previous note, 136-150 ms repeats it
+        let content = map(0, &circles(4, 130).iter().map(String::as_str).collect::<Vec<_>>());
+        assert_eq!(lanes(&convert(&content, 4)), vec![0, 3, 0, 3]);
+        let content = map(0, &circles(4, 140).iter().map(String::as_str).collect::<Vec<_>>());
+        assert_eq!(lanes(&convert(&content, 4)), vec![0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn streams_never_jack() {
+        // 80 ms or less: one note per circle, never on the column of the one before
+        let content = map(0, &circles(32, 60).iter().map(String::as_str).collect::<Vec<_>>());
+        let converted = convert(&content, 7);
+        assert_eq!(converted.len(), 32);
+        assert!(lanes(&converted).windows(2).all(|w| w[0] != w[1]));
+        // Seeded, so converting again gives the same chart
+        assert_eq!(converted, convert(&content, 7));
+    }
+
+    #[test]
+    fn sliders_become_holds_or_stairs() {
+        // Two 50 ms spans: one hold over the whole slider
+        let content = map(0, &["0,0,1000,2,0,L|100:0,2,14"]);
+        let converted = convert(&content, 4);
+        assert_eq!(converted.len(), 1);
+        assert_eq!((converted[0].0, converted[0].1), (1000.0, Some(1100.0)));
+
+        // Three 150 ms spans: a tap on every node, each one column from the last
+        let content = map(0, &["0,0,1000,2,0,L|100:0,3,42"]);
+        let converted = convert(&content, 4);
+        let times: Vec<f32> = converted.iter().map(|o| o.0).collect();
+        assert_eq!(times, vec![1000.0, 1150.0, 1300.0, 1450.0]);
+        assert!(converted.iter().all(|o| o.1.is_none()));
+        assert!(lanes(&converted).windows(2).all(|w| w[0].abs_diff(w[1]) == 1));
+    }
+
+    #[test]
+    fn spinners_become_holds() {
+        let content = map(0, &["256,192,1000,12,0,3000"]);
+        let converted = convert(&content, 4);
+        assert_eq!(converted.len(), 1);
+        assert_eq!((converted[0].0, converted[0].1), (1000.0, Some(3000.0)));
+
+        // Too short to hold
+        let content = map(0, &["256,192,1000,12,0,1050"]);
+        assert_eq!(convert(&content, 4)[0].1, None);
+    }
+
+    #[test]
+    fn taiko_uses_kat_don_don_kat() {
+        let content = map(1, &[
+            "256,192,1000,1,0",              // don
+            "256,192,1100,1,0",              // don, other hand
+            "256,192,1200,1,8",              // kat (clap)
+            "256,192,1300,1,2",              // kat (whistle), other hand
+            "256,192,1400,1,4",              // big don: both don lanes
+            "256,192,1500,2,0,L|300:192,1,140", // drumroll: 500 ms hold
+            "256,192,2500,12,0,3000",        // swell: both don lanes held
+        ]);
+        assert_eq!(convert(&content, 4), vec![
+            (1000.0, None, 1),
+            (1100.0, None, 2),
+            (1200.0, None, 0),
+            (1300.0, None, 3),
+            (1400.0, None, 1),
+            (1400.0, None, 2),
+            (1500.0, Some(2000.0), 1),
+            (2500.0, Some(3000.0), 1),
+            (2500.0, Some(3000.0), 2),
+        ]);
+    }
+
+    #[test]
+    fn overlaps_are_removed() {
+        let object = |time: f32, end_time: Option<f32>, lane: usize| HitObject { time, end_time, lane, hitsound: 0, sample: String::new() };
+        let objects = vec![
+            object(1500.0, None, 0), // inside the hold below
+            object(1000.0, Some(2000.0), 0),
+            object(2000.0, None, 0), // on the hold's tail
+            object(2001.0, None, 0),
+            object(1000.0, None, 1),
+            object(1000.0, None, 1), // same spot
+        ];
+        let kept: Vec<(f32, Option<f32>, usize)> = remove_overlaps(objects, 2).iter().map(|o| (o.time, o.end_time, o.lane)).collect();
+        assert_eq!(kept, vec![(1000.0, Some(2000.0), 0), (1000.0, None, 1), (2001.0, None, 0)]);
+    }
+}
@@ -0,0 +1,807 @@
+use macroquad::prelude::*;
+use macroquad::ui::root_ui;
+use rodio::{Decoder, OutputStreamHandle, Sink, Source};
+use std::fs;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::audio::AudioSystem;
+use crate::models::{GameOptions, TimingPoint};
+use crate::parser::{self, Beatmap, HitObject};
+use crate::writer;
+
+// Beat snap divisors, same set as osu!'s editor
+const SNAP_DIVISORS: [u32; 8] = [1, 2, 3, 4, 6, 8, 12, 16];
+
+const LANE_W: f32 = 60.0;
+const PLAYFIELD_X: f32 = 40.0;
+const PANEL_X: f32 = 440.0;
+const JUDGE_LINE: f32 = 0.8; // playhead position as a fraction of screen height
+const NOTE_H: f32 = 14.0;
+
+enum Drag {
+    Place { lane: usize, start: f32 },
+    Move { index: usize, time_offset: f32, from: (f32, usize) }, // from: original (time, lane)
+}
+
+/// Mania chart editor for one .osu difficulty.
+/// Time flows upward like gameplay; the playhead sits on the judge line.
+pub struct Editor {
+    osu_path: PathBuf,
+    content: String,
+    // Converted maps and osu! Songs folder sets are never overwritten; SAVE writes a new difficulty
+    save_in_place: bool,
+    metadata: Beatmap, // parsed map without notes or timing, for "save as new difficulty"
+    key_count: usize,
+    objects: Vec<HitObject>, // sorted by time
+    timing_points: Vec<TimingPoint>,
+    time: f32, // playhead in ms
+    song_length: f32,
+    zoom: f32, // pixels per ms
+    snap_index: usize,
+    selected_note: Option<usize>,
+    selected_point: Option<usize>,
+    drag: Option<Drag>,
+
+    samples: Arc<Vec<f32>>,
+    sample_rate: u32,
+    channels: u16,
+    stream: Option<OutputStreamHandle>,
+    music: Option<Sink>,
+    play_origin: Option<(Instant, f32)>, // wall clock and song time playback started at
+    next_hitsound: usize,
+    audio: AudioSystem,
+    effects_volume: f32,
+
+    dirty: bool,
+    confirm_quit: bool,
+    status: String,
+    status_time: f64,
+}
+
+impl Editor {
+    /// Load a difficulty for editing. Non-mania maps are edited with `fallback_keys` lanes.
+    /// `external` marks a set in the osu! Songs folder.
+    pub fn open(osu_path: PathBuf, stream: Option<&OutputStreamHandle>, fallback_keys: usize, external: bool) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(&osu_path)?;
+        let mania = parser::find_value(&content, "Mode").as_deref() == Some("3");
+        let mut metadata = parser::parse_beatmap(&content, fallback_keys);
+        let timing_points = std::mem::take(&mut metadata.timing_points);
+        let mut objects = std::mem::take(&mut metadata.hit_objects);
+        objects.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        let audio_path = osu_path.parent().unwrap().join(&metadata.audio_filename);
+        let source = Decoder::new(BufReader::new(fs::File::open(audio_path)?))?;
+        let (sample_rate, channels) = (source.sample_rate(), source.channels());
+        let samples: Vec<f32> = source.convert_samples().collect();
+        let song_length = samples.len() as f32 / (sample_rate as f32 * channels as f32) * 1000.0;
+
+        let mut editor = Self {
+            osu_path,
+            content,
+            save_in_place: mania && !external,
+            key_count: metadata.key_count,
+            metadata,
+            objects,
+            timing_points,
+            time: 0.0,
+            song_length,
+            zoom: 0.5,
+            snap_index: 3, // 1/4
+            selected_note: None,
+            selected_point: None,
+            drag: None,
+            samples: Arc::new(samples),
+            sample_rate,
+            channels,
+            stream: stream.cloned(),
+            music: None,
+            play_origin: None,
+            next_hitsound: 0,
+            audio: AudioSystem::new(stream),
+            effects_volume: -1.0,
+            dirty: false,
+            confirm_quit: false,
+            status: String::new(),
+            status_time: -10.0,
+        };
+
+        if !editor.save_in_place {
+            editor.set_status(String::from("The original is left untouched: saving creates a new difficulty"));
+        }
+        // Saving rewrites [HitObjects], so unreadable lines would be lost
+        if !editor.metadata.warnings.is_empty() {
+            for warning in &editor.metadata.warnings {
+                eprintln!("{}: {}", editor.osu_path.display(), warning);
+            }
+            editor.set_status(format!("{} hit object line(s) could not be read and will be dropped on save", editor.metadata.warnings.len()));
+        }
+        Ok(editor)
+    }
+
+    /// Returns true when the editor should close
+    pub fn update_and_draw(&mut self, options: &GameOptions) -> bool {
+        if self.effects_volume != options.effects_gain() {
+            self.effects_volume = options.effects_gain();
+            self.audio.set_volume(self.effects_volume);
+        }
+        if let Some(music) = &self.music {
+            music.set_volume(options.music_gain());
+        }
+
+        // === PLAYBACK ===
+        if let Some((started, from)) = self.play_origin {
+            self.time = from + started.elapsed().as_secs_f32() * 1000.0;
+            while let Some(object) = self.objects.get(self.next_hitsound) {
+                if object.time > self.time { break; }
+                self.audio.play_hit(self.hitsound_volume_at(object.time));
+                self.next_hitsound += 1;
+            }
+            if self.time >= self.song_length {
+                self.stop_playback();
+                self.time = self.song_length;
+            }
+        }
+
+        let judge_y = screen_height() * JUDGE_LINE;
+
+        // === KEYBOARD ===
+        if is_key_pressed(KeyCode::Space) {
+            if self.play_origin.is_some() {
+                self.stop_playback();
+            } else {
+                self.start_playback(options);
+            }
+        }
+        if is_key_pressed(KeyCode::Delete) || is_key_pressed(KeyCode::Backspace) {
+            if let Some(index) = self.selected_note {
+                self.remove_object(index);
+            }
+        }
+        let ctrl = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+        if ctrl && is_key_pressed(KeyCode::S) {
+            self.save();
+        }
+
+        // Wheel seeks by one snap step, SHIFT + wheel zooms. ALT + wheel is the volume overlay.
+        let alt = is_key_down(KeyCode::LeftAlt) || is_key_down(KeyCode::RightAlt);
+        let shift = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+        let (_, wheel_y) = mouse_wheel();
+        let mut seek = 0;
+        if !alt && wheel_y != 0.0 {
+            if shift {
+                self.zoom = if wheel_y > 0.0 { self.zoom * 1.25 } else { self.zoom / 1.25 }.clamp(0.05, 4.0);
+            } else {
+                seek = if wheel_y > 0.0 { 1 } else { -1 };
+            }
+        }
+        if is_key_pressed(KeyCode::Up) && !alt { seek = 1; }
+        if is_key_pressed(KeyCode::Down) && !alt { seek = -1; }
+        if seek != 0 {
+            let step = self.snap_step(self.time);
+            let target = self.snap(self.time + seek as f32 * step).clamp(0.0, self.song_length);
+            self.seek(target, options);
+        }
+
+        // === DRAWING ===
+        self.draw_timeline(judge_y);
+        self.handle_mouse(judge_y);
+        if self.draw_top_bar(options) {
+            return true;
+        }
+        self.draw_timing_panel(options);
+        if root_ui().button(vec2(PANEL_X, screen_height() - 60.0), "SAVE AS NEW DIFFICULTY") {
+            self.save_as_new_difficulty();
+        }
+
+        if get_time() - self.status_time < 2.5 {
+            draw_text(&self.status, PANEL_X, screen_height() - 20.0, 20.0, YELLOW);
+        }
+
+        false
+    }
+
+    fn draw_top_bar(&mut self, options: &GameOptions) -> bool {
+        draw_rectangle(0.0, 0.0, screen_width(), 40.0, Color::new(0.1, 0.1, 0.1, 0.95));
+
+        let back_text = if self.confirm_quit { "UNSAVED! BACK again" } else { "< BACK" };
+        if root_ui().button(vec2(10.0, 10.0), back_text) || is_key_pressed(KeyCode::Escape) {
+            if !self.dirty || self.confirm_quit {
+                self.stop_playback();
+                return true;
+            }
+            self.confirm_quit = true;
+        }
+
+        let save_text = if self.dirty { "SAVE*" } else { "SAVE" };
+        if root_ui().button(vec2(170.0, 10.0), save_text) {
+            self.save();
+        }
+
+        let play_text = if self.play_origin.is_some() { "PAUSE" } else { "PLAY" };
+        if root_ui().button(vec2(230.0, 10.0), play_text) {
+            if self.play_origin.is_some() {
+                self.stop_playback();
+            } else {
+                self.start_playback(options);
+            }
+        }
+
+        if root_ui().button(vec2(300.0, 10.0), "<") {
+            self.snap_index = self.snap_index.saturating_sub(1);
+        }
+        draw_text(format!("1/{}", SNAP_DIVISORS[self.snap_index]), 320.0, 27.0, 20.0, WHITE);
+        if root_ui().button(vec2(355.0, 10.0), ">") {
+            self.snap_index = (self.snap_index + 1).min(SNAP_DIVISORS.len() - 1);
+        }
+
+        let seconds = self.time / 1000.0;
+        draw_text(
+            format!("{:02}:{:06.3}  ({:.0}ms)  {}K", (seconds / 60.0) as i32, seconds % 60.0, self.time, self.key_count),
+            PANEL_X, 27.0, 20.0, SKYBLUE,
+        );
+        false
+    }
+
+    fn draw_timeline(&self, judge_y: f32) {
+        let total_w = LANE_W * self.key_count as f32;
+        draw_rectangle(PLAYFIELD_X, 0.0, total_w, screen_height(), Color::new(0.05, 0.05, 0.05, 1.0));
+        for i in 0..=self.key_count {
+            let x = PLAYFIELD_X + i as f32 * LANE_W;
+            draw_line(x, 0.0, x, screen_height(), 1.0, Color::new(0.3, 0.3, 0.3, 1.0));
+        }
+
+        // Visible time range: top of the screen is later than the bottom
+        let t_top = self.time_at(0.0, judge_y);
+        let t_bottom = self.time_at(screen_height(), judge_y);
+
+        // === SNAP TICKS ===
+        let divisor = SNAP_DIVISORS[self.snap_index];
+        let uninherited: Vec<&TimingPoint> = self.timing_points.iter().filter(|tp| tp.uninherited).collect();
+        for (i, tp) in uninherited.iter().enumerate() {
+            if tp.beat_length < 1.0 { continue; }
+            let segment_end = uninherited.get(i + 1).map(|next| next.time).unwrap_or(f32::MAX).min(t_top);
+            let step = tp.beat_length / divisor as f32;
+            let first_tick = ((t_bottom.max(tp.time) - tp.time) / step).floor().max(0.0) as i64;
+
+            let mut tick = first_tick;
+            loop {
+                let t = tp.time + tick as f32 * step;
+                if t >= segment_end { break; }
+                let y = self.y_at(t, judge_y);
+                let (color, thickness) = tick_style(tick, divisor, tp.meter);
+                draw_line(PLAYFIELD_X, y, PLAYFIELD_X + total_w, y, thickness, color);
+                tick += 1;
+            }
+        }
+
+        // === NOTES ===
+        for (i, object) in self.objects.iter().enumerate() {
+            let end = object.end_time.unwrap_or(object.time);
+            if end < t_bottom || object.time > t_top { continue; }
+
+            let x = PLAYFIELD_X + object.lane as f32 * LANE_W;
+            let y_head = self.y_at(object.time, judge_y);
+            let selected = self.selected_note == Some(i);
+
+            if is_ln(object) {
+                let y_tail = self.y_at(end, judge_y);
+                draw_rectangle(x + 10.0, y_tail, LANE_W - 20.0, y_head - y_tail, Color::new(0.4, 0.6, 0.9, 0.7));
+                draw_rectangle(x + 4.0, y_tail - 3.0, LANE_W - 8.0, 6.0, Color::new(0.6, 0.8, 1.0, 1.0));
+            }
+            let color = if selected { YELLOW } else { WHITE };
+            draw_rectangle(x + 4.0, y_head - NOTE_H / 2.0, LANE_W - 8.0, NOTE_H, color);
+        }
+
+        // Preview of the note being placed
+        if let Some(Drag::Place { lane, start }) = self.drag {
+            let end = self.snap(self.time_at(mouse_position().1, judge_y));
+            let (a, b) = (start.min(end), start.max(end));
+            let x = PLAYFIELD_X + lane as f32 * LANE_W;
+            draw_rectangle(x + 4.0, self.y_at(b, judge_y), LANE_W - 8.0, self.y_at(a, judge_y) - self.y_at(b, judge_y) + NOTE_H / 2.0, Color::new(1.0, 1.0, 1.0, 0.3));
+        }
+
+        // Timing point markers on the left edge
+        for tp in &self.timing_points {
+            if tp.time < t_bottom || tp.time > t_top { continue; }
+            let y = self.y_at(tp.time, judge_y);
+            let color = if tp.uninherited { RED } else { GREEN };
+            draw_rectangle(PLAYFIELD_X - 12.0, y - 2.0, 10.0, 4.0, color);
+        }
+
+        draw_line(PLAYFIELD_X, judge_y, PLAYFIELD_X + total_w, judge_y, 3.0, SKYBLUE);
+    }
+
+    fn handle_mouse(&mut self, judge_y: f32) {
+        let (mx, my) = mouse_position();
+        let total_w = LANE_W * self.key_count as f32;
+        let in_playfield = mx >= PLAYFIELD_X && mx < PLAYFIELD_X + total_w && my > 40.0;
+        let lane = (((mx - PLAYFIELD_X) / LANE_W).floor().max(0.0) as usize).min(self.key_count - 1);
+        let mouse_time = self.time_at(my, judge_y);
+
+        if is_mouse_button_pressed(MouseButton::Left) && in_playfield {
+            match self.object_at(lane, my, judge_y) {
+                Some(index) => {
+                    self.selected_note = Some(index);
+                    let object = &self.objects[index];
+                    self.drag = Some(Drag::Move { index, time_offset: object.time - mouse_time, from: (object.time, object.lane) });
+                }
+                None => {
+                    self.selected_note = None;
+                    self.drag = Some(Drag::Place { lane, start: self.snap(mouse_time).max(0.0) });
+                }
+            }
+        }
+
+        if let Some(Drag::Move { index, time_offset, .. }) = self.drag {
+            let new_time = self.snap(mouse_time + time_offset).max(0.0);
+            let object = &mut self.objects[index];
+            let delta = new_time - object.time;
+            if delta != 0.0 || object.lane != lane {
+                object.time = new_time;
+                object.end_time = object.end_time.map(|e| e + delta);
+                object.lane = lane;
+                self.dirty = true;
+            }
+        }
+
+        if is_mouse_button_released(MouseButton::Left) {
+            match self.drag.take() {
+                Some(Drag::Place { lane, start }) => {
+                    let end = self.snap(mouse_time).max(0.0);
+                    let (time, end_time) = if (end - start).abs() >= 1.0 {
+                        (start.min(end), Some(start.max(end)))
+                    } else {
+                        (start, None)
+                    };
+                    // One object per lane and time
+                    if !self.objects.iter().any(|o| o.lane == lane && (o.time - time).abs() < 1.0) {
+                        self.objects.push(HitObject { time, end_time, lane, hitsound: 0, sample: String::from("0:0:0:0:") });
+                        self.edited();
+                        self.selected_note = self.objects.iter().position(|o| o.lane == lane && o.time == time);
+                    }
+                }
+                Some(Drag::Move { index, from, .. }) => {
+                    let (mut time, mut lane) = (self.objects[index].time, self.objects[index].lane);
+                    // Same rule as placing: dropping onto another object puts the note back
+                    let taken = self.objects.iter().enumerate()
+                        .any(|(i, o)| i != index && o.lane == lane && (o.time - time).abs() < 1.0);
+                    if taken {
+                        let object = &mut self.objects[index];
+                        object.end_time = object.end_time.map(|e| e + from.0 - time);
+                        (object.time, object.lane) = from;
+                        (time, lane) = from;
+                        self.set_status(String::from("A note is already there"));
+                    }
+                    // A plain click only selects
+                    if (time, lane) != from {
+                        self.edited();
+                    }
+                    self.selected_note = self.objects.iter().position(|o| o.lane == lane && o.time == time);
+                }
+                None => {}
+            }
+        }
+
+        if is_mouse_button_pressed(MouseButton::Right) && in_playfield {
+            if let Some(index) = self.object_at(lane, my, judge_y) {
+                self.remove_object(index);
+            }
+        }
+    }
+
+    fn draw_timing_panel(&mut self, options: &GameOptions) {
+        draw_rectangle(PANEL_X - 10.0, 40.0, screen_width() - PANEL_X + 10.0, screen_height() - 40.0, Color::new(0.1, 0.1, 0.1, 0.95));
+        draw_text("TIMING POINTS", PANEL_X, 70.0, 25.0, WHITE);
+
+        if root_ui().button(vec2(PANEL_X, 80.0), "+ BPM here") {
+            let parent = self.uninherited_at(self.time).cloned();
+            let point = TimingPoint {
+                time: self.time.round(),
+                uninherited: true,
+                velocity_mult: 1.0,
+                ..parent.unwrap_or_else(default_point)
+            };
+            self.insert_point(point);
+        }
+        if root_ui().button(vec2(PANEL_X + 110.0, 80.0), "+ SV here") {
+            let parent = self.uninherited_at(self.time).cloned().unwrap_or_else(default_point);
+            let point = TimingPoint {
+                time: self.time.round(),
+                uninherited: false,
+                velocity_mult: 1.0,
+                ..parent
+            };
+            self.insert_point(point);
+        }
+        if root_ui().button(vec2(PANEL_X + 210.0, 80.0), "Delete") {
+            if let Some(index) = self.selected_point.take() {
+                // The map needs at least one uninherited point
+                if self.timing_points.iter().filter(|tp| tp.uninherited).count() > 1 || !self.timing_points[index].uninherited {
+                    self.timing_points.remove(index);
+                    self.points_edited();
+                }
+            }
+        }
+
+        // List points around the playhead
+        let current = self.timing_points.partition_point(|tp| tp.time <= self.time);
+        let first = current.saturating_sub(4);
+        for (row, index) in (first..self.timing_points.len()).take(8).enumerate() {
+            let tp = &self.timing_points[index];
+            let marker = if self.selected_point == Some(index) { ">" } else { " " };
+            let label = if tp.uninherited {
+                format!("{} {:.0}ms  {:.2} BPM {}/4", marker, tp.time, 60000.0 / tp.beat_length, tp.meter)
+            } else {
+                format!("{} {:.0}ms  SV {:.2}x", marker, tp.time, tp.velocity_mult)
+            };
+            if root_ui().button(vec2(PANEL_X, 115.0 + row as f32 * 26.0), label.as_str()) {
+                self.selected_point = Some(index);
+                self.seek(tp.time, options);
+            }
+        }
+
+        // Edit the selected point; SHIFT makes steps 10x finer
+        let Some(index) = self.selected_point else { return; };
+        let fine = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+        let y = 340.0;
+        draw_text("SELECTED POINT:", PANEL_X, y, 22.0, SKYBLUE);
+
+        let mut offset_delta = 0.0;
+        draw_text("Offset", PANEL_X, y + 28.0, 20.0, GRAY);
+        if root_ui().button(vec2(PANEL_X + 100.0, y + 12.0), "-") { offset_delta = -1.0; }
+        if root_ui().button(vec2(PANEL_X + 125.0, y + 12.0), "+") { offset_delta = 1.0; }
+        if root_ui().button(vec2(PANEL_X + 150.0, y + 12.0), "To playhead") {
+            offset_delta = self.time.round() - self.timing_points[index].time;
+        }
+
+        let tp = &mut self.timing_points[index];
+        let mut changed = false;
+        if tp.uninherited {
+            let mut bpm = 60000.0 / tp.beat_length;
+            let step = if fine { 0.1 } else { 1.0 };
+            draw_text("BPM", PANEL_X, y + 58.0, 20.0, GRAY);
+            if root_ui().button(vec2(PANEL_X + 100.0, y + 42.0), "-") { bpm -= step; changed = true; }
+            if root_ui().button(vec2(PANEL_X + 125.0, y + 42.0), "+") { bpm += step; changed = true; }
+            tp.beat_length = 60000.0 / bpm.max(1.0);
+
+            draw_text("Meter", PANEL_X, y + 88.0, 20.0, GRAY);
+            if root_ui().button(vec2(PANEL_X + 100.0, y + 72.0), "-") { tp.meter = (tp.meter - 1).max(1); changed = true; }
+            if root_ui().button(vec2(PANEL_X + 125.0, y + 72.0), "+") { tp.meter = (tp.meter + 1).min(16); changed = true; }
+        } else {
+            let step = if fine { 0.01 } else { 0.05 };
+            draw_text("SV", PANEL_X, y + 58.0, 20.0, GRAY);
+            if root_ui().button(vec2(PANEL_X + 100.0, y + 42.0), "-") { tp.velocity_mult = (tp.velocity_mult - step).max(0.01); changed = true; }
+            if root_ui().button(vec2(PANEL_X + 125.0, y + 42.0), "+") { tp.velocity_mult = (tp.velocity_mult + step).min(10.0); changed = true; }
+        }
+
+        draw_text(format!("Volume {:.0}", tp.volume), PANEL_X, y + 118.0, 20.0, GRAY);
+        if root_ui().button(vec2(PANEL_X + 100.0, y + 102.0), "-") { tp.volume = (tp.volume - 5.0).max(0.0); changed = true; }
+        if root_ui().button(vec2(PANEL_X + 125.0, y + 102.0), "+") { tp.volume = (tp.volume + 5.0).min(100.0); changed = true; }
+
+        if offset_delta != 0.0 {
+            let step = if fine && offset_delta.abs() == 1.0 { 0.1 } else { 1.0 };
+            tp.time = (tp.time + offset_delta * step).max(0.0);
+            let (time, uninherited) = (tp.time, tp.uninherited);
+            self.points_edited();
+            self.selected_point = self.timing_points.iter().position(|p| p.time == time && p.uninherited == uninherited);
+        } else if changed {
+            self.points_edited();
+        }
+    }
+
+    // === HELPERS ===
+
+    fn y_at(&self, time: f32, judge_y: f32) -> f32 {
+        judge_y - (time - self.time) * self.zoom
+    }
+
+    fn time_at(&self, y: f32, judge_y: f32) -> f32 {
+        self.time + (judge_y - y) / self.zoom
+    }
+
+    fn object_at(&self, lane: usize, y: f32, judge_y: f32) -> Option<usize> {
+        self.objects.iter().position(|o| {
+            if o.lane != lane { return false; }
+            let y_head = self.y_at(o.time, judge_y);
+            let y_tail = self.y_at(o.end_time.unwrap_or(o.time), judge_y);
+            (y - y_head).abs() <= NOTE_H / 2.0 || (is_ln(o) && y <= y_head && y >= y_tail)
+        })
+    }
+
+    fn uninherited_at(&self, time: f32) -> Option<&TimingPoint> {
+        self.timing_points.iter()
+            .filter(|tp| tp.uninherited)
+            .rev()
+            .find(|tp| tp.time <= time)
+            .or_else(|| self.timing_points.iter().find(|tp| tp.uninherited))
+    }
+
+    fn hitsound_volume_at(&self, time: f32) -> f32 {
+        self.timing_points.iter()
+            .rev()
+            .find(|tp| tp.time <= time)
+            .map(|tp| (tp.volume / 100.0).clamp(0.0, 1.0))
+            .unwrap_or(1.0)
+    }
+
+    fn snap_step(&self, time: f32) -> f32 {
+        let beat_length = self.uninherited_at(time).map(|tp| tp.beat_length).unwrap_or(500.0);
+        beat_length / SNAP_DIVISORS[self.snap_index] as f32
+    }
+
+    /// Round a time to the nearest tick of the current divisor
+    fn snap(&self, time: f32) -> f32 {
+        let Some(tp) = self.uninherited_at(time) else { return time.round(); };
+        let step = tp.beat_length / SNAP_DIVISORS[self.snap_index] as f32;
+        tp.time + ((time - tp.time) / step).round() * step
+    }
+
+    fn insert_point(&mut self, point: TimingPoint) {
+        let (time, uninherited) = (point.time, point.uninherited);
+        // Replace a point of the same kind at the same time instead of stacking them
+        self.timing_points.retain(|tp| !(tp.time == time && tp.uninherited == uninherited));
+        self.timing_points.push(point);
+        self.points_edited();
+        self.selected_point = self.timing_points.iter().position(|tp| tp.time == time && tp.uninherited == uninherited);
+    }
+
+    /// Re-sort points and refresh inherited beat lengths/meters from their parents
+    fn points_edited(&mut self) {
+        self.timing_points.sort_by(|a, b| a.time.total_cmp(&b.time).then(b.uninherited.cmp(&a.uninherited)));
+        let mut parent: Option<(f32, i32)> = None;
+        for tp in self.timing_points.iter_mut() {
+            if tp.uninherited {
+                parent = Some((tp.beat_length, tp.meter));
+            } else if let Some((beat_length, meter)) = parent {
+                tp.beat_length = beat_length;
+                tp.meter = meter;
+            }
+        }
+        self.dirty = true;
+        self.confirm_quit = false;
+    }
+
+    /// Indices shift on removal, so the selection and any drag are dropped with it
+    fn remove_object(&mut self, index: usize) {
+        self.objects.remove(index);
+        self.selected_note = None;
+        self.drag = None;
+        self.edited();
+    }
+
+    fn edited(&mut self) {
+        self.objects.sort_by(|a, b| a.time.total_cmp(&b.time));
+        self.next_hitsound = self.objects.partition_point(|o| o.time < self.time);
+        self.dirty = true;
+        self.confirm_quit = false;
+    }
+
+    fn seek(&mut self, time: f32, options: &GameOptions) {
+        let playing = self.play_origin.is_some();
+        self.stop_playback();
+        self.time = time;
+        if playing {
+            self.start_playback(options);
+        }
+    }
+
+    fn start_playback(&mut self, options: &GameOptions) {
+        if self.time >= self.song_length {
+            self.time = 0.0;
+        }
+        if let Some(stream) = &self.stream {
+            if let Ok(sink) = Sink::try_new(stream) {
+                // Start on a frame boundary so channels stay in order
+                let frame = (self.time / 1000.0 * self.sample_rate as f32) as usize;
+                let position = (frame * self.channels as usize).min(self.samples.len());
+                sink.append(SongSource { samples: self.samples.clone(), position, channels: self.channels, sample_rate: self.sample_rate });
+                sink.set_volume(options.music_gain());
+                self.music = Some(sink);
+            }
+        }
+        self.next_hitsound = self.objects.partition_point(|o| o.time < self.time);
+        self.play_origin = Some((Instant::now(), self.time));
+    }
+
+    fn stop_playback(&mut self) {
+        if let Some(music) = self.music.take() {
+            music.stop();
+        }
+        self.play_origin = None;
+    }
+
+    fn set_status(&mut self, status: String) {
+        self.status = status;
+        self.status_time = get_time();
+    }
+
+    /// Write the current chart next to the original as a separate difficulty.
+    /// Returns the new file and its contents.
+    fn save_as_new_difficulty(&mut self) -> Option<(PathBuf, String)> {
+        let folder = self.osu_path.parent().unwrap().to_path_buf();
+        let stem = self.osu_path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+        let mut n = 1;
+        let path = loop {
+            let candidate = folder.join(format!("{} (copy {}).osu", stem, n));
+            if !candidate.exists() { break candidate; }
+            n += 1;
+        };
+
+        let beatmap = Beatmap {
+            version: format!("{} (copy {})", self.metadata.version, n),
+            beatmap_id: 0,
+            key_count: self.key_count,
+            timing_points: self.timing_points.clone(),
+            hit_objects: self.objects.clone(),
+            ..self.metadata.clone()
+        };
+        let content = writer::write_osu(&beatmap);
+        match fs::write(&path, &content) {
+            Ok(()) => {
+                self.set_status(format!("Saved new difficulty {}", beatmap.version));
+                Some((path, content))
+            }
+            Err(e) => {
+                self.set_status(format!("Save failed: {}", e));
+                None
+            }
+        }
+    }
+
+    /// Write the chart back to its .osu file, keeping every other section as it was.
+    /// The first save of a map that can't be saved in place creates a new difficulty,
+    /// which later saves then update.
+    fn save(&mut self) {
+        if !self.save_in_place {
+            if let Some((path, content)) = self.save_as_new_difficulty() {
+                self.osu_path = path;
+                self.content = content;
+                self.save_in_place = true;
+                self.dirty = false;
+                self.confirm_quit = false;
+            }
+            return;
+        }
+
+        let backup = self.osu_path.with_extension("osu.bak");
+        if !backup.exists() {
+            let _ = fs::copy(&self.osu_path, &backup);
+        }
+
+        let newline = if self.content.contains("\r\n") { "\r\n" } else { "\n" };
+        let has_mode = parser::find_value(&self.content, "Mode").is_some();
+        let mut out = String::new();
+        let mut skipping = false;
+
+        for line in self.content.lines() {
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') {
+                skipping = false;
+                out.push_str(trimmed);
+                out.push_str(newline);
+                match trimmed {
+                    // A missing Mode means osu!standard
+                    "[General]" if !has_mode => {
+                        out.push_str("Mode: 3");
+                        out.push_str(newline);
+                    }
+                    "[TimingPoints]" => {
+                        for tp in &self.timing_points {
+                            out.push_str(&writer::timing_point_line(tp));
+                            out.push_str(newline);
+                        }
+                        out.push_str(newline);
+                        skipping = true;
+                    }
+                    "[HitObjects]" => {
+                        for object in &self.objects {
+                            out.push_str(&writer::hit_object_line(object, self.key_count));
+                            out.push_str(newline);
+                        }
+                        skipping = true;
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            if skipping { continue; }
+
+            // Whatever the source mode was, the saved chart is a mania chart
+            let key = trimmed.split(':').next().unwrap_or("").trim();
+            match key {
+                "Mode" => out.push_str("Mode: 3"),
+                "CircleSize" => out.push_str(&format!("CircleSize:{}", self.key_count)),
+                _ => out.push_str(line),
+            }
+            out.push_str(newline);
+        }
+
+        match fs::write(&self.osu_path, &out) {
+            Ok(()) => {
+                self.content = out;
+                self.dirty = false;
+                self.confirm_quit = false;
+                self.set_status(format!("Saved {}", self.osu_path.file_name().unwrap_or_default().to_string_lossy()));
+            }
+            Err(e) => self.set_status(format!("Save failed: {}", e)),
+        }
+    }
+}
+
+/// The decoded song from some point on, sharing the samples so seeking copies nothing
+struct SongSource {
+    samples: Arc<Vec<f32>>,
+    position: usize,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl Iterator for SongSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.samples.get(self.position).copied();
+        self.position += 1;
+        sample
+    }
+}
+
+impl Source for SongSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+fn is_ln(object: &HitObject) -> bool {
+    object.end_time.is_some_and(|e| e - object.time >= 1.0)
+}
+
+fn default_point() -> TimingPoint {
+    TimingPoint {
+        time: 0.0,
+        beat_length: 500.0,
+        velocity_mult: 1.0,
+        meter: 4,
+        sample_set: 0,
+        sample_index: 0,
+        volume: 100.0,
+        uninherited: true,
+        effects: 0,
+    }
+}
+
+/// Color and thickness of snap tick `tick` (counted from its timing point), osu!-style
+fn tick_style(tick: i64, divisor: u32, meter: i32) -> (Color, f32) {
+    let divisor = divisor as i64;
+    if tick % divisor == 0 {
+        let beat = tick / divisor;
+        if beat % meter.max(1) as i64 == 0 {
+            return (WHITE, 2.0);
+        }
+        return (Color::new(0.7, 0.7, 0.7, 1.0), 1.0);
+    }
+    let sub = tick % divisor;
+    if divisor % 2 == 0 && sub * 2 == divisor {
+        (Color::new(0.9, 0.2, 0.2, 0.8), 1.0) // 1/2
+    } else if divisor % 4 == 0 && (sub * 4) % divisor == 0 {
+        (Color::new(0.3, 0.5, 1.0, 0.8), 1.0) // 1/4
+    } else if divisor % 3 == 0 && (sub * 3) % divisor == 0 {
+        (Color::new(0.7, 0.3, 0.9, 0.8), 1.0) // 1/3
+    } else {
+        (Color::new(0.9, 0.9, 0.3, 0.6), 1.0)
+    }
+}
@@ -1,10 +1,20 @@
 use macroquad::prelude::*;
-use crate::models::{GameState, HitJudgment, GameOptions};
+use crate::input::InputQueue;
+use crate::models::{GameState, HitJudgment, GameOptions, Grade, LnScoring, Note};
 
 // osu!mania timing windows (in seconds)
 const OK_WINDOW: f32 = 0.135;       // ±135ms = 50
+const TAIL_LENIENCE: f32 = 1.5;     // LN releases get 1.5x wider windows
 
-pub fn update_and_draw(state: &mut GameState, options: &mut GameOptions) -> bool {
+// Hold tick scoring
+const HOLD_TICK_INTERVAL: f32 = 0.1;
+const HOLD_TICK_SCORE: i32 = 10;
+
+// Combo
+const COMBO_MILESTONE: i32 = 100;      // flash the counter every 100 combo
+const COMBO_BREAK_SOUND_MIN: i32 = 20; // like osu!, losing a tiny combo stays silent
+
+pub fn update_and_draw(state: &mut GameState, options: &mut GameOptions, input: &InputQueue) -> bool {
     let dt = get_frame_time();
     
     // Handle scroll speed changes with F3/F4
@@ -35,25 +45,38 @@ pub fn update_and_draw(state: &mut GameState, options: &mut GameOptions) -> bool
         state.paused = !state.paused;
         if state.paused {
             state.pause_start = Some(std::time::Instant::now());
+            // Freeze hold sounds so they pick up where they left off
+            if let Some(audio) = &state.audio {
+                audio.pause_sliders();
+            }
         } else {
             if let Some(pause_start) = state.pause_start {
                 state.total_pause_time += pause_start.elapsed().as_secs_f32();
             }
             state.pause_start = None;
+            if let Some(audio) = &state.audio {
+                audio.resume_sliders();
+            }
         }
     }
     
     if state.paused {
-        // Stop all slider sounds when paused
-        if let Some(audio) = &state.audio {
-            audio.stop_all_sliders();
-        }
         draw_pause_menu(state, options);
         return false; // Don't quit
     }
     
     let now = state.start_time.elapsed().as_secs_f32() - state.total_pause_time;
     
+    if let Some(audio) = &state.audio {
+        while let Some(&(time, keysound, volume)) = state.sample_events.get(state.next_sample_event) {
+            if time > now {
+                break;
+            }
+            audio.play_keysound(keysound, volume);
+            state.next_sample_event += 1;
+        }
+    }
+    
     if now >= state.song_duration + 2.0 {
         state.song_finished = true;
         // Stop all slider sounds when song finishes
@@ -75,7 +98,8 @@ pub fn update_and_draw(state: &mut GameState, options: &mut GameOptions) -> bool
     // Formula: higher number = faster scroll
     let scroll_speed = 400.0 + (options.scroll_speed as f32 * 50.0);
     
-    let lane_w = 100.0; 
+    // 8K gets narrower lanes on small windows
+    let lane_w = (screen_width() / state.key_count as f32).min(100.0);
     let total_w = lane_w * state.key_count as f32;
     let start_x = (screen_width() - total_w) / 2.0;
     let playfield_height = screen_height();
@@ -88,20 +112,30 @@ pub fn update_and_draw(state: &mut GameState, options: &mut GameOptions) -> bool
         playfield_height * 0.85  // Bottom of screen for normal
     };
     
-    // Draw background
-    if let Some(bg) = &state.bg_texture {
+    // Draw background (a playing video covers the static image)
+    let now_ms = now * 1000.0;
+    if options.show_storyboard {
+        state.storyboard.update(now_ms);
+    }
+    let video_playing = options.show_storyboard && state.storyboard.video_active(now_ms);
+    draw_rectangle(0.0, 0.0, screen_width(), screen_height(), BLACK);
+    if let (Some(bg), false) = (&state.bg_texture, video_playing) {
+        let (bg_x, bg_y, bg_w, bg_h) = options.background_fit.dest_rect(bg.width(), bg.height());
         draw_texture_ex(
-            bg, 0.0, 0.0, WHITE,
+            bg, bg_x, bg_y, WHITE,
             DrawTextureParams {
-                dest_size: Some(vec2(screen_width(), screen_height())),
+                dest_size: Some(vec2(bg_w, bg_h)),
                 ..Default::default()
             }
         );
-    } else {
-        draw_rectangle(0.0, 0.0, screen_width(), screen_height(), BLACK);
     }
     
-    draw_rectangle(0.0, 0.0, screen_width(), screen_height(), Color::new(0.0, 0.0, 0.0, 0.4));
+    if options.show_storyboard {
+        state.storyboard.draw(now_ms, options.background_fit);
+    }
+    
+    let dim = options.background_dim as f32 / 100.0;
+    draw_rectangle(0.0, 0.0, screen_width(), screen_height(), Color::new(0.0, 0.0, 0.0, dim));
     draw_rectangle(start_x, hit_zone, total_w, 4.0, WHITE);
     
     for i in 1..state.key_count {
@@ -109,60 +143,66 @@ pub fn update_and_draw(state: &mut GameState, options: &mut GameOptions) -> bool
         draw_line(lx, 0.0, lx, playfield_height, 1.0, Color::new(0.3, 0.3, 0.3, 0.5));
     }
 
-    let keys = if state.key_count == 2 { 
-        &options.keys_2k[..]
-    } else { 
-        &options.keys_4k[..]
-    };
+    let bindings = options.lane_bindings(state.key_count);
+    // Charts with fewer lanes than bound lanes leave the extra bindings unused
+    let bindings = &bindings[..bindings.len().min(state.lane_notes.len())];
+    let lanes_down: Vec<bool> = bindings.iter().map(|lane| lane.iter().any(|b| input.is_down(b))).collect();
 
-    // Draw lane highlights and labels
-    for (i, key) in keys.iter().enumerate() {
+    // Draw lane highlights and labels (the lane's first binding)
+    for (i, lane_bindings) in bindings.iter().enumerate() {
         let lx = start_x + (i as f32 * lane_w);
 
-        if is_key_down(*key) {
+        if lanes_down[i] {
             draw_rectangle(lx, 0.0, lane_w, playfield_height, Color::new(1.0, 1.0, 1.0, 0.1));
         }
 
-        let label = format!("{:?}", key);
-        let measure = measure_text(&label, None, 30, 1.0);
+        let label = lane_bindings.first().map(|b| b.name()).unwrap_or_default();
+        let label_size = if measure_text(&label, None, 30, 1.0).width > lane_w - 8.0 { 18.0 } else { 30.0 };
+        let measure = measure_text(&label, None, label_size as u16, 1.0);
         let label_x = lx + (lane_w - measure.width) / 2.0;
         let label_y = if options.reverse_mode {
             hit_zone + 40.0  // Below hit zone for FNF
         } else {
             hit_zone - 10.0  // Above hit zone for normal
         };
-        draw_text(&label, label_x, label_y, 30.0, WHITE);
+        draw_text(&label, label_x, label_y, label_size, WHITE);
     }
 
-    // === KEY PRESS HANDLING ===
-    for (i, key) in keys.iter().enumerate() {
-        if is_key_pressed(*key) {
-            handle_key_press(state, i, now);
-        }
-    }
-
-    // === KEY RELEASE HANDLING (for LN tails) ===
-    for (i, key) in keys.iter().enumerate() {
-        if is_key_released(*key) {
-            handle_key_release(state, i, now);
+    // === KEY PRESS / RELEASE HANDLING ===
+    // Judged in the order they happened, at the song time each event was stamped with (see `InputQueue`)
+    for event in input.events() {
+        let Some(lane) = bindings.iter().position(|lane| lane.contains(&event.binding)) else { continue; };
+        let time = event.time.saturating_duration_since(state.start_time).as_secs_f32() - state.total_pause_time;
+        let time = time.min(now);
+        if event.pressed {
+            handle_key_press(state, lane, time, options.note_lock, options.ln_scoring);
+        } else {
+            handle_key_release(state, lane, time, options.ln_scoring);
         }
     }
 
     // === HOLD INTEGRITY CHECKING ===
-    for (i, key) in keys.iter().enumerate() {
-        let is_holding = is_key_down(*key);
-        check_ln_hold_integrity(state, i, is_holding, now);
+    for (i, is_holding) in lanes_down.into_iter().enumerate() {
+        check_ln_hold_integrity(state, i, is_holding, now, options.ln_scoring);
     }
     
+    // Only notes between each lane's cursor and the top of the screen need any work this frame.
+    // LN bodies are drawn from up to 1000px past the edge, so look that far ahead.
+    let now_pos = state.scroll_map.position_at(now);
+    let ahead = (screen_height() + 1000.0) / scroll_speed;
+    let behind = (screen_height() + 50.0) / scroll_speed;
+    let active = active_notes(state, now_pos, behind, ahead);
+
     // === SLIDER SOUND MANAGEMENT ===
-    // Start/stop slider sounds based on hold state
-    for note in state.notes.iter_mut() {
+    // Start/stop slider sounds based on hold state, one loop per LN
+    for &idx in &active {
+        let note = &mut state.notes[idx];
         if !note.is_ln { continue; }
         if !note.ln_head_hit || note.ln_completed || note.ln_hold_broken { 
             // Stop sound if it was playing
             if note.slider_sound_playing {
                 if let Some(audio) = &state.audio {
-                    audio.stop_slider(note.lane);
+                    audio.stop_slider(note.lane, idx);
                 }
                 note.slider_sound_playing = false;
             }
@@ -174,7 +214,7 @@ pub fn update_and_draw(state: &mut GameState, options: &mut GameOptions) -> bool
             if !note.slider_sound_playing {
                 // Start slider sound
                 if let Some(audio) = &state.audio {
-                    audio.play_slider_start(note.lane);
+                    audio.play_slider_start(note.lane, idx, note.hitsound_volume);
                 }
                 note.slider_sound_playing = true;
             }
@@ -182,10 +222,31 @@ pub fn update_and_draw(state: &mut GameState, options: &mut GameOptions) -> bool
     }
 
     // === MISS CHECKING ===
-    check_missed_notes(state, now);
+    check_missed_notes(state, &active, now);
+    
+    // === BEAT / MEASURE LINES ===
+    if options.show_measure_lines || options.show_beat_lines {
+        // Lines are sorted by position; skip everything already past the hit zone
+        let first = state.beat_lines.partition_point(|l| l.pos < now_pos);
+        for line in &state.beat_lines[first..] {
+            let y = if options.reverse_mode {
+                hit_zone + ((line.pos - now_pos) * scroll_speed)
+            } else {
+                hit_zone - ((line.pos - now_pos) * scroll_speed)
+            };
+            if y < -10.0 || y > screen_height() + 10.0 { break; }
+            
+            if line.measure && options.show_measure_lines {
+                draw_line(start_x, y, start_x + total_w, y, 2.0, Color::new(1.0, 1.0, 1.0, 0.5));
+            } else if !line.measure && options.show_beat_lines {
+                draw_line(start_x, y, start_x + total_w, y, 1.0, Color::new(0.6, 0.6, 0.6, 0.25));
+            }
+        }
+    }
 
     // === DRAWING NOTES ===
-    for note in state.notes.iter() {
+    for &idx in &active {
+        let note = &state.notes[idx];
         // Don't draw completely missed regular notes
         if note.missed && !note.is_ln {
             continue;
@@ -193,20 +254,20 @@ pub fn update_and_draw(state: &mut GameState, options: &mut GameOptions) -> bool
         
         let x = start_x + (note.lane as f32 * lane_w);
         
-        // Calculate Y position based on mode
+        // Calculate Y position based on mode (scroll positions follow SV)
         let y_head = if options.reverse_mode {
             // FNF: notes scroll DOWN (positive direction)
-            hit_zone + ((note.start_time - now) * scroll_speed)
+            hit_zone + ((note.start_pos - now_pos) * scroll_speed)
         } else {
             // Normal: notes scroll UP (negative direction)
-            hit_zone - ((note.start_time - now) * scroll_speed)
+            hit_zone - ((note.start_pos - now_pos) * scroll_speed)
         };
 
         if note.is_ln {
             let y_tail = if options.reverse_mode {
-                hit_zone + ((note.end_time - now) * scroll_speed)
+                hit_zone + ((note.end_pos - now_pos) * scroll_speed)
             } else {
-                hit_zone - ((note.end_time - now) * scroll_speed)
+                hit_zone - ((note.end_pos - now_pos) * scroll_speed)
             };
             
             // Check visibility based on mode
@@ -270,9 +331,24 @@ pub fn update_and_draw(state: &mut GameState, options: &mut GameOptions) -> bool
     }
 
     // === UI ELEMENTS ===
+    // The counter swells and turns gold for a moment at every milestone
+    let since_milestone = now - state.milestone_time;
+    let milestone_active = options.combo_milestones && since_milestone < 1.0;
+    let (combo_size, combo_color) = if milestone_active {
+        let t = 1.0 - since_milestone;
+        (60.0 * (1.0 + 0.4 * t * t), Color::new(1.0, 0.8 + 0.2 * (1.0 - t), 1.0 - t, 1.0))
+    } else {
+        (60.0, WHITE)
+    };
     let combo_text = format!("{}", state.combo);
-    let measure = measure_text(&combo_text, None, 60, 1.0);
-    draw_text(&combo_text, (screen_width() - measure.width)/2.0, 200.0, 60.0, WHITE);
+    let measure = measure_text(&combo_text, None, combo_size as u16, 1.0);
+    draw_text(&combo_text, (screen_width() - measure.width)/2.0, 200.0, combo_size, combo_color);
+    if milestone_active {
+        let milestone_text = format!("{} COMBO!", state.combo - state.combo % COMBO_MILESTONE);
+        let milestone_measure = measure_text(&milestone_text, None, 30, 1.0);
+        draw_text(&milestone_text, (screen_width() - milestone_measure.width) / 2.0, 240.0, 30.0,
+                  Color::new(1.0, 0.8, 0.0, 1.0 - since_milestone));
+    }
     
     if now - state.judgment_time < 0.5 {
         let jtext = state.last_judgment;
@@ -318,39 +394,46 @@ pub fn update_and_draw(state: &mut GameState, options: &mut GameOptions) -> bool
     draw_text(&format!("Score: {}", state.score), 10.0, 100.0, 20.0, WHITE);
     draw_text(&format!("Speed: {} (F3/F4)", options.scroll_speed), 10.0, 125.0, 20.0, SKYBLUE);
     
+    draw_live_stats(state, options);
+    
     false // Don't quit
 }
 
-fn handle_key_press(state: &mut GameState, lane: usize, now: f32) {
-    // Find closest unhit note in this lane
-    let mut closest_note: Option<(usize, f32)> = None;
+/// Whether a note's head still waits for a press
+fn head_pending(note: &Note) -> bool {
+    if note.is_ln {
+        !note.ln_head_hit && !note.missed
+    } else {
+        !note.hit && !note.missed
+    }
+}
 
-    for (idx, note) in state.notes.iter().enumerate() {
-        if note.lane != lane { continue; }
-        
-        // For LNs: only consider if head not yet hit
-        // For regular notes: only consider if not hit
-        let can_hit = if note.is_ln {
-            !note.ln_head_hit && !note.missed
-        } else {
-            !note.hit && !note.missed
-        };
-        
-        if !can_hit { continue; }
-        
-        let time_diff = (note.start_time - now).abs();
-        if time_diff < OK_WINDOW {
-            if let Some((_, current_diff)) = closest_note {
-                if time_diff < current_diff {
-                    closest_note = Some((idx, time_diff));
-                }
-            } else {
-                closest_note = Some((idx, time_diff));
-            }
-        }
+/// Move the lane's queue head past notes whose head is already judged
+fn advance_lane_head(state: &mut GameState, lane: usize) {
+    while let Some(&idx) = state.lane_notes[lane].get(state.lane_heads[lane]) {
+        if head_pending(&state.notes[idx]) { break; }
+        state.lane_heads[lane] += 1;
+    }
+}
+
+/// The note a press in `lane` hits. With notelock the earliest pending note in its window wins,
+/// so a later note can't be taken before an earlier one; without it the closest one does.
+fn press_target(state: &mut GameState, lane: usize, now: f32, note_lock: bool) -> Option<usize> {
+    advance_lane_head(state, lane);
+    let notes = &state.notes;
+    let mut candidates = state.lane_notes[lane][state.lane_heads[lane]..].iter().copied()
+        .take_while(|&idx| notes[idx].start_time - now < OK_WINDOW)
+        .filter(|&idx| head_pending(&notes[idx]) && (notes[idx].start_time - now).abs() < OK_WINDOW);
+
+    if note_lock {
+        candidates.next()
+    } else {
+        candidates.min_by(|&a, &b| (notes[a].start_time - now).abs().total_cmp(&(notes[b].start_time - now).abs()))
     }
+}
 
-    if let Some((idx, _)) = closest_note {
+fn handle_key_press(state: &mut GameState, lane: usize, now: f32, note_lock: bool, ln_scoring: LnScoring) {
+    if let Some(idx) = press_target(state, lane, now, note_lock) {
         let note = &mut state.notes[idx];
         let timing_diff = note.start_time - now;
         let abs_timing = timing_diff.abs();
@@ -362,158 +445,238 @@ fn handle_key_press(state: &mut GameState, lane: usize, now: f32) {
                 // LN HEAD HIT
                 note.ln_head_hit = true;
                 note.ln_head_judgment = Some(judgment);
-                state.combo += 1;
-                state.score += judgment.score_value();
-                
-                // Update hit counts for head
-                match judgment {
-                    HitJudgment::Perfect => state.hit_counts.perfect += 1,
-                    HitJudgment::Great => state.hit_counts.great += 1,
-                    HitJudgment::Good => state.hit_counts.good += 1,
-                    HitJudgment::Ok => state.hit_counts.ok += 1,
-                    _ => {}
-                }
-                
-                state.last_judgment = judgment.text();
-                state.judgment_color = judgment.color();
-                state.judgment_time = now;
-                state.last_input_delay = timing_diff * 1000.0;
+                note.ln_head_offset = timing_diff;
+                state.holding[lane] = Some(idx);
                 
                 // Play hit sound
                 if let Some(audio) = &state.audio {
-                    audio.play_hit();
+                    audio.play_note(note.keysound, note.hitsound_volume);
                 }
-            } else {
-                // REGULAR NOTE HIT
-                note.hit = true;
-                state.combo += 1;
-                state.score += judgment.score_value();
                 
-                match judgment {
-                    HitJudgment::Perfect => state.hit_counts.perfect += 1,
-                    HitJudgment::Great => state.hit_counts.great += 1,
-                    HitJudgment::Good => state.hit_counts.good += 1,
-                    HitJudgment::Ok => state.hit_counts.ok += 1,
-                    _ => {}
+                match ln_scoring {
+                    // The head only counts towards combo; the LN is judged once at its tail
+                    LnScoring::Combined => {
+                        add_combo(state, now);
+                        state.last_judgment = judgment.text();
+                        state.judgment_color = judgment.color();
+                        state.judgment_time = now;
+                    }
+                    LnScoring::Ticks => record_judgment(state, judgment, now),
                 }
-                
-                state.last_judgment = judgment.text();
-                state.judgment_color = judgment.color();
-                state.judgment_time = now;
                 state.last_input_delay = timing_diff * 1000.0;
+            } else {
+                // REGULAR NOTE HIT
+                note.hit = true;
                 
                 // Play hit sound
                 if let Some(audio) = &state.audio {
-                    audio.play_hit();
+                    audio.play_note(note.keysound, note.hitsound_volume);
                 }
+                
+                record_judgment(state, judgment, now);
+                state.last_input_delay = timing_diff * 1000.0;
             }
         }
     }
 }
 
-fn handle_key_release(state: &mut GameState, lane: usize, now: f32) {
-    // Find active LNs in this lane that are waiting for release
-    for note in state.notes.iter_mut() {
-        if note.lane != lane { continue; }
-        if !note.is_ln { continue; }
-        if !note.ln_head_hit { continue; }
-        if note.ln_completed || note.ln_hold_broken { continue; }
-        
-        // Check if we're within the tail's timing window
-        let tail_timing_diff = note.end_time - now;
-        let abs_tail_timing = tail_timing_diff.abs();
-        
-        if abs_tail_timing < OK_WINDOW {
-            // Valid tail release
-            let tail_judgment = HitJudgment::from_timing(abs_tail_timing);
-            
-            note.ln_tail_judgment = Some(tail_judgment);
-            note.ln_completed = true;
-            state.combo += 1;
-            state.score += tail_judgment.score_value();
-            
-            // Update hit counts for tail
-            match tail_judgment {
-                HitJudgment::Perfect => state.hit_counts.perfect += 1,
-                HitJudgment::Great => state.hit_counts.great += 1,
-                HitJudgment::Good => state.hit_counts.good += 1,
-                HitJudgment::Ok => state.hit_counts.ok += 1,
-                _ => {}
-            }
-            
-            state.last_judgment = tail_judgment.text();
-            state.judgment_color = tail_judgment.color();
-            state.judgment_time = now;
-            state.last_input_delay = tail_timing_diff * 1000.0;
-            
-            // Play hit sound for tail
-            if let Some(audio) = &state.audio {
-                audio.play_hit();
-            }
-            
-            // Only process one LN tail per release
-            break;
+fn add_combo(state: &mut GameState, now: f32) {
+    state.combo += 1;
+    state.max_combo = state.max_combo.max(state.combo);
+    if state.combo % COMBO_MILESTONE == 0 {
+        state.milestone_time = now;
+    }
+}
+
+/// Reset the combo, remembering when it broke for the results screen
+fn break_combo(state: &mut GameState, now: f32) {
+    if state.combo > 0 {
+        state.combo_breaks.push(now);
+    }
+    if state.combo >= COMBO_BREAK_SOUND_MIN {
+        if let Some(audio) = &state.audio {
+            audio.play_combo_break();
         }
     }
+    state.combo = 0;
+    state.milestone_time = -10.0;
 }
 
-fn check_ln_hold_integrity(state: &mut GameState, lane: usize, is_holding: bool, now: f32) {
-    for note in state.notes.iter_mut() {
-        if note.lane != lane { continue; }
-        if !note.is_ln { continue; }
-        if !note.ln_head_hit { continue; }
-        if note.ln_completed || note.ln_hold_broken { continue; }
-        
-        // Check if we're in the hold phase
-        if now >= note.start_time && now < note.end_time {
-            if !is_holding {
-                // HOLD BROKEN - released too early
+/// Count a judgment towards score, hit counts and combo, and show it
+fn record_judgment(state: &mut GameState, judgment: HitJudgment, now: f32) {
+    match judgment {
+        HitJudgment::Perfect => state.hit_counts.perfect += 1,
+        HitJudgment::Great => state.hit_counts.great += 1,
+        HitJudgment::Good => state.hit_counts.good += 1,
+        HitJudgment::Ok => state.hit_counts.ok += 1,
+        HitJudgment::Miss => state.hit_counts.miss += 1,
+    }
+    if judgment == HitJudgment::Miss {
+        break_combo(state, now);
+    } else {
+        add_combo(state, now);
+    }
+    state.score += judgment.score_value();
+    state.last_judgment = judgment.text();
+    state.judgment_color = judgment.color();
+    state.judgment_time = now;
+}
+
+/// osu!mania LN result: the head's judgment, lowered to whatever the average of the head offset
+/// and the (1.5x lenient) tail offset earns. A hold that was let go in the middle earns at most OK.
+fn combined_ln_judgment(head_diff: f32, tail_diff: f32, broken: bool) -> HitJudgment {
+    let head = HitJudgment::from_timing(head_diff.abs());
+    let average = HitJudgment::from_timing((head_diff.abs() + tail_diff.abs() / TAIL_LENIENCE) / 2.0);
+    let judgment = if average.score_value() < head.score_value() { average } else { head };
+    if broken && judgment.score_value() > HitJudgment::Ok.score_value() {
+        HitJudgment::Ok
+    } else {
+        judgment
+    }
+}
+
+/// Judge the held LN in `lane` as released `tail_diff` seconds before its end (combined scoring)
+fn complete_ln(state: &mut GameState, lane: usize, idx: usize, tail_diff: f32, now: f32) {
+    let note = &mut state.notes[idx];
+    let judgment = combined_ln_judgment(note.ln_head_offset, tail_diff, note.ln_hold_broken);
+    note.ln_tail_judgment = Some(judgment);
+    note.ln_completed = true;
+    state.holding[lane] = None;
+    
+    // Play hit sound for tail (a keysound only belongs to the head)
+    if let (Some(audio), None) = (&state.audio, note.keysound) {
+        audio.play_hit(note.hitsound_volume);
+    }
+    
+    record_judgment(state, judgment, now);
+    state.last_input_delay = tail_diff * 1000.0;
+}
+
+fn handle_key_release(state: &mut GameState, lane: usize, now: f32, ln_scoring: LnScoring) {
+    // Only the LN held in this lane can be released; with hold ticks the tail isn't judged
+    let Some(idx) = state.holding[lane] else { return; };
+    if ln_scoring == LnScoring::Ticks { return; }
+    let note = &state.notes[idx];
+    if note.ln_completed {
+        state.holding[lane] = None;
+        return;
+    }
+
+    // Check if we're within the tail's (lenient) timing window
+    let tail_timing_diff = note.end_time - now;
+    if tail_timing_diff.abs() < OK_WINDOW * TAIL_LENIENCE {
+        complete_ln(state, lane, idx, tail_timing_diff, now);
+    }
+}
+
+fn check_ln_hold_integrity(state: &mut GameState, lane: usize, is_holding: bool, now: f32, ln_scoring: LnScoring) {
+    let Some(idx) = state.holding[lane] else { return; };
+    let note = &state.notes[idx];
+    if note.ln_completed {
+        state.holding[lane] = None;
+        return;
+    }
+
+    if ln_scoring == LnScoring::Ticks {
+        // Every tick inside the body is worth combo if the key is down at that moment
+        let end_time = note.end_time;
+        loop {
+            let note = &mut state.notes[idx];
+            let tick_time = note.start_time + (note.ln_ticks + 1) as f32 * HOLD_TICK_INTERVAL;
+            if tick_time >= end_time || tick_time > now { break; }
+            note.ln_ticks += 1;
+            if is_holding {
+                state.score += HOLD_TICK_SCORE;
+                add_combo(state, now);
+            } else {
                 note.ln_hold_broken = true;
-                note.ln_completed = true;
-                note.ln_tail_judgment = Some(HitJudgment::Miss);
-                state.combo = 0;
-                state.last_judgment = "MISS";
-                state.judgment_color = RED;
-                state.judgment_time = now;
-                state.hit_counts.miss += 1;
+                break_combo(state, now);
             }
         }
+        if now >= end_time {
+            state.notes[idx].ln_completed = true;
+            state.holding[lane] = None;
+        }
+        return;
+    }
+
+    // Holding through the end counts as an on-time release
+    if now >= note.end_time {
+        if is_holding {
+            complete_ln(state, lane, idx, 0.0, now);
+        }
+        return;
+    }
+
+    // Letting go in the middle breaks combo; the hold can still be re-pressed and finished for an OK
+    if now >= note.start_time && !is_holding && !note.ln_hold_broken {
+        // Letting go to hit a note that overlaps the hold is fine while that note is in its window
+        let end_time = note.end_time;
+        advance_lane_head(state, lane);
+        let overlap_due = state.lane_notes[lane].get(state.lane_heads[lane]).is_some_and(|&next| {
+            let next = &state.notes[next];
+            next.start_time < end_time && (next.start_time - now).abs() < OK_WINDOW
+        });
+        if overlap_due { return; }
+
+        state.notes[idx].ln_hold_broken = true;
+        break_combo(state, now);
+    }
+}
+
+/// Whether a note needs no more judging or hold sound
+fn note_finished(note: &Note) -> bool {
+    if note.is_ln {
+        (note.ln_completed || note.missed) && !note.slider_sound_playing
+    } else {
+        note.hit || note.missed
     }
 }
 
-fn check_missed_notes(state: &mut GameState, now: f32) {
-    for note in state.notes.iter_mut() {
+/// Indices of the notes that can still be judged, sound or be seen this frame. Each lane's cursor
+/// moves past notes that are finished and scrolled more than `behind` below the hit position;
+/// the window ends at the first note more than `ahead` above it. Positions are scroll positions.
+fn active_notes(state: &mut GameState, now_pos: f32, behind: f32, ahead: f32) -> Vec<usize> {
+    let mut active = Vec::new();
+    for (lane, queue) in state.lane_notes.iter().enumerate() {
+        let cursor = &mut state.lane_cursors[lane];
+        while let Some(&idx) = queue.get(*cursor) {
+            let note = &state.notes[idx];
+            let last_pos = if note.is_ln { note.end_pos } else { note.start_pos };
+            if !note_finished(note) || last_pos >= now_pos - behind { break; }
+            *cursor += 1;
+        }
+        active.extend(queue[*cursor..].iter().copied().take_while(|&idx| state.notes[idx].start_pos < now_pos + ahead));
+    }
+    active
+}
+
+fn check_missed_notes(state: &mut GameState, active: &[usize], now: f32) {
+    for &idx in active {
+        let note = &mut state.notes[idx];
         if note.missed { continue; }
         
         if note.is_ln {
-            // Check if LN head was missed
+            // Check if LN head was missed; the whole LN is one judgment
             if !note.ln_head_hit && (note.start_time - now) < -OK_WINDOW {
                 note.missed = true;
                 note.ln_completed = true;
                 note.ln_head_judgment = Some(HitJudgment::Miss);
                 note.ln_tail_judgment = Some(HitJudgment::Miss);
-                state.combo = 0;
-                state.last_judgment = "MISS";
-                state.judgment_color = RED;
-                state.judgment_time = now;
-                state.hit_counts.miss += 2; // Both head and tail missed
+                record_judgment(state, HitJudgment::Miss, now);
             }
-            // Check if LN tail was missed (head was hit but never released)
-            else if note.ln_head_hit && !note.ln_completed && (note.end_time - now) < -OK_WINDOW {
+            // Check if LN tail was missed (let go early and never released in the tail window)
+            else if note.ln_head_hit && !note.ln_completed && (note.end_time - now) < -OK_WINDOW * TAIL_LENIENCE {
                 note.missed = true;
                 note.ln_completed = true;
                 note.ln_tail_judgment = Some(HitJudgment::Miss);
-                state.combo = 0;
-                state.last_judgment = "MISS";
-                state.judgment_color = RED;
-                state.judgment_time = now;
-                state.hit_counts.miss += 1; // Just tail missed
+                record_judgment(state, HitJudgment::Miss, now);
             }
         } else {
             // Regular note missed
             if !note.hit && (note.start_time - now) < -OK_WINDOW {
                 note.missed = true;
-                state.combo = 0;
+                break_combo(state, now);
                 state.last_judgment = "MISS";
                 state.judgment_color = RED;
                 state.judgment_time = now;
@@ -539,6 +702,43 @@ fn hsv_to_rgb(h: f32, s: f32, v: f32) -> (f32, f32, f32) {
     (r + m, g + m, b + m)
 }
 
+/// Live accuracy, grade and pace in the top right corner, each element optional
+fn draw_live_stats(state: &GameState, options: &GameOptions) {
+    let counts = &state.hit_counts;
+    // Nothing judged yet counts as a clean run
+    let accuracy = if counts.judged() == 0 { 100.0 } else { counts.accuracy() };
+    let best_possible = Grade::from_accuracy(counts.best_possible_accuracy(state.notes.len() as i32), counts.miss);
+    let right_x = screen_width() - 20.0;
+    let mut y = 40.0;
+    
+    if options.show_accuracy {
+        let acc_text = format!("{:.2}%", accuracy);
+        let acc_measure = measure_text(&acc_text, None, 36, 1.0);
+        draw_text(&acc_text, right_x - acc_measure.width, y, 36.0, WHITE);
+        y += 34.0;
+    }
+    
+    if options.show_grade {
+        let grade = Grade::from_accuracy(accuracy, counts.miss);
+        let grade_text = format!("{} (max {})", grade.name(), best_possible.name());
+        let grade_measure = measure_text(&grade_text, None, 26, 1.0);
+        draw_text(&grade_text, right_x - grade_measure.width, y, 26.0, grade.color());
+        y += 28.0;
+    }
+    
+    if options.show_pace {
+        let target = options.target_grade;
+        let (pace_text, pace_color) = if best_possible > target {
+            (format!("{} pace: out of reach", target.name()), RED)
+        } else {
+            let margin = accuracy - target.min_accuracy();
+            (format!("{} pace: {:+.2}%", target.name(), margin), if margin >= 0.0 { GREEN } else { ORANGE })
+        };
+        let pace_measure = measure_text(&pace_text, None, 22, 1.0);
+        draw_text(&pace_text, right_x - pace_measure.width, y, 22.0, pace_color);
+    }
+}
+
 fn draw_pause_menu(state: &GameState, options: &GameOptions) {
     // Darken background
     draw_rectangle(0.0, 0.0, screen_width(), screen_height(), Color::new(0.0, 0.0, 0.0, 0.7));
@@ -573,42 +773,16 @@ fn draw_pause_menu(state: &GameState, options: &GameOptions) {
 fn draw_results_screen(state: &GameState) {
     clear_background(BLACK);
     
-    let total_objects = (state.hit_counts.perfect + state.hit_counts.great + 
-                        state.hit_counts.good + state.hit_counts.ok + 
-                        state.hit_counts.miss) as f32;
-    
-    let accuracy = if total_objects > 0.0 {
-        let weighted_score = (300.0 * state.hit_counts.perfect as f32) +
-                            (200.0 * state.hit_counts.great as f32) +
-                            (100.0 * state.hit_counts.good as f32) +
-                            (50.0 * state.hit_counts.ok as f32);
-        let max_score = 300.0 * total_objects;
-        (weighted_score / max_score) * 100.0
-    } else {
-        0.0
-    };
-    
-    let (grade, grade_color) = if accuracy >= 100.0 && state.hit_counts.miss == 0 {
-        ("SS", Color::new(1.0, 0.9, 0.0, 1.0))
-    } else if accuracy >= 95.0 && state.hit_counts.miss == 0 {
-        ("S", Color::new(0.9, 0.9, 0.9, 1.0))
-    } else if accuracy >= 90.0 {
-        ("A", Color::new(0.2, 1.0, 0.3, 1.0))
-    } else if accuracy >= 80.0 {
-        ("B", Color::new(0.3, 0.8, 1.0, 1.0))
-    } else if accuracy >= 70.0 {
-        ("C", Color::new(0.9, 0.6, 0.2, 1.0))
-    } else {
-        ("D", Color::new(0.9, 0.3, 0.3, 1.0))
-    };
+    let accuracy = state.hit_counts.accuracy();
+    let grade = state.hit_counts.grade();
     
     let cx = screen_width() / 2.0;
     
     draw_text("RESULTS", cx - 100.0, 80.0, 50.0, WHITE);
     
     let grade_size = 120.0;
-    let grade_measure = measure_text(grade, None, grade_size as u16, 1.0);
-    draw_text(grade, cx - grade_measure.width / 2.0, 200.0, grade_size, grade_color);
+    let grade_measure = measure_text(grade.name(), None, grade_size as u16, 1.0);
+    draw_text(grade.name(), cx - grade_measure.width / 2.0, 200.0, grade_size, grade.color());
     
     let acc_text = format!("{:.2}%", accuracy);
     let acc_measure = measure_text(&acc_text, None, 40, 1.0);
@@ -618,6 +792,21 @@ fn draw_results_screen(state: &GameState) {
     let score_measure = measure_text(&score_text, None, 35, 1.0);
     draw_text(&score_text, cx - score_measure.width / 2.0, 300.0, 35.0, GRAY);
     
+    // Full combo: never dropped the combo; perfect FC: every judgment PERFECT as well
+    let full_combo = state.hit_counts.miss == 0 && state.combo_breaks.is_empty() && state.max_combo > 0;
+    let perfect = state.hit_counts.great + state.hit_counts.good + state.hit_counts.ok == 0;
+    let badge = match (full_combo, perfect) {
+        (true, true) => Some(("PFC", Color::new(1.0, 0.9, 0.0, 1.0))),
+        (true, false) => Some(("FC", Color::new(0.3, 0.8, 1.0, 1.0))),
+        _ => None,
+    };
+    if let Some((badge_text, badge_color)) = badge {
+        let badge_x = cx + grade_measure.width / 2.0 + 20.0;
+        draw_rectangle_lines(badge_x, 120.0, 90.0, 44.0, 3.0, badge_color);
+        let badge_measure = measure_text(badge_text, None, 36, 1.0);
+        draw_text(badge_text, badge_x + (90.0 - badge_measure.width) / 2.0, 154.0, 36.0, badge_color);
+    }
+    
     let y_start = 360.0;
     let spacing = 35.0;
     
@@ -626,6 +815,206 @@ fn draw_results_screen(state: &GameState) {
     draw_text(&format!("GOOD: {}", state.hit_counts.good), cx - 100.0, y_start + spacing * 2.0, 25.0, Color::new(0.3, 0.8, 1.0, 1.0));
     draw_text(&format!("OK: {}", state.hit_counts.ok), cx - 100.0, y_start + spacing * 3.0, 25.0, Color::new(0.7, 0.7, 0.7, 1.0));
     draw_text(&format!("MISS: {}", state.hit_counts.miss), cx - 100.0, y_start + spacing * 4.0, 25.0, RED);
+    let max_combo_text = format!("MAX COMBO: {}", state.max_combo);
+    draw_text(&max_combo_text, cx - 100.0, y_start + spacing * 5.0 + 10.0, 25.0, WHITE);
+    
+    // Where in the song the combo broke
+    let bar_w = 400.0;
+    let bar_y = y_start + spacing * 6.0;
+    draw_rectangle(cx - bar_w / 2.0, bar_y, bar_w, 6.0, Color::new(0.3, 0.3, 0.3, 1.0));
+    for &time in &state.combo_breaks {
+        let x = cx - bar_w / 2.0 + (time / state.song_duration).clamp(0.0, 1.0) * bar_w;
+        draw_rectangle(x - 1.0, bar_y - 4.0, 2.0, 14.0, RED);
+    }
+    let breaks_text = format!("Combo breaks: {}", state.combo_breaks.len());
+    draw_text(&breaks_text, cx - bar_w / 2.0, bar_y + 30.0, 20.0, GRAY);
     
     draw_text("Press ESC to return to song select", cx - 180.0, screen_height() - 40.0, 20.0, DARKGRAY);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{HitCounts, ScrollMap, TimingPoint};
+    use crate::parser::lane_queues;
+    use crate::storyboard::Storyboard;
+    use std::time::Instant;
+
+    const LANES: usize = 4;
+    const FRAME: f32 = 1.0 / 60.0;
+    // 1080p at the default scroll speed
+    const BEHIND: f32 = (1080.0 + 50.0) / 1400.0;
+    const AHEAD: f32 = (1080.0 + 1000.0) / 1400.0;
+
+    /// Dense stream with a long note every 8th note; scroll positions equal times (no SV)
+    fn chart(count: usize) -> Vec<Note> {
+        (0..count).map(|i| {
+            let start_time = i as f32 * 0.025;
+            let end_time = if i % 8 == 0 { start_time + 0.4 } else { 0.0 };
+            Note {
+                start_time,
+                end_time,
+                lane: i % LANES,
+                hit: false,
+                missed: false,
+                is_ln: end_time > 0.0,
+                ln_head_hit: false,
+                ln_hold_broken: false,
+                ln_completed: false,
+                ln_head_judgment: None,
+                ln_tail_judgment: None,
+                ln_head_offset: 0.0,
+                ln_ticks: 0,
+                slider_sound_playing: false,
+                hitsound_volume: 1.0,
+                keysound: None,
+                start_pos: start_time,
+                end_pos: end_time,
+            }
+        }).collect()
+    }
+
+    fn game_state(notes: Vec<Note>) -> GameState {
+        let song_duration = notes.last().map(|n| n.start_time + 1.0).unwrap_or(0.0);
+        GameState {
+            lane_notes: lane_queues(&notes, LANES),
+            notes,
+            score: 0,
+            combo: 0,
+            max_combo: 0,
+            combo_breaks: Vec::new(),
+            milestone_time: -10.0,
+            last_judgment: "",
+            judgment_color: WHITE,
+            judgment_time: -1.0,
+            start_time: Instant::now(),
+            key_count: LANES,
+            last_input_delay: 0.0,
+            hit_counts: HitCounts { perfect: 0, great: 0, good: 0, ok: 0, miss: 0 },
+            song_finished: false,
+            song_duration,
+            bg_texture: None,
+            song_name: String::new(),
+            paused: false,
+            pause_start: None,
+            total_pause_time: 0.0,
+            speed_change_time: -10.0,
+            speed_display_text: String::new(),
+            audio: None,
+            storyboard: Storyboard::default(),
+            scroll_map: ScrollMap::build(&[], false, false, 0.0),
+            beat_lines: Vec::new(),
+            sample_events: Vec::new(),
+            next_sample_event: 0,
+            lane_heads: vec![0; LANES],
+            holding: vec![None; LANES],
+            lane_cursors: vec![0; LANES],
+        }
+    }
+
+    #[test]
+    fn active_window_misses_every_unplayed_note() {
+        let mut state = game_state(chart(400));
+        let mut now = 0.0;
+        while now < state.song_duration {
+            let active = active_notes(&mut state, now, BEHIND, AHEAD);
+            assert!(active.len() < 200, "window should only hold nearby notes");
+            check_missed_notes(&mut state, &active, now);
+            now += FRAME;
+        }
+
+        assert!(state.notes.iter().all(|n| n.missed));
+        // A long note is a single judgment
+        assert_eq!(state.hit_counts.miss, 400);
+        assert!(active_notes(&mut state, now + BEHIND + 1.0, BEHIND, AHEAD).is_empty());
+    }
+
+    #[test]
+    fn released_and_repressed_long_note_is_capped_at_ok() {
+        // Note 0 is a long note in lane 0 from 0.0s to 0.4s
+        let mut state = game_state(chart(1));
+        handle_key_press(&mut state, 0, 0.0, true, LnScoring::Combined);
+        check_ln_hold_integrity(&mut state, 0, false, 0.1, LnScoring::Combined);
+        assert!(state.notes[0].ln_hold_broken);
+        assert_eq!(state.combo, 0);
+
+        check_ln_hold_integrity(&mut state, 0, true, 0.2, LnScoring::Combined);
+        handle_key_release(&mut state, 0, 0.4, LnScoring::Combined);
+        assert_eq!(state.notes[0].ln_tail_judgment, Some(HitJudgment::Ok));
+        assert_eq!((state.hit_counts.ok, state.hit_counts.miss), (1, 0));
+    }
+
+    #[test]
+    fn active_window_follows_scroll_velocity_changes() {
+        // Normal speed, a quarter speed from 2s, four times speed from 4s
+        let point = |time: f32, velocity_mult: f32, uninherited: bool| TimingPoint {
+            time, beat_length: 500.0, velocity_mult, meter: 4, sample_set: 0, sample_index: 0,
+            volume: 100.0, uninherited, effects: 0,
+        };
+        let points = [point(0.0, 1.0, true), point(2000.0, 0.25, false), point(4000.0, 4.0, false)];
+        let mut state = game_state(chart(240));
+        state.scroll_map = ScrollMap::build(&points, true, false, 6000.0);
+        for note in &mut state.notes {
+            note.start_pos = state.scroll_map.position_at(note.start_time);
+            note.end_pos = state.scroll_map.position_at(note.end_time.max(note.start_time));
+        }
+
+        let (mut slow_peak, mut fast_peak) = (0, 0);
+        let mut now = 0.0;
+        while now < state.song_duration {
+            let now_pos = state.scroll_map.position_at(now);
+            let active = active_notes(&mut state, now_pos, BEHIND, AHEAD);
+            // Everything still judgeable is in the window, and nothing past the top of the screen
+            for (idx, note) in state.notes.iter().enumerate() {
+                if !note.missed && (note.start_time - now).abs() <= OK_WINDOW {
+                    assert!(active.contains(&idx), "note {} missing from the window at {:.3}s", idx, now);
+                }
+            }
+            assert!(active.iter().all(|&idx| state.notes[idx].start_pos < now_pos + AHEAD));
+            if (2.5..3.5).contains(&now) { slow_peak = slow_peak.max(active.len()); }
+            if (4.5..5.0).contains(&now) { fast_peak = fast_peak.max(active.len()); }
+
+            check_missed_notes(&mut state, &active, now);
+            now += FRAME;
+        }
+
+        // Slow scrolling packs more notes on screen than fast scrolling
+        assert!(slow_peak > fast_peak * 4, "slow {} vs fast {}", slow_peak, fast_peak);
+        assert_eq!(state.hit_counts.miss, 240);
+        let end_pos = state.scroll_map.position_at(now) + BEHIND + 1.0;
+        assert!(active_notes(&mut state, end_pos, BEHIND, AHEAD).is_empty());
+        for (lane, queue) in state.lane_notes.iter().enumerate() {
+            assert_eq!(state.lane_cursors[lane], queue.len());
+        }
+    }
+    /// Per-frame cost of miss checking over a whole 20000 note chart, scanning every note vs the
+    /// per-lane window. Run with `cargo test --release frame_work -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_frame_work() {
+        let count = 20000;
+        let all: Vec<usize> = (0..count).collect();
+
+        let mut state = game_state(chart(count));
+        let frames = (state.song_duration / FRAME) as u32;
+        let timer = Instant::now();
+        for frame in 0..frames {
+            check_missed_notes(&mut state, &all, frame as f32 * FRAME);
+        }
+        let full_scan = timer.elapsed().as_secs_f64() / frames as f64;
+
+        let mut state = game_state(chart(count));
+        let timer = Instant::now();
+        for frame in 0..frames {
+            let now = frame as f32 * FRAME;
+            let active = active_notes(&mut state, now, BEHIND, AHEAD);
+            check_missed_notes(&mut state, &active, now);
+        }
+        let windowed = timer.elapsed().as_secs_f64() / frames as f64;
+
+        println!("{} notes, {} frames", count, frames);
+        println!("full scan: {:.2} us/frame", full_scan * 1e6);
+        println!("windowed:  {:.2} us/frame", windowed * 1e6);
+        assert!(windowed < full_scan);
+    }
+}
@@ -0,0 +1,435 @@
+use macroquad::prelude::*;
+use macroquad::ui::root_ui;
+use crate::parser::{self, ChartFormat};
+use crate::toast::Toasts;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const BEATMAPS_DIR: &str = "beatmaps";
+
+/// What identifies a set on disk: its online set ID (if any) and a hash of its chart files
+#[derive(Clone, Copy)]
+struct SetIdentity {
+    set_id: i32,
+    hash: u64,
+}
+
+/// Identities of the sets in `beatmaps/`, so an import only has to read and hash its own archive.
+/// An entry is recomputed when the chart files in its folder change name, size or modification time.
+#[derive(Default)]
+pub struct SetIndex {
+    entries: HashMap<PathBuf, (u64, SetIdentity)>, // folder -> (chart file stamp, identity)
+}
+
+impl SetIndex {
+    /// Every set folder in `root` with its identity
+    fn sets(&mut self, root: &Path) -> io::Result<Vec<(PathBuf, SetIdentity)>> {
+        let mut sets = Vec::new();
+        for entry in fs::read_dir(root)?.flatten() {
+            let folder = entry.path();
+            if !folder.is_dir() || entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let stamp = chart_stamp(&folder);
+            let identity = match self.entries.get(&folder) {
+                Some(&(cached, identity)) if cached == stamp => identity,
+                _ => {
+                    let identity = set_identity(&folder);
+                    self.entries.insert(folder.clone(), (stamp, identity));
+                    identity
+                }
+            };
+            sets.push((folder, identity));
+        }
+        self.entries.retain(|folder, _| folder.exists());
+        Ok(sets)
+    }
+
+    fn insert(&mut self, folder: PathBuf, identity: SetIdentity) {
+        let stamp = chart_stamp(&folder);
+        self.entries.insert(folder, (stamp, identity));
+    }
+}
+
+pub enum ImportOutcome {
+    Imported(String),
+    AlreadyImported(String),
+    /// Same set ID is already imported with different content
+    Conflict(PendingImport),
+}
+
+/// An extracted set waiting for the user to choose update or skip
+pub struct PendingImport {
+    temp_dir: PathBuf,
+    existing: PathBuf,
+    name: String,
+}
+
+impl PendingImport {
+    /// Replace the existing folder with the new version
+    pub fn update(self) -> Result<String, Box<dyn std::error::Error>> {
+        let old = temp_path(self.existing.parent().unwrap_or(Path::new(BEATMAPS_DIR)), "old", &self.name);
+        fs::rename(&self.existing, &old)?;
+        if let Err(e) = fs::rename(&self.temp_dir, &self.existing) {
+            let _ = fs::rename(&old, &self.existing);
+            let _ = fs::remove_dir_all(&self.temp_dir);
+            return Err(e.into());
+        }
+        let _ = fs::remove_dir_all(&old);
+        Ok(self.name)
+    }
+
+    pub fn skip(self) {
+        let _ = fs::remove_dir_all(&self.temp_dir);
+    }
+}
+
+/// True for archives `import_package` understands: osu! .osz and Quaver .qp
+pub fn is_package(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("osz") || e.eq_ignore_ascii_case("qp"))
+}
+
+/// Import an .osz or .qp into `beatmaps/`. The archive is extracted into a hidden temp folder
+/// first and only renamed into place once it is complete and valid.
+pub fn import_package(path: &Path, index: &mut SetIndex) -> Result<ImportOutcome, Box<dyn std::error::Error>> {
+    import_into(Path::new(BEATMAPS_DIR), path, index)
+}
+
+fn import_into(root: &Path, path: &Path, index: &mut SetIndex) -> Result<ImportOutcome, Box<dyn std::error::Error>> {
+    fs::create_dir_all(root)?;
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let temp_dir = temp_path(root, "import", &stem);
+
+    let result = extract_and_place(root, path, &temp_dir, &stem, index);
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+    result
+}
+
+fn extract_and_place(root: &Path, path: &Path, temp_dir: &Path, stem: &str, index: &mut SetIndex) -> Result<ImportOutcome, Box<dyn std::error::Error>> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path)?)?;
+
+    // Difficulties have to sit at the top of the archive to be found
+    let has_chart = archive.file_names().any(|n| !n.contains('/') && ChartFormat::of(Path::new(n)).is_some());
+    if !has_chart {
+        return Err("archive contains no .osu or .qua files".into());
+    }
+
+    fs::create_dir_all(temp_dir)?;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        // Reject absolute paths and `..` so nothing is written outside the set folder
+        let relative = match file.enclosed_name() {
+            Some(name) => name.to_path_buf(),
+            None => return Err(format!("unsafe path in archive: {}", file.name()).into()),
+        };
+        let out_path = temp_dir.join(relative);
+
+        if file.is_dir() {
+            fs::create_dir_all(&out_path)?;
+        } else {
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            io::copy(&mut file, &mut fs::File::create(&out_path)?)?;
+        }
+    }
+
+    let identity = set_identity(temp_dir);
+    let name = set_folder_name(temp_dir, identity.set_id).unwrap_or_else(|| sanitize(stem));
+
+    // Duplicates: identical content is skipped, same set ID with other content needs a decision
+    for (existing, other) in index.sets(root)? {
+        let existing_name = existing.file_name().unwrap_or_default().to_string_lossy().to_string();
+        if other.hash == identity.hash {
+            fs::remove_dir_all(temp_dir)?;
+            return Ok(ImportOutcome::AlreadyImported(existing_name));
+        }
+        if identity.set_id > 0 && other.set_id == identity.set_id {
+            return Ok(ImportOutcome::Conflict(PendingImport {
+                temp_dir: temp_dir.to_path_buf(),
+                existing,
+                name: existing_name,
+            }));
+        }
+    }
+
+    // A different set may already use the folder name
+    let mut target_name = name.clone();
+    let mut n = 2;
+    while root.join(&target_name).exists() {
+        target_name = format!("{} ({})", name, n);
+        n += 1;
+    }
+    let target = root.join(&target_name);
+    fs::rename(temp_dir, &target)?;
+    index.insert(target, identity);
+    Ok(ImportOutcome::Imported(target_name))
+}
+
+fn chart_files(folder: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(folder)
+        .map(|entries| entries.flatten().map(|e| e.path()).collect())
+        .unwrap_or_default();
+    files.retain(|p| ChartFormat::of(p).is_some());
+    files.sort();
+    files
+}
+
+/// Cheap change check for a set folder: chart file names, sizes and modification times
+fn chart_stamp(folder: &Path) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for path in chart_files(folder) {
+        path.hash(&mut hasher);
+        if let Ok(metadata) = fs::metadata(&path) {
+            metadata.len().hash(&mut hasher);
+            metadata.modified().ok().hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+fn set_identity(folder: &Path) -> SetIdentity {
+    let mut hasher = DefaultHasher::new();
+    let mut set_id = -1;
+    for path in chart_files(folder) {
+        if let Ok(content) = fs::read(&path) {
+            content.hash(&mut hasher);
+            if set_id <= 0 {
+                let text = String::from_utf8_lossy(&content);
+                set_id = parser::find_value(&text, "BeatmapSetID")
+                    .or_else(|| parser::find_value(&text, "MapSetId"))
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(-1);
+            }
+        }
+    }
+    SetIdentity { set_id, hash: hasher.finish() }
+}
+
+/// osu!-style folder name, `<set id> <artist> - <title>`
+fn set_folder_name(folder: &Path, set_id: i32) -> Option<String> {
+    let content = fs::read_to_string(chart_files(folder).first()?).ok()?;
+    // .qua values may be YAML-quoted
+    let value = |key: &str| parser::find_value(&content, key).map(|v| v.trim_matches(['\'', '"']).to_string());
+    let artist = value("Artist").unwrap_or_default();
+    let title = value("Title")?;
+    let name = if set_id > 0 {
+        format!("{} {} - {}", set_id, artist, title)
+    } else {
+        format!("{} - {}", artist, title)
+    };
+    Some(sanitize(&name))
+}
+
+fn sanitize(name: &str) -> String {
+    let cleaned: String = name.chars()
+        .map(|c| if c.is_control() || "<>:\"/\\|?*".contains(c) { '_' } else { c })
+        .collect();
+    let cleaned = cleaned.trim().trim_matches('.').to_string();
+    if cleaned.is_empty() { String::from("beatmap") } else { cleaned }
+}
+
+/// Hidden working folder inside `beatmaps/`, so the final rename stays on one filesystem
+fn temp_path(root: &Path, kind: &str, name: &str) -> PathBuf {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    root.join(format!(".{}-{}-{}", kind, nanos, sanitize(name)))
+}
+
+/// Remove working folders left behind when the game closed mid-import or with an update
+/// prompt still open. A `.old-*` folder whose set is missing was interrupted mid-update and
+/// is put back instead.
+fn clean_temp_folders() {
+    let Ok(entries) = fs::read_dir(BEATMAPS_DIR) else { return; };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        if name.starts_with(".import-") {
+            let _ = fs::remove_dir_all(&path);
+        } else if let Some(rest) = name.strip_prefix(".old-") {
+            let original = rest.split_once('-').map(|(_, set)| Path::new(BEATMAPS_DIR).join(set));
+            match original {
+                Some(original) if !original.exists() => {
+                    if let Err(e) = fs::rename(&path, &original) {
+                        eprintln!("Failed to restore {}: {}", original.display(), e);
+                    }
+                }
+                _ => {
+                    let _ = fs::remove_dir_all(&path);
+                }
+            }
+        }
+    }
+}
+
+/// Imports queued from the file dialog or drag and drop, one archive per frame
+/// so the progress banner gets drawn between them.
+pub struct ImportQueue {
+    queue: VecDeque<PathBuf>,
+    done: usize,
+    total: usize,
+    banner_shown: bool,
+    conflicts: Vec<PendingImport>,
+    changed: bool,
+}
+
+impl ImportQueue {
+    pub fn new() -> Self {
+        clean_temp_folders();
+        Self {
+            queue: VecDeque::new(),
+            done: 0,
+            total: 0,
+            banner_shown: false,
+            conflicts: Vec::new(),
+            changed: false,
+        }
+    }
+
+    pub fn add(&mut self, path: PathBuf) {
+        if self.queue.is_empty() {
+            self.done = 0;
+            self.total = 0;
+        }
+        self.queue.push_back(path);
+        self.total += 1;
+    }
+
+    /// True once after a set was added or replaced, so the library can rescan
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    pub fn update(&mut self, index: &mut SetIndex, toasts: &mut Toasts) {
+        if !self.banner_shown {
+            return;
+        }
+        let Some(path) = self.queue.pop_front() else { return; };
+        self.banner_shown = false;
+        self.done += 1;
+
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        match import_package(&path, index) {
+            Ok(ImportOutcome::Imported(name)) => {
+                toasts.success(format!("Imported {}", name));
+                self.changed = true;
+            }
+            Ok(ImportOutcome::AlreadyImported(name)) => toasts.success(format!("{} is already imported", name)),
+            Ok(ImportOutcome::Conflict(pending)) => self.conflicts.push(pending),
+            Err(e) => toasts.error(format!("Failed to import {}: {}", file_name, e)),
+        }
+    }
+
+    /// Progress banner and the update-or-skip prompt for conflicting sets
+    pub fn draw(&mut self, toasts: &mut Toasts) {
+        if let Some(path) = self.queue.front() {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let text = format!("IMPORTING {}/{}: {}", self.done + 1, self.total, name);
+            draw_rectangle(0.0, 0.0, screen_width(), 30.0, Color::new(0.1, 0.1, 0.1, 0.9));
+            draw_text(text, 20.0, 21.0, 20.0, YELLOW);
+            self.banner_shown = true;
+        }
+
+        let Some(pending) = self.conflicts.first() else { return; };
+        let (w, h) = (460.0, 140.0);
+        let x = (screen_width() - w) / 2.0;
+        let y = (screen_height() - h) / 2.0;
+        draw_rectangle(0.0, 0.0, screen_width(), screen_height(), Color::new(0.0, 0.0, 0.0, 0.6));
+        draw_rectangle(x, y, w, h, Color::new(0.15, 0.15, 0.15, 1.0));
+        draw_text("SET ALREADY IMPORTED", x + 20.0, y + 35.0, 25.0, SKYBLUE);
+        draw_text(pending.name.as_str(), x + 20.0, y + 62.0, 20.0, WHITE);
+        draw_text("has a different version on disk.", x + 20.0, y + 84.0, 20.0, GRAY);
+
+        if root_ui().button(vec2(x + 20.0, y + 100.0), "UPDATE") {
+            let pending = self.conflicts.remove(0);
+            match pending.update() {
+                Ok(name) => {
+                    toasts.success(format!("Updated {}", name));
+                    self.changed = true;
+                }
+                Err(e) => toasts.error(format!("Failed to update set: {}", e)),
+            }
+        } else if root_ui().button(vec2(x + 100.0, y + 100.0), "SKIP") {
+            self.conflicts.remove(0).skip();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const QUA: &str = "AudioFile: audio.mp3\nMapSetId: 77\nMode: Keys4\nTitle: Song\nArtist: Someone\n\
+        DifficultyName: Easy\nTimingPoints:\n- Bpm: 120\nHitObjects:\n- Lane: 1\n";
+
+    /// Empty scratch folder under the system temp dir
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mania-import-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_package(path: &Path, files: &[(&str, &str)]) {
+        let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+        for (name, content) in files {
+            zip.start_file(*name, zip::write::FileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn qp_import_places_dedupes_and_detects_updates() {
+        let dir = scratch_dir("qp");
+        let root = dir.join("beatmaps");
+        let mut index = SetIndex::default();
+
+        let package = dir.join("song.qp");
+        write_package(&package, &[("easy.qua", QUA), ("audio.mp3", "not really audio")]);
+        let imported = match import_into(&root, &package, &mut index).unwrap() {
+            ImportOutcome::Imported(name) => name,
+            _ => panic!("expected a fresh import"),
+        };
+        assert_eq!(imported, "77 Someone - Song");
+        assert!(root.join(&imported).join("easy.qua").is_file());
+
+        assert!(matches!(import_into(&root, &package, &mut index).unwrap(), ImportOutcome::AlreadyImported(name) if name == imported));
+
+        // Same set ID with another difficulty added is an update, left pending until the user decides
+        let updated = dir.join("song-v2.qp");
+        let hard = QUA.replace("Easy", "Hard");
+        write_package(&updated, &[("easy.qua", QUA), ("hard.qua", &hard), ("audio.mp3", "not really audio")]);
+        match import_into(&root, &updated, &mut index).unwrap() {
+            ImportOutcome::Conflict(pending) => assert_eq!(pending.update().unwrap(), imported),
+            _ => panic!("expected a conflict"),
+        }
+        assert!(root.join(&imported).join("hard.qua").is_file());
+        // Nothing but the set is left behind
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn packages_without_top_level_charts_are_rejected() {
+        let dir = scratch_dir("nested");
+        let root = dir.join("beatmaps");
+        let package = dir.join("nested.qp");
+        write_package(&package, &[("inner/easy.qua", QUA)]);
+
+        assert!(import_into(&root, &package, &mut SetIndex::default()).is_err());
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 0);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
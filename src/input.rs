@@ -0,0 +1,378 @@
+use gilrs::{Button, EventType, Gilrs};
+use macroquad::input::utils::{register_input_subscriber, repeat_all_miniquad_input};
+use macroquad::miniquad::{EventHandler, KeyCode, KeyMods};
+use midir::{MidiInput, MidiInputConnection};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant, SystemTime};
+
+// How far before a window key event its raw kernel event may be and still be matched to it
+const RAW_KEY_SLACK: Duration = Duration::from_millis(50);
+
+/// Gamepad buttons that can be bound, in the order the config names them
+pub const PAD_BUTTONS: [Button; 19] = [
+    Button::South, Button::East, Button::North, Button::West, Button::C, Button::Z,
+    Button::LeftTrigger, Button::LeftTrigger2, Button::RightTrigger, Button::RightTrigger2,
+    Button::Select, Button::Start, Button::Mode, Button::LeftThumb, Button::RightThumb,
+    Button::DPadUp, Button::DPadDown, Button::DPadLeft, Button::DPadRight,
+];
+
+/// Something a lane can be bound to. Gamepad buttons and MIDI notes match on any
+/// connected pad or MIDI port/channel.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Binding {
+    Key(KeyCode),
+    Pad(Button),
+    Midi(u8), // note number
+}
+
+impl Binding {
+    pub fn name(&self) -> String {
+        match self {
+            Binding::Key(key) => format!("{:?}", key),
+            Binding::Pad(button) => format!("Pad {:?}", button),
+            Binding::Midi(note) => format!("MIDI {}", note),
+        }
+    }
+}
+
+/// A binding going down or up, stamped with when it happened
+pub struct InputEvent {
+    pub binding: Binding,
+    pub pressed: bool,
+    pub time: Instant,
+}
+
+/// Every press and release from the keyboard, gamepads and MIDI devices since the last poll,
+/// in the order they happened. Unlike `is_key_pressed`, two taps of the same key within one
+/// frame are both kept, and a release is never reordered before the press it follows.
+///
+/// Timestamps: MIDI messages are stamped on midir's own thread as they arrive, gamepad events
+/// carry the time gilrs read them. Keyboard events come from the window (so they follow focus
+/// and the keyboard layout), but on Linux they take the kernel's timestamp of the matching raw
+/// evdev event, read on threads of their own. Without access to /dev/input, or on other
+/// platforms, they are stamped when the frame picks them up.
+pub struct InputQueue {
+    subscriber: usize,
+    keyboard: Vec<InputEvent>,
+    raw_keys: Receiver<InputEvent>,
+    raw_pending: Vec<InputEvent>, // raw key events not matched to a window event yet
+    gilrs: Option<Gilrs>,
+    // Kept alive to keep receiving; MIDI devices are picked up at startup
+    _midi_connections: Vec<MidiInputConnection<()>>,
+    midi_events: Receiver<InputEvent>,
+    events: Vec<InputEvent>,
+    held: Vec<Binding>, // gamepad buttons and MIDI notes currently down
+}
+
+impl InputQueue {
+    pub fn new() -> Self {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                eprintln!("Gamepad input unavailable: {}", e);
+                None
+            }
+        };
+        let (tx, rx) = mpsc::channel();
+        let (raw_tx, raw_rx) = mpsc::channel();
+        connect_raw_keyboards(raw_tx);
+
+        Self {
+            subscriber: register_input_subscriber(),
+            keyboard: Vec::new(),
+            raw_keys: raw_rx,
+            raw_pending: Vec::new(),
+            gilrs,
+            _midi_connections: connect_midi(tx),
+            midi_events: rx,
+            events: Vec::new(),
+            held: Vec::new(),
+        }
+    }
+
+    /// Collect the events received since the last call. Call once per frame, before anything
+    /// else, or events pile up (and get stale) while a scene isn't reading them.
+    pub fn poll(&mut self) {
+        let subscriber = self.subscriber;
+        repeat_all_miniquad_input(self, subscriber);
+        let mut events = std::mem::take(&mut self.keyboard);
+        self.raw_pending.extend(self.raw_keys.try_iter());
+        restamp_keys(&mut events, &mut self.raw_pending, Instant::now());
+
+        if let Some(gilrs) = &mut self.gilrs {
+            while let Some(event) = gilrs.next_event() {
+                let age = SystemTime::now().duration_since(event.time).unwrap_or_default();
+                let time = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+                let (button, pressed) = match event.event {
+                    EventType::ButtonPressed(button, _) => (button, true),
+                    EventType::ButtonReleased(button, _) => (button, false),
+                    EventType::Disconnected => {
+                        // An unplugged pad sends no releases; let go of what no other pad holds
+                        let still_down: Vec<Button> = gilrs.gamepads()
+                            .flat_map(|(_, pad)| PAD_BUTTONS.into_iter().filter(move |b| pad.is_pressed(*b)))
+                            .collect();
+                        for binding in &self.held {
+                            if matches!(binding, Binding::Pad(b) if !still_down.contains(b)) {
+                                events.push(InputEvent { binding: *binding, pressed: false, time });
+                            }
+                        }
+                        continue;
+                    }
+                    _ => continue,
+                };
+                if button == Button::Unknown {
+                    continue;
+                }
+                events.push(InputEvent { binding: Binding::Pad(button), pressed, time });
+            }
+        }
+        events.extend(self.midi_events.try_iter());
+        events.sort_by_key(|e| e.time);
+
+        for event in &events {
+            if matches!(event.binding, Binding::Key(_)) {
+                continue;
+            }
+            self.held.retain(|b| *b != event.binding);
+            if event.pressed {
+                self.held.push(event.binding);
+            }
+        }
+        self.events = events;
+    }
+
+    /// This frame's events, oldest first
+    pub fn events(&self) -> &[InputEvent] {
+        &self.events
+    }
+
+    pub fn is_down(&self, binding: &Binding) -> bool {
+        match binding {
+            // macroquad also clears keys when the window loses focus
+            Binding::Key(key) => macroquad::input::is_key_down(*key),
+            _ => self.held.contains(binding),
+        }
+    }
+}
+
+impl EventHandler for InputQueue {
+    fn update(&mut self) {}
+
+    fn draw(&mut self) {}
+
+    fn key_down_event(&mut self, key: KeyCode, _mods: KeyMods, repeat: bool) {
+        // Auto-repeat is not a new press. Stamped for now, `restamp_keys` puts in the real time
+        if !repeat {
+            self.keyboard.push(InputEvent { binding: Binding::Key(key), pressed: true, time: Instant::now() });
+        }
+    }
+
+    fn key_up_event(&mut self, key: KeyCode, _mods: KeyMods) {
+        self.keyboard.push(InputEvent { binding: Binding::Key(key), pressed: false, time: Instant::now() });
+    }
+}
+
+/// Give window key events the time of their raw counterpart: the oldest unmatched raw event
+/// for the same key and direction. Raw events nobody claimed (typed into another window) are
+/// dropped once they're older than `RAW_KEY_SLACK`.
+fn restamp_keys(events: &mut [InputEvent], raw: &mut Vec<InputEvent>, now: Instant) {
+    for event in events.iter_mut() {
+        let matching = raw.iter().position(|r| {
+            r.binding == event.binding && r.pressed == event.pressed && r.time + RAW_KEY_SLACK >= event.time
+        });
+        if let Some(i) = matching {
+            event.time = raw.remove(i).time.min(event.time);
+        }
+    }
+    raw.retain(|r| r.time + RAW_KEY_SLACK >= now);
+}
+
+#[cfg(target_os = "linux")]
+fn connect_raw_keyboards(tx: Sender<InputEvent>) {
+    if evdev::connect(tx) == 0 {
+        eprintln!("No readable keyboard in /dev/input, key presses are timed per frame");
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect_raw_keyboards(_tx: Sender<InputEvent>) {}
+
+/// Raw key events straight from the kernel's input devices, stamped when the key changed rather
+/// than when the window system got around to it. Keyboards are picked up at startup.
+#[cfg(target_os = "linux")]
+mod evdev {
+    use super::{Binding, InputEvent};
+    use macroquad::miniquad::KeyCode;
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::unix::io::AsRawFd;
+    use std::sync::mpsc::Sender;
+    use std::time::{Duration, Instant};
+
+    const EV_KEY: u16 = 1;
+    const KEY_A: usize = 30;
+    const KEY_SPACE: usize = 57;
+    const KEY_BITS: usize = 96; // KEY_MAX (0x2ff) bits
+    // _IOC(_IOC_READ, 'E', 0x20 + EV_KEY, KEY_BITS): which keys a device has
+    const EVIOCGBIT_KEY: u64 = (2 << 30) | ((KEY_BITS as u64) << 16) | (0x45 << 8) | (0x20 + EV_KEY as u64);
+    // _IOW('E', 0xa0, int): stamp events with CLOCK_MONOTONIC, the clock `Instant` uses
+    const EVIOCSCLOCKID: u64 = (1 << 30) | (4 << 16) | (0x45 << 8) | 0xa0;
+
+    /// Start a reader thread per keyboard; returns how many were found
+    pub fn connect(tx: Sender<InputEvent>) -> usize {
+        let Ok(entries) = std::fs::read_dir("/dev/input") else { return 0 };
+        let mut count = 0;
+        for entry in entries.flatten() {
+            if !entry.file_name().to_string_lossy().starts_with("event") {
+                continue;
+            }
+            // Usually needs the `input` group
+            let Ok(file) = File::open(entry.path()) else { continue };
+            if !is_keyboard(&file) {
+                continue;
+            }
+            let clock: libc::c_int = libc::CLOCK_MONOTONIC;
+            unsafe { libc::ioctl(file.as_raw_fd(), EVIOCSCLOCKID as _, &clock) };
+            let tx = tx.clone();
+            std::thread::spawn(move || read_keys(file, tx));
+            count += 1;
+        }
+        count
+    }
+
+    /// Has letters and a space bar, unlike power buttons and mice that also report EV_KEY
+    fn is_keyboard(file: &File) -> bool {
+        let mut bits = [0u8; KEY_BITS];
+        let result = unsafe { libc::ioctl(file.as_raw_fd(), EVIOCGBIT_KEY as _, bits.as_mut_ptr()) };
+        let has = |key: usize| bits[key / 8] & (1 << (key % 8)) != 0;
+        result >= 0 && has(KEY_A) && has(KEY_SPACE)
+    }
+
+    fn read_keys(mut file: File, tx: Sender<InputEvent>) {
+        let mut buffer = [0u8; std::mem::size_of::<libc::input_event>()];
+        // Ends when the device goes away or the queue is dropped
+        while file.read_exact(&mut buffer).is_ok() {
+            let event: libc::input_event = unsafe { std::ptr::read_unaligned(buffer.as_ptr().cast()) };
+            // 0 up, 1 down, 2 auto-repeat
+            if event.type_ != EV_KEY || event.value > 1 {
+                continue;
+            }
+            let Some(key) = key_code(event.code) else { continue };
+            let time = Instant::now().checked_sub(age(&event.time)).unwrap_or_else(Instant::now);
+            if tx.send(InputEvent { binding: Binding::Key(key), pressed: event.value == 1, time }).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// How long ago a CLOCK_MONOTONIC timestamp was
+    fn age(time: &libc::timeval) -> Duration {
+        let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+        unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+        let now = Duration::new(now.tv_sec as u64, now.tv_nsec as u32);
+        now.saturating_sub(Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000))
+    }
+
+    /// Linux key codes (physical, US layout) of the keys lanes can be bound to
+    fn key_code(code: u16) -> Option<KeyCode> {
+        use KeyCode::*;
+        const ROW_1: [KeyCode; 10] = [Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0];
+        const ROW_Q: [KeyCode; 12] = [Q, W, E, R, T, Y, U, I, O, P, LeftBracket, RightBracket];
+        const ROW_A: [KeyCode; 11] = [A, S, D, F, G, H, J, K, L, Semicolon, Apostrophe];
+        const ROW_Z: [KeyCode; 10] = [Z, X, C, V, B, N, M, Comma, Period, Slash];
+        let code = code as usize;
+        match code {
+            2..=11 => Some(ROW_1[code - 2]),
+            16..=27 => Some(ROW_Q[code - 16]),
+            29 => Some(LeftControl),
+            30..=40 => Some(ROW_A[code - 30]),
+            42 => Some(LeftShift),
+            44..=53 => Some(ROW_Z[code - 44]),
+            54 => Some(RightShift),
+            57 => Some(Space),
+            97 => Some(RightControl),
+            _ => None,
+        }
+    }
+}
+
+/// Listen on every MIDI input port. Note-on/off messages become events on `tx`, stamped on
+/// midir's thread as they arrive.
+fn connect_midi(tx: Sender<InputEvent>) -> Vec<MidiInputConnection<()>> {
+    let ports = match MidiInput::new("Rustania") {
+        Ok(probe) => probe.ports(),
+        Err(e) => {
+            eprintln!("MIDI input unavailable: {}", e);
+            return Vec::new();
+        }
+    };
+
+    ports.iter()
+        .filter_map(|port| {
+            // Each connection consumes its own client
+            let client = MidiInput::new("Rustania").ok()?;
+            let tx = tx.clone();
+            let connection = client.connect(port, "Rustania input", move |_, message, _| {
+                if let Some((note, pressed)) = midi_note(message) {
+                    let _ = tx.send(InputEvent { binding: Binding::Midi(note), pressed, time: Instant::now() });
+                }
+            }, ());
+            match connection {
+                Ok(connection) => Some(connection),
+                Err(e) => {
+                    eprintln!("Failed to open MIDI port: {}", e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// (note, pressed) for note-on/note-off messages; a note-on with velocity 0 is a note-off
+fn midi_note(message: &[u8]) -> Option<(u8, bool)> {
+    match *message {
+        [status, note, velocity, ..] if status & 0xF0 == 0x90 => Some((note, velocity > 0)),
+        [status, note, _, ..] if status & 0xF0 == 0x80 => Some((note, false)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: KeyCode, pressed: bool, time: Instant) -> InputEvent {
+        InputEvent { binding: Binding::Key(key), pressed, time }
+    }
+
+    #[test]
+    fn window_keys_take_their_raw_timestamps() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        // Two taps of D within one 16ms frame, all picked up at the next poll
+        let mut events = vec![key(KeyCode::D, true, at(16)), key(KeyCode::D, false, at(16)), key(KeyCode::D, true, at(16))];
+        let mut raw = vec![key(KeyCode::D, true, at(2)), key(KeyCode::D, false, at(7)), key(KeyCode::D, true, at(12))];
+        restamp_keys(&mut events, &mut raw, at(16));
+        let times: Vec<Instant> = events.iter().map(|e| e.time).collect();
+        assert_eq!(times, vec![at(2), at(7), at(12)]);
+        assert!(raw.is_empty());
+    }
+
+    #[test]
+    fn unmatched_keys_keep_their_poll_time() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        // F typed into another window long ago, J has no raw event (no /dev/input access)
+        let mut raw = vec![key(KeyCode::F, true, at(0))];
+        let mut events = vec![key(KeyCode::J, true, at(500))];
+        restamp_keys(&mut events, &mut raw, at(500));
+        assert_eq!(events[0].time, at(500));
+        assert!(raw.is_empty());
+
+        // A stale raw press is never matched to a new window press
+        let mut raw = vec![key(KeyCode::F, true, at(0))];
+        let mut events = vec![key(KeyCode::F, true, at(500))];
+        restamp_keys(&mut events, &mut raw, at(500));
+        assert_eq!(events[0].time, at(500));
+    }
+}
@@ -0,0 +1,137 @@
+use crate::import::{self, ImportQueue, SetIndex};
+use crate::models::GameOptions;
+use macroquad::prelude::get_time;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const WATCH_POLL_INTERVAL: f64 = 2.0; // seconds
+
+/// Size and modification time of a watched package
+#[derive(Clone, Copy, PartialEq)]
+struct FileStamp {
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+pub struct BeatmapSet {
+    pub path: PathBuf,
+    pub name: String,
+    pub external: bool, // lives in the osu! Songs folder rather than `beatmaps/`
+}
+
+/// Beatmap sets from `beatmaps/` and the optional osu! Songs folder, plus the watched downloads folder
+pub struct Library {
+    pub sets: Vec<BeatmapSet>,
+    pub index: SetIndex, // identities of the `beatmaps/` sets, for import dedupe
+    watching: String,
+    watched_files: HashMap<PathBuf, (FileStamp, bool)>, // package -> (stamp at the last poll, queued)
+    last_poll: f64,
+}
+
+impl Library {
+    pub fn new(options: &GameOptions) -> Self {
+        let mut library = Self {
+            sets: Vec::new(),
+            index: SetIndex::default(),
+            watching: String::new(),
+            watched_files: HashMap::new(),
+            last_poll: 0.0,
+        };
+        library.refresh(options);
+        library
+    }
+
+    /// Rescan both library folders
+    pub fn refresh(&mut self, options: &GameOptions) {
+        let mut sets = Vec::new();
+
+        if let Ok(entries) = fs::read_dir("beatmaps") {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                // Dot folders are unfinished imports
+                if entry.path().is_dir() && !name.starts_with('.') {
+                    sets.push(BeatmapSet { path: entry.path(), name, external: false });
+                }
+            }
+        }
+
+        // The Songs folder also holds loose files and empty leftovers; only list folders with maps
+        if !options.songs_dir.is_empty() {
+            match fs::read_dir(&options.songs_dir) {
+                Ok(entries) => {
+                    for entry in entries.flatten() {
+                        let path = entry.path();
+                        if path.is_dir() && contains_osu(&path) {
+                            let name = entry.file_name().to_string_lossy().to_string();
+                            sets.push(BeatmapSet { path, name, external: true });
+                        }
+                    }
+                }
+                Err(e) => eprintln!("Failed to read Songs folder {}: {}", options.songs_dir, e),
+            }
+        }
+
+        sets.sort_by_key(|s| s.name.to_lowercase());
+        self.sets = sets;
+    }
+
+    /// Queue .osz/.qp files that appeared or changed in the watched folder. A file is only picked
+    /// up once its size and modification time stayed the same between two polls, so downloads in
+    /// progress are left alone. Deleted files are forgotten, so downloading one again re-imports it.
+    pub fn poll_watch_folder(&mut self, options: &GameOptions, imports: &mut ImportQueue) {
+        if options.watch_dir != self.watching {
+            // Whatever is in a newly chosen folder already is not "new"
+            self.watching = options.watch_dir.clone();
+            self.watched_files = list_packages(&self.watching).into_iter().map(|(p, stamp)| (p, (stamp, true))).collect();
+            self.last_poll = get_time();
+            return;
+        }
+        if self.watching.is_empty() || get_time() - self.last_poll < WATCH_POLL_INTERVAL {
+            return;
+        }
+        self.last_poll = get_time();
+
+        let packages = list_packages(&self.watching);
+        self.watched_files.retain(|path, _| packages.iter().any(|(p, _)| p == path));
+
+        for (path, stamp) in packages {
+            match self.watched_files.get(&path) {
+                Some(&(last, true)) if last == stamp => {}
+                Some(&(last, false)) if last == stamp => {
+                    imports.add(path.clone());
+                    self.watched_files.insert(path, (stamp, true));
+                }
+                _ => {
+                    self.watched_files.insert(path, (stamp, false));
+                }
+            }
+        }
+    }
+}
+
+fn contains_osu(folder: &Path) -> bool {
+    fs::read_dir(folder)
+        .map(|entries| entries.flatten().any(|e| {
+            e.path().extension().and_then(|x| x.to_str()).is_some_and(|x| x.eq_ignore_ascii_case("osu"))
+        }))
+        .unwrap_or(false)
+}
+
+fn list_packages(folder: &str) -> Vec<(PathBuf, FileStamp)> {
+    if folder.is_empty() {
+        return Vec::new();
+    }
+    fs::read_dir(folder)
+        .map(|entries| {
+            entries.flatten()
+                .filter(|e| import::is_package(&e.path()))
+                .filter_map(|e| {
+                    let metadata = e.metadata().ok()?;
+                    Some((e.path(), FileStamp { size: metadata.len(), modified: metadata.modified().ok() }))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
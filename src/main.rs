@@ -5,20 +5,44 @@ mod parser;
 mod game;
 mod discord_rpc;
 mod audio;
+mod volume;
+mod storyboard;
+mod editor;
+mod writer;
+mod import;
+mod toast;
+mod library;
+mod stepmania;
+mod quaver;
+mod bms;
+mod convert;
+mod input;
 
 use macroquad::prelude::*;
 use macroquad::ui::root_ui;
-use rodio::{OutputStream, Sink};
+use rodio::Sink;
 use std::fs;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use models::GameOptions;
 
-#[macroquad::main("Rustania")]
+fn window_conf() -> Conf {
+    // Without vsync, frames come as fast as the machine allows
+    let uncapped = GameOptions::load().unwrap_or_default().uncapped_fps;
+    Conf {
+        window_title: "Rustania".to_owned(),
+        platform: miniquad::conf::Platform {
+            swap_interval: if uncapped { Some(0) } else { None },
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[macroquad::main(window_conf)]
 async fn main() {
     let mut scene = "Menu";
     let mut state: Option<models::GameState> = None;
-    let (_stream, stream_handle) = OutputStream::try_default().unwrap();
     let audio_sink: Arc<Mutex<Option<Sink>>> = Arc::new(Mutex::new(None));
     let mut selected_beatmap: Option<PathBuf> = None;
     let mut difficulties: Vec<parser::BeatmapInfo> = Vec::new();
@@ -31,12 +55,58 @@ async fn main() {
     // Load saved options or use defaults
     let mut options = GameOptions::load().unwrap_or_default();
     
-    // Key remapping state
-    let mut remapping_mode: Option<(usize, bool)> = None; // (key_index, is_2k_mode)
+    // Falls back to the default device, then to silence, instead of panicking
+    let mut audio_output = audio::AudioOutput::open(&options.audio_device);
+    let mut audio_devices: Vec<String> = Vec::new();
+    
+    // Binding capture state
+    let mut remapping_mode: Option<(usize, usize)> = None; // (lane_index, key mode)
+    let mut options_tab = "Gameplay";
+    
+    let mut volume_overlay = volume::VolumeOverlay::new();
+    let mut editor: Option<editor::Editor> = None;
+    let mut toasts = toast::Toasts::new();
+    let mut imports = import::ImportQueue::new();
+    let mut library = library::Library::new(&options);
+    let mut library_scroll: usize = 0;
+    let mut input = input::InputQueue::new();
 
     loop {
+        // Drained every frame so gameplay never sees presses from another scene
+        input.poll();
         clear_background(BLACK);
         
+        // ALT + wheel volume control works in every scene
+        if volume_overlay.update(&mut options) {
+            let _ = options.save();
+            if let Some(audio) = state.as_ref().and_then(|s| s.audio.as_ref()) {
+                audio.set_volume(options.effects_gain());
+            }
+        }
+        if let Ok(sink_lock) = audio_sink.lock() {
+            if let Some(sink) = sink_lock.as_ref() {
+                sink.set_volume(options.music_gain());
+            }
+        }
+
+        // .osz/.qp files can be dropped onto the window anywhere outside gameplay
+        for file in get_dropped_files() {
+            let Some(path) = file.path else { continue; };
+            if scene == "Playing" {
+                continue;
+            }
+            if import::is_package(&path) {
+                imports.add(path);
+            } else {
+                toasts.error(format!("Not an .osz or .qp file: {}", path.display()));
+            }
+        }
+        library.poll_watch_folder(&options, &mut imports);
+        imports.update(&mut library.index, &mut toasts);
+        if imports.take_changed() {
+            library.refresh(&options);
+        }
+        
         // Handle pause/resume for audio during gameplay
         if scene == "Playing" {
             if let Some(ref mut s) = state {
@@ -66,34 +136,53 @@ async fn main() {
                 if root_ui().button(vec2(100.0, 125.0), "4K") {
                     key_mode = 4;
                 }
+                // 7K+1, the BMS layout: scratch lane plus seven keys
+                if root_ui().button(vec2(160.0, 125.0), "8K") {
+                    key_mode = 8;
+                }
                 
                 if root_ui().button(vec2(40.0, 170.0), "OPTIONS") {
+                    audio_devices = audio::AudioOutput::list_devices();
                     scene = "Options";
                 }
                 
-                if root_ui().button(vec2(40.0, 210.0), "IMPORT .OSZ FILE") {
-                    if let Some(path) = rfd::FileDialog::new().add_filter("osu", &["osz"]).pick_file() {
-                        let _ = parser::import_osz(path);
+                if root_ui().button(vec2(40.0, 210.0), "IMPORT .OSZ/.QP FILES") {
+                    for path in rfd::FileDialog::new().add_filter("Beatmap packages", &["osz", "qp"]).pick_files().unwrap_or_default() {
+                        imports.add(path);
                     }
                 }
 
-                draw_text("BEATMAPS:", 40.0, 270.0, 25.0, GRAY);
-                
-                if let Ok(entries) = fs::read_dir("beatmaps") {
-                    for (i, entry) in entries.flatten().enumerate() {
-                        let name = entry.file_name().into_string().unwrap_or_default();
-                        let truncated = if name.len() > 35 {
-                            format!("{}...", &name[..32])
-                        } else {
-                            name.clone()
-                        };
-                        
-                        if root_ui().button(vec2(40.0, 300.0 + (i as f32 * 40.0)), truncated.as_str()) {
-                            selected_beatmap = Some(entry.path());
-                            if let Ok(diffs) = parser::get_difficulties(&entry.path()) {
-                                difficulties = diffs;
-                                scene = "DiffSelect";
-                            }
+                draw_text(format!("BEATMAPS ({}):", library.sets.len()), 40.0, 270.0, 25.0, GRAY);
+
+                // The list scrolls with the mouse wheel (ALT + wheel is volume)
+                let visible_rows = (((screen_height() - 300.0) / 40.0).floor() as usize).max(1);
+                let max_scroll = library.sets.len().saturating_sub(visible_rows);
+                let (mouse_x, _) = mouse_position();
+                let (_, wheel_y) = mouse_wheel();
+                let alt_down = is_key_down(KeyCode::LeftAlt) || is_key_down(KeyCode::RightAlt);
+                if mouse_x < 500.0 && !alt_down {
+                    if wheel_y < 0.0 {
+                        library_scroll += 1;
+                    } else if wheel_y > 0.0 {
+                        library_scroll = library_scroll.saturating_sub(1);
+                    }
+                }
+                library_scroll = library_scroll.min(max_scroll);
+
+                for (i, set) in library.sets.iter().skip(library_scroll).take(visible_rows).enumerate() {
+                    // Sets indexed from the osu! Songs folder are marked
+                    let name = if set.external { format!("[osu!] {}", set.name) } else { set.name.clone() };
+                    let truncated = if name.chars().count() > 35 {
+                        format!("{}...", name.chars().take(32).collect::<String>())
+                    } else {
+                        name
+                    };
+                    
+                    if root_ui().button(vec2(40.0, 300.0 + (i as f32 * 40.0)), truncated.as_str()) {
+                        selected_beatmap = Some(set.path.clone());
+                        if let Ok(diffs) = parser::get_difficulties(&set.path) {
+                            difficulties = diffs;
+                            scene = "DiffSelect";
                         }
                     }
                 }
@@ -108,54 +197,282 @@ async fn main() {
                     scene = "Menu";
                 }
                 
-                // Reverse mode toggle
-                draw_text("GAMEPLAY:", 40.0, 140.0, 30.0, WHITE);
-                let reverse_text = if options.reverse_mode { "Reverse: ON (FNF style)" } else { "Reverse: OFF (Normal)" };
-                if root_ui().button(vec2(40.0, 160.0), reverse_text) {
-                    options.reverse_mode = !options.reverse_mode;
-                }
-                
-                // 2K Key bindings
-                draw_text("2K KEY BINDINGS:", 40.0, 230.0, 30.0, WHITE);
-                for i in 0..2 {
-                    let key_name = format!("Lane {}: {:?}", i + 1, options.keys_2k[i]);
-                    let button_text = if remapping_mode == Some((i, true)) {
-                        "Press any key...".to_string()
-                    } else {
-                        key_name
-                    };
-                    
-                    if root_ui().button(vec2(40.0, 260.0 + (i as f32 * 40.0)), button_text.as_str()) {
-                        remapping_mode = Some((i, true));
+                // Settings are split into tabs so each page fits on screen
+                for (i, tab) in ["Gameplay", "Input", "Audio", "Visual", "Library"].iter().enumerate() {
+                    let label = if options_tab == *tab { format!("[{}]", tab) } else { tab.to_string() };
+                    if root_ui().button(vec2(130.0 + (i as f32 * 100.0), 80.0), label.as_str()) {
+                        options_tab = tab;
+                        remapping_mode = None;
                     }
                 }
                 
-                // 4K Key bindings
-                draw_text("4K KEY BINDINGS:", 40.0, 380.0, 30.0, WHITE);
-                for i in 0..4 {
-                    let key_name = format!("Lane {}: {:?}", i + 1, options.keys_4k[i]);
-                    let button_text = if remapping_mode == Some((i, false)) {
-                        "Press any key...".to_string()
-                    } else {
-                        key_name
-                    };
-                    
-                    if root_ui().button(vec2(40.0, 410.0 + (i as f32 * 40.0)), button_text.as_str()) {
-                        remapping_mode = Some((i, false));
+                match options_tab {
+                    "Gameplay" => {
+                        // Reverse mode toggle
+                        draw_text("GAMEPLAY:", 40.0, 140.0, 30.0, WHITE);
+                        let reverse_text = if options.reverse_mode { "Reverse: ON (FNF style)" } else { "Reverse: OFF (Normal)" };
+                        if root_ui().button(vec2(40.0, 160.0), reverse_text) {
+                            options.reverse_mode = !options.reverse_mode;
+                        }
+                        let note_lock_text = if options.note_lock { "Notelock: ON (earliest note)" } else { "Notelock: OFF (closest note)" };
+                        if root_ui().button(vec2(40.0, 190.0), note_lock_text) {
+                            options.note_lock = !options.note_lock;
+                        }
+                        
+                        // Scroll velocity handling, applied when a map loads
+                        draw_text("SCROLL:", 380.0, 140.0, 30.0, WHITE);
+                        let sv_text = if options.use_sv { "SV: Follow map" } else { "SV: Ignore" };
+                        if root_ui().button(vec2(380.0, 160.0), sv_text) {
+                            options.use_sv = !options.use_sv;
+                        }
+                        let bpm_text = if options.bpm_scaled_scroll { "Speed: BPM-scaled" } else { "Speed: Constant" };
+                        if root_ui().button(vec2(380.0, 190.0), bpm_text) {
+                            options.bpm_scaled_scroll = !options.bpm_scaled_scroll;
+                        }
+                        
+                        // Long note scoring
+                        draw_text("LONG NOTES:", 380.0, 250.0, 30.0, WHITE);
+                        let ln_text = match options.ln_scoring {
+                            models::LnScoring::Combined => "LN scoring: osu!mania (head + tail)",
+                            models::LnScoring::Ticks => "LN scoring: Hold ticks",
+                        };
+                        if root_ui().button(vec2(380.0, 270.0), ln_text) {
+                            options.ln_scoring = match options.ln_scoring {
+                                models::LnScoring::Combined => models::LnScoring::Ticks,
+                                models::LnScoring::Ticks => models::LnScoring::Combined,
+                            };
+                        }
+                        
+                        draw_text("COMBO:", 380.0, 330.0, 30.0, WHITE);
+                        let milestones_text = if options.combo_milestones { "Milestones: ON (every 100)" } else { "Milestones: OFF" };
+                        if root_ui().button(vec2(380.0, 350.0), milestones_text) {
+                            options.combo_milestones = !options.combo_milestones;
+                        }
+
                     }
-                }
-                
-                // Handle key remapping
-                if let Some((key_index, is_2k)) = remapping_mode {
-                    // Check for any key press
-                    if let Some(pressed_key) = get_pressed_key() {
-                        if is_2k {
-                            options.keys_2k[key_index] = pressed_key;
-                        } else {
-                            options.keys_4k[key_index] = pressed_key;
+                    "Input" => {
+                        // Lane bindings: each lane can have several keys, pad buttons and MIDI notes.
+                        // Clicking a lane adds a binding, "x" clears the lane.
+                        let modes = [(2, "2K BINDINGS:", 40.0, 140.0), (4, "4K BINDINGS:", 40.0, 280.0), (8, "8K (7K+1) BINDINGS:", 380.0, 140.0)];
+                        for (mode, header, x, header_y) in modes {
+                            draw_text(header, x, header_y, 30.0, WHITE);
+                            for (i, lane) in options.lane_bindings_mut(mode).iter_mut().enumerate() {
+                                let row_y = header_y + 30.0 + (i as f32 * 40.0);
+                                if root_ui().button(vec2(x, row_y), "x") {
+                                    lane.clear();
+                                    remapping_mode = None;
+                                }
+                                
+                                let button_text = if remapping_mode == Some((i, mode)) {
+                                    "Press a key, pad button or MIDI note...".to_string()
+                                } else if lane.is_empty() {
+                                    format!("Lane {}: (unbound)", i + 1)
+                                } else {
+                                    let names: Vec<String> = lane.iter().map(|b| b.name()).collect();
+                                    format!("Lane {}: {}", i + 1, names.join(", "))
+                                };
+                                if root_ui().button(vec2(x + 30.0, row_y), button_text.as_str()) {
+                                    remapping_mode = Some((i, mode));
+                                }
+                            }
+                        }
+                        
+                        // Handle binding capture
+                        if let Some((lane_index, mode)) = remapping_mode {
+                            if is_key_pressed(KeyCode::Escape) {
+                                remapping_mode = None;
+                            } else if let Some(binding) = pressed_binding(&input) {
+                                let lanes = options.lane_bindings_mut(mode);
+                                // A binding drives one lane per mode, so it moves rather than duplicates
+                                for lane in lanes.iter_mut() {
+                                    lane.retain(|b| *b != binding);
+                                }
+                                lanes[lane_index].push(binding);
+                                remapping_mode = None;
+                            }
                         }
-                        remapping_mode = None;
                     }
+                    "Audio" => {
+                        // Volume sliders (also adjustable anywhere with ALT + wheel)
+                        draw_text("VOLUME:", 40.0, 140.0, 30.0, WHITE);
+                        let volumes = [
+                            ("Master", &mut options.master_volume),
+                            ("Music", &mut options.music_volume),
+                            ("Effects", &mut options.effects_volume),
+                        ];
+                        for (i, (label, volume)) in volumes.into_iter().enumerate() {
+                            let row_y = 160.0 + (i as f32 * 40.0);
+                            draw_text(format!("{}: {}", label, volume), 40.0, row_y + 18.0, 22.0, GRAY);
+                            if root_ui().button(vec2(200.0, row_y), "-") {
+                                *volume = (*volume - 5).max(0);
+                            }
+                            if root_ui().button(vec2(230.0, row_y), "+") {
+                                *volume = (*volume + 5).min(100);
+                            }
+                        }
+                        
+                        // Output device list; switching reopens the stream right away
+                        draw_text("OUTPUT DEVICE:", 40.0, 310.0, 30.0, WHITE);
+                        let current = audio_output.device_name().unwrap_or("None (silent)");
+                        draw_text(format!("Using: {}", current), 40.0, 335.0, 18.0, GRAY);
+                        if root_ui().button(vec2(40.0, 345.0), "Refresh") {
+                            audio_devices = audio::AudioOutput::list_devices();
+                        }
+                        
+                        let mut device_choice: Option<String> = None;
+                        let default_label = if options.audio_device.is_empty() { "> System default" } else { "System default" };
+                        if root_ui().button(vec2(40.0, 380.0), default_label) {
+                            device_choice = Some(String::new());
+                        }
+                        for (i, name) in audio_devices.iter().enumerate() {
+                            let truncated = if name.chars().count() > 35 {
+                                format!("{}...", name.chars().take(32).collect::<String>())
+                            } else {
+                                name.clone()
+                            };
+                            let label = if *name == options.audio_device { format!("> {}", truncated) } else { truncated };
+                            if root_ui().button(vec2(40.0, 410.0 + (i as f32 * 30.0)), label.as_str()) {
+                                device_choice = Some(name.clone());
+                            }
+                        }
+                        
+                        if let Some(device) = device_choice {
+                            // Anything playing on the old stream goes away with it
+                            if let Ok(mut sink) = audio_sink.lock() {
+                                if let Some(sink_inst) = sink.take() {
+                                    sink_inst.stop();
+                                }
+                            }
+                            state = None;
+                            options.audio_device = device;
+                            audio_output = audio::AudioOutput::open(&options.audio_device);
+                            let _ = options.save();
+                        }
+                    }
+                    "Visual" => {
+                        draw_text("BACKGROUND:", 40.0, 140.0, 30.0, WHITE);
+                        draw_text(format!("Dim: {}%", options.background_dim), 40.0, 178.0, 22.0, GRAY);
+                        if root_ui().button(vec2(200.0, 160.0), "-") {
+                            options.background_dim = (options.background_dim - 5).max(0);
+                        }
+                        if root_ui().button(vec2(230.0, 160.0), "+") {
+                            options.background_dim = (options.background_dim + 5).min(100);
+                        }
+                        
+                        // Blur is baked into the texture when a map loads
+                        draw_text(format!("Blur: {}%", options.background_blur), 40.0, 218.0, 22.0, GRAY);
+                        if root_ui().button(vec2(200.0, 200.0), "-") {
+                            options.background_blur = (options.background_blur - 10).max(0);
+                        }
+                        if root_ui().button(vec2(230.0, 200.0), "+") {
+                            options.background_blur = (options.background_blur + 10).min(100);
+                        }
+                        
+                        let fit_text = format!("Scaling: {}", options.background_fit.name());
+                        if root_ui().button(vec2(40.0, 240.0), fit_text.as_str()) {
+                            options.background_fit = match options.background_fit {
+                                models::BackgroundFit::Cover => models::BackgroundFit::Contain,
+                                models::BackgroundFit::Contain => models::BackgroundFit::Cover,
+                            };
+                        }
+                        
+                        let storyboard_text = if options.show_storyboard { "Video/Storyboard: ON" } else { "Video/Storyboard: OFF" };
+                        if root_ui().button(vec2(40.0, 280.0), storyboard_text) {
+                            options.show_storyboard = !options.show_storyboard;
+                        }
+                        
+                        draw_text("PLAYFIELD:", 40.0, 340.0, 30.0, WHITE);
+                        let measure_lines_text = if options.show_measure_lines { "Measure lines: ON" } else { "Measure lines: OFF" };
+                        if root_ui().button(vec2(40.0, 360.0), measure_lines_text) {
+                            options.show_measure_lines = !options.show_measure_lines;
+                        }
+                        let beat_text = if options.show_beat_lines { "Beat lines: ON" } else { "Beat lines: OFF" };
+                        if root_ui().button(vec2(40.0, 390.0), beat_text) {
+                            options.show_beat_lines = !options.show_beat_lines;
+                        }
+                        
+                        // Live stats in the gameplay HUD
+                        draw_text("HUD:", 380.0, 140.0, 30.0, WHITE);
+                        let accuracy_text = if options.show_accuracy { "Accuracy: ON" } else { "Accuracy: OFF" };
+                        if root_ui().button(vec2(380.0, 160.0), accuracy_text) {
+                            options.show_accuracy = !options.show_accuracy;
+                        }
+                        let grade_text = if options.show_grade { "Grade: ON" } else { "Grade: OFF" };
+                        if root_ui().button(vec2(380.0, 190.0), grade_text) {
+                            options.show_grade = !options.show_grade;
+                        }
+                        let pace_text = if options.show_pace { "Pace: ON" } else { "Pace: OFF" };
+                        if root_ui().button(vec2(380.0, 220.0), pace_text) {
+                            options.show_pace = !options.show_pace;
+                        }
+                        let target_text = format!("Target grade: {}", options.target_grade.name());
+                        if root_ui().button(vec2(380.0, 250.0), target_text.as_str()) {
+                            options.target_grade = match options.target_grade {
+                                models::Grade::SS => models::Grade::S,
+                                models::Grade::S => models::Grade::A,
+                                models::Grade::A => models::Grade::B,
+                                models::Grade::B => models::Grade::C,
+                                _ => models::Grade::SS,
+                            };
+                        }
+                        
+                        // The swap interval is only set when the window is created, so switching
+                        // relaunches the game with the new setting
+                        draw_text("DISPLAY:", 380.0, 310.0, 30.0, WHITE);
+                        let fps_text = if options.uncapped_fps { "Frame rate: Unlimited" } else { "Frame rate: VSync" };
+                        if root_ui().button(vec2(380.0, 330.0), fps_text) {
+                            options.uncapped_fps = !options.uncapped_fps;
+                            let _ = options.save();
+                            let relaunch = std::env::current_exe()
+                                .and_then(|exe| std::process::Command::new(exe).args(std::env::args_os().skip(1)).spawn());
+                            match relaunch {
+                                Ok(_) => std::process::exit(0),
+                                Err(e) => toasts.error(format!("Restart the game to apply the frame rate: {}", e)),
+                            }
+                        }
+                        draw_text("Switching restarts the game", 380.0, 375.0, 18.0, GRAY);
+                    }
+                    "Library" => {
+                        let folders = [
+                            ("OSU! SONGS FOLDER:", "Indexed in place, nothing is copied", 140.0),
+                            ("WATCH FOLDER:", "New .osz/.qp files here are imported automatically", 260.0),
+                        ];
+                        for (i, (title, hint, y)) in folders.into_iter().enumerate() {
+                            let folder = if i == 0 { &mut options.songs_dir } else { &mut options.watch_dir };
+                            draw_text(title, 40.0, y, 30.0, WHITE);
+                            draw_text(hint, 40.0, y + 22.0, 18.0, GRAY);
+                            let shown = if folder.is_empty() {
+                                String::from("(none)")
+                            } else if folder.chars().count() > 55 {
+                                format!("...{}", folder.chars().skip(folder.chars().count() - 52).collect::<String>())
+                            } else {
+                                folder.clone()
+                            };
+                            draw_text(shown, 40.0, y + 46.0, 20.0, YELLOW);
+
+                            let mut changed = false;
+                            if root_ui().button(vec2(40.0, y + 56.0), "Browse") {
+                                if let Some(dir) = rfd::FileDialog::new().pick_folder() {
+                                    *folder = dir.to_string_lossy().to_string();
+                                    changed = true;
+                                }
+                            }
+                            if root_ui().button(vec2(110.0, y + 56.0), "Clear") {
+                                folder.clear();
+                                changed = true;
+                            }
+                            if changed {
+                                let _ = options.save();
+                                library.refresh(&options);
+                            }
+                        }
+
+                        if root_ui().button(vec2(40.0, 400.0), "RESCAN LIBRARY") {
+                            library.refresh(&options);
+                            toasts.success(format!("{} beatmap sets found", library.sets.len()));
+                        }
+                    }
+                    _ => {}
                 }
             }
             "DiffSelect" => {
@@ -176,27 +493,81 @@ async fn main() {
                     rpc.update_idle();
                 }
 
+                if root_ui().button(vec2(200.0, 170.0), "EXPORT .OSZ") {
+                    if let Some(ref bm_path) = selected_beatmap {
+                        let set_name = bm_path.file_name().unwrap().to_string_lossy().to_string();
+                        if let Some(out) = rfd::FileDialog::new()
+                            .add_filter("osu", &["osz"])
+                            .set_file_name(format!("{}.osz", set_name))
+                            .save_file()
+                        {
+                            match parser::export_osz(bm_path, &out) {
+                                Ok(()) => toasts.success(format!("Exported {}", out.display())),
+                                Err(e) => toasts.error(format!("Failed to export {}: {}", out.display(), e)),
+                            }
+                        }
+                    }
+                }
+
                 for (i, diff) in difficulties.iter().enumerate() {
+                    // The editor only writes .osu files
+                    if parser::ChartFormat::of(&diff.path) == Some(parser::ChartFormat::Osu) && root_ui().button(vec2(500.0, 220.0 + (i as f32 * 40.0)), "EDIT") {
+                        let external = library.sets.iter().any(|set| Some(&set.path) == selected_beatmap.as_ref() && set.external);
+                        match editor::Editor::open(diff.path.clone(), audio_output.handle(), key_mode, external) {
+                            Ok(e) => {
+                                editor = Some(e);
+                                scene = "Editor";
+                                rpc.update_idle();
+                            }
+                            Err(e) => toasts.error(format!("Failed to open editor for {}: {}", diff.path.display(), e)),
+                        }
+                    }
                     if root_ui().button(vec2(40.0, 220.0 + (i as f32 * 40.0)), diff.version.as_str()) {
-                        if let Ok((s, sink)) = parser::load_map(diff.path.clone(), &stream_handle, key_mode).await {
-                            state = Some(s);
-                            
-                            // Store the sink so we can pause/resume it
-                            if let Ok(mut sink_lock) = audio_sink.lock() {
-                                *sink_lock = Some(sink);
+                        match parser::load_map(diff, audio_output.handle(), key_mode, &options).await {
+                            Ok((s, sink, warnings)) => {
+                                if !warnings.is_empty() {
+                                    for warning in &warnings {
+                                        eprintln!("{}: {}", diff.path.display(), warning);
+                                    }
+                                    toasts.error(format!("Skipped {} unplayable hit object(s), see the console", warnings.len()));
+                                }
+                                if let Some(audio) = &s.audio {
+                                    audio.set_volume(options.effects_gain());
+                                }
+                                state = Some(s);
+                                
+                                // Store the sink so we can pause/resume it
+                                sink.set_volume(options.music_gain());
+                                if let Ok(mut sink_lock) = audio_sink.lock() {
+                                    *sink_lock = Some(sink);
+                                }
+                                
+                                scene = "Playing";
+                                song_finished_shown = false;
+                                let map_name = selected_beatmap.as_ref().unwrap().file_stem().unwrap().to_string_lossy();
+                                rpc.update_playing(&map_name, &diff.version);
                             }
-                            
-                            scene = "Playing";
-                            song_finished_shown = false;
-                            let map_name = selected_beatmap.as_ref().unwrap().file_stem().unwrap().to_string_lossy();
-                            rpc.update_playing(&map_name, &diff.version);
+                            Err(e) => toasts.error(format!("Failed to load {}: {}", diff.version, e)),
+                        }
+                    }
+                }
+            }
+            "Editor" => {
+                let should_quit = editor.as_mut().is_none_or(|e| e.update_and_draw(&options));
+                if should_quit {
+                    editor = None;
+                    // Saving may have changed difficulty names or modes
+                    if let Some(ref bm_path) = selected_beatmap {
+                        if let Ok(diffs) = parser::get_difficulties(bm_path) {
+                            difficulties = diffs;
                         }
                     }
+                    scene = "DiffSelect";
                 }
             }
             "Playing" => {
                 if let Some(ref mut s) = state {
-                    let should_quit = game::update_and_draw(s, &mut options);
+                    let should_quit = game::update_and_draw(s, &mut options, &input);
                     
                     if should_quit {
                         // Stop audio
@@ -223,12 +594,19 @@ async fn main() {
             }
             _ => {}
         }
+        
+        if scene != "Playing" {
+            imports.draw(&mut toasts);
+        }
+        toasts.draw();
+        volume_overlay.draw(&options);
         next_frame().await
     }
 }
 
-fn get_pressed_key() -> Option<KeyCode> {
-    // Check all common keys
+/// The first binding pressed this frame. Keys are limited to the ones the config can store.
+fn pressed_binding(input: &input::InputQueue) -> Option<input::Binding> {
+    // No ALT: holding it turns the arrow keys and wheel into volume controls
     let keys = [
         KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G, KeyCode::H,
         KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N, KeyCode::O, KeyCode::P,
@@ -237,15 +615,15 @@ fn get_pressed_key() -> Option<KeyCode> {
         KeyCode::Key0, KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
         KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
         KeyCode::Space, KeyCode::LeftShift, KeyCode::RightShift, KeyCode::LeftControl, KeyCode::RightControl,
-        KeyCode::LeftAlt, KeyCode::RightAlt, KeyCode::Comma, KeyCode::Period, KeyCode::Slash,
+        KeyCode::Comma, KeyCode::Period, KeyCode::Slash,
         KeyCode::Semicolon, KeyCode::Apostrophe, KeyCode::LeftBracket, KeyCode::RightBracket,
     ];
     
-    for key in keys.iter() {
-        if is_key_pressed(*key) {
-            return Some(*key);
-        }
-    }
-    
-    None
+    input.events().iter()
+        .filter(|event| event.pressed)
+        .map(|event| event.binding)
+        .find(|binding| match binding {
+            input::Binding::Key(key) => keys.contains(key),
+            _ => true,
+        })
 }
\ No newline at end of file
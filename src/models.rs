@@ -3,6 +3,8 @@ use std::time::Instant;
 use std::fs;
 use std::path::Path;
 use crate::audio::AudioSystem;
+use crate::input::{Binding, PAD_BUTTONS};
+use crate::storyboard::Storyboard;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum HitJudgment {
@@ -73,15 +75,145 @@ pub struct Note {
     pub ln_completed: bool,
     pub ln_head_judgment: Option<HitJudgment>,
     pub ln_tail_judgment: Option<HitJudgment>,
+    pub ln_head_offset: f32, // seconds the head was hit early (+) or late (-), for the combined judgment
+    pub ln_ticks: u32, // hold ticks judged so far in tick scoring
     
     // Audio tracking for sliders
     pub slider_sound_playing: bool,
+    pub hitsound_volume: f32, // 0.0-1.0, from the active timing point
+    pub keysound: Option<usize>, // index into AudioSystem's keysounds, played instead of the hit sound
+    
+    // Scroll positions from ScrollMap, used instead of raw times when drawing
+    pub start_pos: f32,
+    pub end_pos: f32,
+}
+
+/// One `[TimingPoints]` entry. Inherited points carry the beat length and meter
+/// of the uninherited point before them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimingPoint {
+    pub time: f32,          // ms
+    pub beat_length: f32,   // ms per beat
+    pub velocity_mult: f32, // slider velocity, 1.0 on uninherited points, 0.0 for a simfile stop
+    pub meter: i32,         // beats per measure
+    pub sample_set: i32,    // 0 default, 1 normal, 2 soft, 3 drum
+    pub sample_index: i32,
+    pub volume: f32,        // 0-100
+    pub uninherited: bool,
+    pub effects: i32,       // bit 0 kiai, bit 3 omit first barline
+}
+
+/// A beat or measure line derived from uninherited timing points
+pub struct BeatLine {
+    pub pos: f32, // scroll position, see ScrollMap
+    pub measure: bool,
+}
+
+impl BeatLine {
+    // Guards against broken maps with near-zero beat lengths
+    const MAX_LINES: usize = 100_000;
+    
+    /// One line per beat from each uninherited point until the next one (or `end_ms`).
+    /// Every `meter`-th beat counted from the timing point is a measure line, except that
+    /// points with "omit first barline" (effects bit 3) get no line of their own.
+    pub fn generate(points: &[TimingPoint], end_ms: f32, scroll_map: &ScrollMap) -> Vec<BeatLine> {
+        let uninherited: Vec<&TimingPoint> = points.iter().filter(|tp| tp.uninherited).collect();
+        let mut lines = Vec::new();
+        
+        for (i, tp) in uninherited.iter().enumerate() {
+            if tp.beat_length < 1.0 { continue; }
+            let segment_end = uninherited.get(i + 1).map(|next| next.time).unwrap_or(end_ms);
+            
+            let mut beat = 0;
+            loop {
+                let time_ms = tp.time + beat as f32 * tp.beat_length;
+                // Stop just short of the next point so its own first beat isn't doubled
+                if time_ms >= segment_end - 1.0 || lines.len() >= Self::MAX_LINES { break; }
+                if beat == 0 && tp.effects & 8 != 0 {
+                    beat += 1;
+                    continue;
+                }
+                
+                lines.push(BeatLine {
+                    pos: scroll_map.position_at(time_ms / 1000.0),
+                    measure: beat % tp.meter == 0,
+                });
+                beat += 1;
+            }
+        }
+        
+        lines
+    }
+}
+
+/// Scroll position as a function of song time. Position advances at the current
+/// velocity (SV and/or BPM ratio), so with both ignored it is just the time in seconds.
+pub struct ScrollMap {
+    segments: Vec<(f32, f32, f32)>, // (start time s, position at start, velocity)
+}
+
+impl ScrollMap {
+    pub fn build(points: &[TimingPoint], use_sv: bool, bpm_scaled: bool, song_end_ms: f32) -> Self {
+        // Stops from StepMania/BMS charts are timing, not SV, so they freeze the chart either way
+        let has_stops = points.iter().any(|tp| tp.velocity_mult == 0.0);
+        if (!use_sv && !bpm_scaled && !has_stops) || points.is_empty() {
+            return Self { segments: vec![(0.0, 0.0, 1.0)] };
+        }
+        
+        let base_beat_length = Self::dominant_beat_length(points, song_end_ms);
+        let mut segments: Vec<(f32, f32, f32)> = Vec::with_capacity(points.len());
+        
+        for tp in points {
+            let sv = if use_sv { tp.velocity_mult.clamp(0.1, 10.0) } else { 1.0 };
+            let bpm_ratio = if bpm_scaled { base_beat_length / tp.beat_length } else { 1.0 };
+            let velocity = if tp.velocity_mult == 0.0 { 0.0 } else { sv * bpm_ratio };
+            let time = tp.time / 1000.0;
+            
+            let position = match segments.last() {
+                Some(&(prev_time, prev_pos, prev_vel)) => prev_pos + (time - prev_time) * prev_vel,
+                None => time,
+            };
+            segments.push((time, position, velocity));
+        }
+        
+        Self { segments }
+    }
+    
+    /// Beat length that lasts the longest, like osu!'s "main BPM"
+    fn dominant_beat_length(points: &[TimingPoint], song_end_ms: f32) -> f32 {
+        let uninherited: Vec<&TimingPoint> = points.iter().filter(|tp| tp.uninherited).collect();
+        let mut durations: Vec<(f32, f32)> = Vec::new(); // (beat length, total ms)
+        
+        for (i, tp) in uninherited.iter().enumerate() {
+            let end = uninherited.get(i + 1).map(|next| next.time).unwrap_or(song_end_ms.max(tp.time));
+            let length = end - tp.time;
+            match durations.iter_mut().find(|(bl, _)| (*bl - tp.beat_length).abs() < 0.001) {
+                Some(entry) => entry.1 += length,
+                None => durations.push((tp.beat_length, length)),
+            }
+        }
+        
+        durations.iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(bl, _)| *bl)
+            .unwrap_or(500.0)
+    }
+    
+    pub fn position_at(&self, time: f32) -> f32 {
+        // Last segment starting at or before `time`; earlier times extrapolate the first one
+        let idx = self.segments.partition_point(|s| s.0 <= time).saturating_sub(1);
+        let (start, position, velocity) = self.segments[idx];
+        position + (time - start) * velocity
+    }
 }
 
 pub struct GameState {
     pub notes: Vec<Note>,
     pub score: i32,
     pub combo: i32,
+    pub max_combo: i32,
+    pub combo_breaks: Vec<f32>, // song time (s) of every combo break
+    pub milestone_time: f32, // when the combo last reached a milestone
     pub last_judgment: &'static str,
     pub judgment_color: Color,
     pub judgment_time: f32,
@@ -99,6 +231,17 @@ pub struct GameState {
     pub speed_change_time: f32,
     pub speed_display_text: String,
     pub audio: Option<AudioSystem>,
+    pub storyboard: Storyboard,
+    pub scroll_map: ScrollMap,
+    pub beat_lines: Vec<BeatLine>,
+    // Timed samples (seconds, keysound, volume); keysounded charts play their backing track this way
+    pub sample_events: Vec<(f32, usize, f32)>,
+    pub next_sample_event: usize,
+    // Note indices per lane sorted by start time, and the first one whose head isn't judged yet
+    pub lane_notes: Vec<Vec<usize>>,
+    pub lane_heads: Vec<usize>,
+    pub holding: Vec<Option<usize>>, // LN currently held down in each lane
+    pub lane_cursors: Vec<usize>, // first note per lane that is still unjudged, sounding or on screen
 }
 
 pub struct HitCounts {
@@ -109,21 +252,201 @@ pub struct HitCounts {
     pub miss: i32,
 }
 
+impl HitCounts {
+    pub fn judged(&self) -> i32 {
+        self.perfect + self.great + self.good + self.ok + self.miss
+    }
+    
+    fn weighted_score(&self) -> f32 {
+        (300 * self.perfect + 200 * self.great + 100 * self.good + 50 * self.ok) as f32
+    }
+    
+    /// Accuracy in percent over everything judged so far, 0 before the first judgment
+    pub fn accuracy(&self) -> f32 {
+        let judged = self.judged();
+        if judged == 0 {
+            return 0.0;
+        }
+        self.weighted_score() / (300.0 * judged as f32) * 100.0
+    }
+    
+    /// Final accuracy if every one of the chart's `total` judgments left is a PERFECT
+    pub fn best_possible_accuracy(&self, total: i32) -> f32 {
+        let total = total.max(self.judged());
+        if total == 0 {
+            return 100.0;
+        }
+        let remaining = (total - self.judged()) as f32;
+        (self.weighted_score() + 300.0 * remaining) / (300.0 * total as f32) * 100.0
+    }
+    
+    pub fn grade(&self) -> Grade {
+        Grade::from_accuracy(self.accuracy(), self.miss)
+    }
+}
+
+/// Letter grades, best first
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Grade {
+    SS,
+    S,
+    A,
+    B,
+    C,
+    D,
+}
+
+impl Grade {
+    /// SS and S also need a run without misses
+    pub fn from_accuracy(accuracy: f32, misses: i32) -> Self {
+        [Grade::SS, Grade::S, Grade::A, Grade::B, Grade::C]
+            .into_iter()
+            .find(|g| accuracy >= g.min_accuracy() && (misses == 0 || *g > Grade::S))
+            .unwrap_or(Grade::D)
+    }
+    
+    pub fn min_accuracy(&self) -> f32 {
+        match self {
+            Grade::SS => 100.0,
+            Grade::S => 95.0,
+            Grade::A => 90.0,
+            Grade::B => 80.0,
+            Grade::C => 70.0,
+            Grade::D => 0.0,
+        }
+    }
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            Grade::SS => "SS",
+            Grade::S => "S",
+            Grade::A => "A",
+            Grade::B => "B",
+            Grade::C => "C",
+            Grade::D => "D",
+        }
+    }
+    
+    pub fn color(&self) -> Color {
+        match self {
+            Grade::SS => Color::new(1.0, 0.9, 0.0, 1.0),
+            Grade::S => Color::new(0.9, 0.9, 0.9, 1.0),
+            Grade::A => Color::new(0.2, 1.0, 0.3, 1.0),
+            Grade::B => Color::new(0.3, 0.8, 1.0, 1.0),
+            Grade::C => Color::new(0.9, 0.6, 0.2, 1.0),
+            Grade::D => Color::new(0.9, 0.3, 0.3, 1.0),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct GameOptions {
-    pub keys_2k: [KeyCode; 2],
-    pub keys_4k: [KeyCode; 4],
+    // Any of a lane's bindings plays it
+    pub bindings_2k: [Vec<Binding>; 2],
+    pub bindings_4k: [Vec<Binding>; 4],
+    pub bindings_8k: [Vec<Binding>; 8], // 7K+1: scratch lane first
     pub reverse_mode: bool,
     pub scroll_speed: i32, // 1-40, osu!mania standard
+    pub master_volume: i32, // 0-100
+    pub music_volume: i32, // 0-100
+    pub effects_volume: i32, // 0-100
+    pub audio_device: String, // empty = system default
+    pub background_dim: i32, // 0-100, darkening over background/video/storyboard
+    pub background_blur: i32, // 0-100, applied to the background image when a map loads
+    pub background_fit: BackgroundFit,
+    pub show_storyboard: bool,
+    pub use_sv: bool, // follow the map's slider velocity changes
+    pub bpm_scaled_scroll: bool, // scroll faster/slower with BPM changes instead of constant speed
+    pub show_measure_lines: bool,
+    pub show_beat_lines: bool,
+    pub songs_dir: String, // osu! stable Songs folder indexed in place, empty = none
+    pub watch_dir: String, // new .osz files here are imported automatically, empty = none
+    pub note_lock: bool, // a press always goes to the earliest note in the lane, never a later one
+    pub ln_scoring: LnScoring,
+    pub combo_milestones: bool, // flash the combo counter every 100 combo
+    // HUD elements shown while playing
+    pub show_accuracy: bool,
+    pub show_grade: bool, // current grade and the best one still reachable
+    pub show_pace: bool, // accuracy margin against `target_grade`
+    pub target_grade: Grade,
+    pub uncapped_fps: bool, // vsync off; read once at startup
+}
+
+/// How long notes are scored
+#[derive(Clone, Copy, PartialEq)]
+pub enum LnScoring {
+    Combined, // osu!mania: one judgment from head and tail timing
+    Ticks,    // head judged like a note, then combo for every tick held
+}
+
+impl LnScoring {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LnScoring::Combined => "Combined",
+            LnScoring::Ticks => "Ticks",
+        }
+    }
+}
+
+/// How background images and videos are scaled to the window
+#[derive(Clone, Copy, PartialEq)]
+pub enum BackgroundFit {
+    Cover,   // Fill the screen, cropping the overflow
+    Contain, // Show the whole image, letterboxed
+}
+
+impl BackgroundFit {
+    /// Destination (x, y, w, h) on screen for a texture of the given size, keeping aspect ratio
+    pub fn dest_rect(&self, tex_w: f32, tex_h: f32) -> (f32, f32, f32, f32) {
+        let (sw, sh) = (screen_width(), screen_height());
+        let scale = match self {
+            BackgroundFit::Cover => (sw / tex_w).max(sh / tex_h),
+            BackgroundFit::Contain => (sw / tex_w).min(sh / tex_h),
+        };
+        let (w, h) = (tex_w * scale, tex_h * scale);
+        ((sw - w) / 2.0, (sh - h) / 2.0, w, h)
+    }
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackgroundFit::Cover => "Cover",
+            BackgroundFit::Contain => "Contain",
+        }
+    }
 }
 
 impl Default for GameOptions {
     fn default() -> Self {
         Self {
-            keys_2k: [KeyCode::D, KeyCode::K],
-            keys_4k: [KeyCode::D, KeyCode::F, KeyCode::J, KeyCode::K],
+            bindings_2k: [KeyCode::D, KeyCode::K].map(|key| vec![Binding::Key(key)]),
+            bindings_4k: [KeyCode::D, KeyCode::F, KeyCode::J, KeyCode::K].map(|key| vec![Binding::Key(key)]),
+            bindings_8k: [KeyCode::LeftShift, KeyCode::S, KeyCode::D, KeyCode::F, KeyCode::Space, KeyCode::J, KeyCode::K, KeyCode::L]
+                .map(|key| vec![Binding::Key(key)]),
             reverse_mode: false,
             scroll_speed: 20, // Default osu!mania speed
+            master_volume: 100,
+            music_volume: 80,
+            effects_volume: 80,
+            audio_device: String::new(),
+            background_dim: 40,
+            background_blur: 0,
+            background_fit: BackgroundFit::Cover,
+            show_storyboard: true,
+            // Constant scroll speed unless the player opts in
+            use_sv: false,
+            bpm_scaled_scroll: false,
+            show_measure_lines: true,
+            show_beat_lines: false,
+            songs_dir: String::new(),
+            watch_dir: String::new(),
+            note_lock: true,
+            ln_scoring: LnScoring::Combined,
+            combo_milestones: true,
+            show_accuracy: true,
+            show_grade: true,
+            show_pace: false,
+            target_grade: Grade::S,
+            uncapped_fps: false,
         }
     }
 }
@@ -138,10 +461,14 @@ impl GameOptions {
         }
         
         let content = fs::read_to_string(Self::CONFIG_FILE)?;
+        Ok(Self::parse(&content))
+    }
+    
+    fn parse(content: &str) -> Self {
         let mut options = Self::default();
         
         for line in content.lines() {
-            let parts: Vec<&str> = line.split('=').collect();
+            let parts: Vec<&str> = line.splitn(2, '=').collect();
             if parts.len() != 2 { continue; }
             
             let key = parts[0].trim();
@@ -152,67 +479,272 @@ impl GameOptions {
                     options.reverse_mode = value == "true";
                 }
                 "key_2k_0" => {
-                    if let Ok(keycode) = Self::parse_keycode(value) {
-                        options.keys_2k[0] = keycode;
-                    }
+                    Self::load_bindings(&mut options.bindings_2k[0], key, value);
                 }
                 "key_2k_1" => {
-                    if let Ok(keycode) = Self::parse_keycode(value) {
-                        options.keys_2k[1] = keycode;
-                    }
+                    Self::load_bindings(&mut options.bindings_2k[1], key, value);
                 }
                 "key_4k_0" => {
-                    if let Ok(keycode) = Self::parse_keycode(value) {
-                        options.keys_4k[0] = keycode;
-                    }
+                    Self::load_bindings(&mut options.bindings_4k[0], key, value);
                 }
                 "key_4k_1" => {
-                    if let Ok(keycode) = Self::parse_keycode(value) {
-                        options.keys_4k[1] = keycode;
-                    }
+                    Self::load_bindings(&mut options.bindings_4k[1], key, value);
                 }
                 "key_4k_2" => {
-                    if let Ok(keycode) = Self::parse_keycode(value) {
-                        options.keys_4k[2] = keycode;
-                    }
+                    Self::load_bindings(&mut options.bindings_4k[2], key, value);
                 }
                 "key_4k_3" => {
-                    if let Ok(keycode) = Self::parse_keycode(value) {
-                        options.keys_4k[3] = keycode;
-                    }
+                    Self::load_bindings(&mut options.bindings_4k[3], key, value);
+                }
+                "key_8k_0" => {
+                    Self::load_bindings(&mut options.bindings_8k[0], key, value);
+                }
+                "key_8k_1" => {
+                    Self::load_bindings(&mut options.bindings_8k[1], key, value);
+                }
+                "key_8k_2" => {
+                    Self::load_bindings(&mut options.bindings_8k[2], key, value);
+                }
+                "key_8k_3" => {
+                    Self::load_bindings(&mut options.bindings_8k[3], key, value);
+                }
+                "key_8k_4" => {
+                    Self::load_bindings(&mut options.bindings_8k[4], key, value);
+                }
+                "key_8k_5" => {
+                    Self::load_bindings(&mut options.bindings_8k[5], key, value);
+                }
+                "key_8k_6" => {
+                    Self::load_bindings(&mut options.bindings_8k[6], key, value);
+                }
+                "key_8k_7" => {
+                    Self::load_bindings(&mut options.bindings_8k[7], key, value);
                 }
                 "scroll_speed" => {
                     if let Ok(speed) = value.parse::<i32>() {
                         options.scroll_speed = speed.clamp(1, 40);
                     }
                 }
+                "master_volume" => {
+                    if let Ok(vol) = value.parse::<i32>() {
+                        options.master_volume = vol.clamp(0, 100);
+                    }
+                }
+                "music_volume" => {
+                    if let Ok(vol) = value.parse::<i32>() {
+                        options.music_volume = vol.clamp(0, 100);
+                    }
+                }
+                "effects_volume" => {
+                    if let Ok(vol) = value.parse::<i32>() {
+                        options.effects_volume = vol.clamp(0, 100);
+                    }
+                }
+                "audio_device" => {
+                    options.audio_device = value.to_string();
+                }
+                "background_dim" => {
+                    if let Ok(dim) = value.parse::<i32>() {
+                        options.background_dim = dim.clamp(0, 100);
+                    }
+                }
+                "background_blur" => {
+                    if let Ok(blur) = value.parse::<i32>() {
+                        options.background_blur = blur.clamp(0, 100);
+                    }
+                }
+                "background_fit" => {
+                    options.background_fit = match value {
+                        "Contain" => BackgroundFit::Contain,
+                        _ => BackgroundFit::Cover,
+                    };
+                }
+                "show_storyboard" => {
+                    options.show_storyboard = value == "true";
+                }
+                "use_sv" => {
+                    options.use_sv = value == "true";
+                }
+                "bpm_scaled_scroll" => {
+                    options.bpm_scaled_scroll = value == "true";
+                }
+                "show_measure_lines" => {
+                    options.show_measure_lines = value == "true";
+                }
+                "show_beat_lines" => {
+                    options.show_beat_lines = value == "true";
+                }
+                "songs_dir" => {
+                    options.songs_dir = value.to_string();
+                }
+                "watch_dir" => {
+                    options.watch_dir = value.to_string();
+                }
+                "note_lock" => {
+                    options.note_lock = value == "true";
+                }
+                "show_accuracy" => {
+                    options.show_accuracy = value == "true";
+                }
+                "show_grade" => {
+                    options.show_grade = value == "true";
+                }
+                "show_pace" => {
+                    options.show_pace = value == "true";
+                }
+                "target_grade" => {
+                    options.target_grade = match value {
+                        "SS" => Grade::SS,
+                        "A" => Grade::A,
+                        "B" => Grade::B,
+                        "C" => Grade::C,
+                        _ => Grade::S,
+                    };
+                }
+                "uncapped_fps" => {
+                    options.uncapped_fps = value == "true";
+                }
+                "combo_milestones" => {
+                    options.combo_milestones = value == "true";
+                }
+                "ln_scoring" => {
+                    options.ln_scoring = match value {
+                        "Ticks" => LnScoring::Ticks,
+                        _ => LnScoring::Combined,
+                    };
+                }
                 _ => {}
             }
         }
         
-        Ok(options)
+        options
     }
     
     /// Save settings to file
     pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let content = format!(
-            "reverse_mode={}\nkey_2k_0={}\nkey_2k_1={}\nkey_4k_0={}\nkey_4k_1={}\nkey_4k_2={}\nkey_4k_3={}\nscroll_speed={}\n",
+        fs::write(Self::CONFIG_FILE, self.to_config())?;
+        Ok(())
+    }
+    
+    fn to_config(&self) -> String {
+        format!(
+            "reverse_mode={}\nkey_2k_0={}\nkey_2k_1={}\nkey_4k_0={}\nkey_4k_1={}\nkey_4k_2={}\nkey_4k_3={}\nkey_8k_0={}\nkey_8k_1={}\nkey_8k_2={}\nkey_8k_3={}\nkey_8k_4={}\nkey_8k_5={}\nkey_8k_6={}\nkey_8k_7={}\nscroll_speed={}\nmaster_volume={}\nmusic_volume={}\neffects_volume={}\naudio_device={}\nbackground_dim={}\nbackground_blur={}\nbackground_fit={}\nshow_storyboard={}\nuse_sv={}\nbpm_scaled_scroll={}\nshow_measure_lines={}\nshow_beat_lines={}\nsongs_dir={}\nwatch_dir={}\nnote_lock={}\nln_scoring={}\ncombo_milestones={}\nshow_accuracy={}\nshow_grade={}\nshow_pace={}\ntarget_grade={}\nuncapped_fps={}\n",
             self.reverse_mode,
-            Self::keycode_to_string(self.keys_2k[0]),
-            Self::keycode_to_string(self.keys_2k[1]),
-            Self::keycode_to_string(self.keys_4k[0]),
-            Self::keycode_to_string(self.keys_4k[1]),
-            Self::keycode_to_string(self.keys_4k[2]),
-            Self::keycode_to_string(self.keys_4k[3]),
+            Self::bindings_to_string(&self.bindings_2k[0]),
+            Self::bindings_to_string(&self.bindings_2k[1]),
+            Self::bindings_to_string(&self.bindings_4k[0]),
+            Self::bindings_to_string(&self.bindings_4k[1]),
+            Self::bindings_to_string(&self.bindings_4k[2]),
+            Self::bindings_to_string(&self.bindings_4k[3]),
+            Self::bindings_to_string(&self.bindings_8k[0]),
+            Self::bindings_to_string(&self.bindings_8k[1]),
+            Self::bindings_to_string(&self.bindings_8k[2]),
+            Self::bindings_to_string(&self.bindings_8k[3]),
+            Self::bindings_to_string(&self.bindings_8k[4]),
+            Self::bindings_to_string(&self.bindings_8k[5]),
+            Self::bindings_to_string(&self.bindings_8k[6]),
+            Self::bindings_to_string(&self.bindings_8k[7]),
             self.scroll_speed,
-        );
-        
-        fs::write(Self::CONFIG_FILE, content)?;
-        Ok(())
+            self.master_volume,
+            self.music_volume,
+            self.effects_volume,
+            self.audio_device,
+            self.background_dim,
+            self.background_blur,
+            self.background_fit.name(),
+            self.show_storyboard,
+            self.use_sv,
+            self.bpm_scaled_scroll,
+            self.show_measure_lines,
+            self.show_beat_lines,
+            self.songs_dir,
+            self.watch_dir,
+            self.note_lock,
+            self.ln_scoring.name(),
+            self.combo_milestones,
+            self.show_accuracy,
+            self.show_grade,
+            self.show_pace,
+            self.target_grade.name(),
+            self.uncapped_fps,
+        )
     }
     
-    fn keycode_to_string(key: KeyCode) -> String {
-        format!("{:?}", key)
+    /// Final music sink volume (master * music)
+    pub fn music_gain(&self) -> f32 {
+        (self.master_volume as f32 / 100.0) * (self.music_volume as f32 / 100.0)
+    }
+    
+    /// Final hit/hold sound volume (master * effects)
+    pub fn effects_gain(&self) -> f32 {
+        (self.master_volume as f32 / 100.0) * (self.effects_volume as f32 / 100.0)
+    }
+    
+    /// Lane bindings for a key mode (2K, 4K or 8K)
+    pub fn lane_bindings(&self, key_count: usize) -> &[Vec<Binding>] {
+        match key_count {
+            2 => &self.bindings_2k,
+            8 => &self.bindings_8k,
+            _ => &self.bindings_4k,
+        }
+    }
+
+    pub fn lane_bindings_mut(&mut self, key_count: usize) -> &mut [Vec<Binding>] {
+        match key_count {
+            2 => &mut self.bindings_2k,
+            8 => &mut self.bindings_8k,
+            _ => &mut self.bindings_4k,
+        }
+    }
+
+    /// Comma separated, e.g. `D,Pad:South,MIDI:36`; a lone key name is what older configs hold
+    fn bindings_to_string(bindings: &[Binding]) -> String {
+        bindings.iter()
+            .map(|binding| match binding {
+                Binding::Key(key) => format!("{:?}", key),
+                Binding::Pad(button) => format!("Pad:{:?}", button),
+                Binding::Midi(note) => format!("MIDI:{}", note),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+    
+    /// One lane's bindings from the config. An empty value is a lane cleared on purpose, but a
+    /// lane left with nothing usable (say, only ALT) keeps its default rather than going dead.
+    fn load_bindings(lane: &mut Vec<Binding>, key: &str, value: &str) {
+        if value.is_empty() {
+            lane.clear();
+            return;
+        }
+        let bindings = Self::parse_bindings(key, value);
+        if bindings.is_empty() {
+            eprintln!("Nothing bindable in {}={}, keeping the default", key, value);
+        } else {
+            *lane = bindings;
+        }
+    }
+    
+    fn parse_bindings(key: &str, value: &str) -> Vec<Binding> {
+        value.split(',')
+            .filter_map(|item| {
+                let item = item.trim();
+                let binding = if let Some(button) = item.strip_prefix("Pad:") {
+                    PAD_BUTTONS.into_iter().find(|b| format!("{:?}", b) == button).map(Binding::Pad)
+                } else if let Some(note) = item.strip_prefix("MIDI:") {
+                    note.parse().ok().map(Binding::Midi)
+                } else if item == "LeftAlt" || item == "RightAlt" {
+                    // Older configs could bind ALT; it's the volume overlay modifier now
+                    eprintln!("{} can't be bound any more (ALT controls the volume), dropped from {}", item, key);
+                    return None;
+                } else {
+                    Self::parse_keycode(item).ok().map(Binding::Key)
+                };
+                if binding.is_none() {
+                    eprintln!("Unknown binding {} in {}", item, key);
+                }
+                binding
+            })
+            .collect()
     }
     
     fn parse_keycode(s: &str) -> Result<KeyCode, ()> {
@@ -237,8 +769,7 @@ impl GameOptions {
             "RightShift" => Ok(KeyCode::RightShift),
             "LeftControl" => Ok(KeyCode::LeftControl),
             "RightControl" => Ok(KeyCode::RightControl),
-            "LeftAlt" => Ok(KeyCode::LeftAlt),
-            "RightAlt" => Ok(KeyCode::RightAlt),
+            // ALT is left out on purpose, it's reserved for the volume overlay
             "Comma" => Ok(KeyCode::Comma),
             "Period" => Ok(KeyCode::Period),
             "Slash" => Ok(KeyCode::Slash),
@@ -249,4 +780,97 @@ impl GameOptions {
             _ => Err(())
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(time: f32, beat_length: f32, velocity_mult: f32, meter: i32, uninherited: bool) -> TimingPoint {
+        TimingPoint {
+            time, beat_length, velocity_mult, meter, sample_set: 0, sample_index: 0,
+            volume: 100.0, uninherited, effects: 0,
+        }
+    }
+
+    /// (time in s, measure) of every line, with positions equal to times (no SV)
+    fn lines(points: &[TimingPoint], end_ms: f32) -> Vec<(f32, bool)> {
+        let scroll_map = ScrollMap::build(points, false, false, end_ms);
+        BeatLine::generate(points, end_ms, &scroll_map).iter().map(|l| (l.pos, l.measure)).collect()
+    }
+
+    #[test]
+    fn measure_lines_follow_meter_and_bpm_changes() {
+        // 4/4 at 120 BPM, then 3/4 at 240 BPM from 4s; the inherited point changes nothing
+        let points = [point(0.0, 500.0, 1.0, 4, true), point(1000.0, 500.0, 2.0, 4, false), point(4000.0, 250.0, 1.0, 3, true)];
+        let lines = lines(&points, 6000.0);
+        assert_eq!(lines.len(), 16);
+        assert!(lines[..8].iter().enumerate().all(|(i, l)| l.0 == i as f32 * 0.5));
+        assert!(lines[8..].iter().enumerate().all(|(i, l)| l.0 == 4.0 + i as f32 * 0.25));
+        let measures: Vec<f32> = lines.iter().filter(|l| l.1).map(|l| l.0).collect();
+        assert_eq!(measures, vec![0.0, 2.0, 4.0, 4.75, 5.5]);
+    }
+
+    #[test]
+    fn omitted_first_barline_is_skipped() {
+        let mut second = point(4000.0, 250.0, 1.0, 3, true);
+        second.effects = 8 | 1; // with kiai, which doesn't matter here
+        let lines = lines(&[point(0.0, 500.0, 1.0, 4, true), second], 6000.0);
+        assert!(!lines.iter().any(|l| l.0 == 4.0));
+        let measures: Vec<f32> = lines.iter().filter(|l| l.1).map(|l| l.0).collect();
+        assert_eq!(measures, vec![0.0, 2.0, 4.75, 5.5]);
+    }
+
+    #[test]
+    fn scroll_velocity_integrates_across_a_stop() {
+        // Stop from 1s to 1.5s, double speed from 2s
+        let points = [
+            point(0.0, 500.0, 1.0, 4, true),
+            point(1000.0, 500.0, 0.0, 4, false),
+            point(1500.0, 500.0, 1.0, 4, true),
+            point(2000.0, 500.0, 2.0, 4, false),
+        ];
+        let map = ScrollMap::build(&points, true, false, 4000.0);
+        assert_eq!(map.position_at(0.5), 0.5);
+        assert_eq!(map.position_at(1.25), 1.0);
+        assert_eq!(map.position_at(1.5), 1.0);
+        assert_eq!(map.position_at(2.0), 1.5);
+        assert_eq!(map.position_at(3.0), 3.5);
+
+        // Without SV the stop still freezes, everything else is constant speed
+        let map = ScrollMap::build(&points, false, false, 4000.0);
+        assert_eq!(map.position_at(1.25), 1.0);
+        assert_eq!(map.position_at(3.0), 2.5);
+    }
+
+    #[test]
+    fn bpm_scaling_is_relative_to_the_main_bpm() {
+        // 120 BPM for 6s, 240 BPM for 2s: 120 is the main BPM, so 240 scrolls twice as fast
+        let points = [point(0.0, 500.0, 1.0, 4, true), point(6000.0, 250.0, 1.0, 4, true)];
+        let map = ScrollMap::build(&points, false, true, 8000.0);
+        assert_eq!(map.position_at(6.0), 6.0);
+        assert_eq!(map.position_at(7.0), 8.0);
+        // Off, nothing changes speed
+        assert_eq!(ScrollMap::build(&points, false, false, 8000.0).position_at(7.0), 7.0);
+    }
+
+    #[test]
+    fn unusable_bindings_fall_back_to_the_default() {
+        let options = GameOptions::parse("key_4k_0=LeftAlt\nkey_4k_1=RightAlt,Pad:South\nkey_4k_2=Bogus\nkey_4k_3=\n");
+        assert_eq!(options.bindings_4k[0], vec![Binding::Key(KeyCode::D)]);
+        assert_eq!(options.bindings_4k[1], vec![Binding::Pad(gilrs::Button::South)]);
+        assert_eq!(options.bindings_4k[2], vec![Binding::Key(KeyCode::J)]);
+        // Cleared in the Input tab
+        assert!(options.bindings_4k[3].is_empty());
+    }
+
+    #[test]
+    fn bindings_survive_a_save() {
+        let mut options = GameOptions::default();
+        options.bindings_8k[0] = vec![Binding::Key(KeyCode::A), Binding::Pad(gilrs::Button::DPadLeft), Binding::Midi(36)];
+        options.bindings_2k[1].clear();
+        let parsed = GameOptions::parse(&options.to_config());
+        assert_eq!(parsed.bindings_8k, options.bindings_8k);
+        assert_eq!(parsed.bindings_2k, options.bindings_2k);
+    }
+}
@@ -1,7 +1,13 @@
-use crate::models::{Note, GameState, HitCounts};
+use crate::models::{Note, GameState, HitCounts, GameOptions, TimingPoint, ScrollMap, BeatLine};
 use crate::audio::AudioSystem;
+use crate::storyboard::Storyboard;
+use crate::stepmania;
+use crate::quaver;
+use crate::convert;
+use crate::bms;
 use macroquad::prelude::*;
 use rodio::{Decoder, OutputStreamHandle, Sink, Source};
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::io::{BufReader, Read};
@@ -9,230 +15,637 @@ use std::time::Instant;
 
 const MIN_LN_DURATION: f32 = 0.15;
 
-pub fn import_osz(path: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-    let target_dir = Path::new("beatmaps").join(path.file_stem().unwrap());
-    if !target_dir.exists() {
-        fs::create_dir_all(&target_dir)?;
-        let file = fs::File::open(path)?;
-        let mut archive = zip::ZipArchive::new(file)?;
-        archive.extract(&target_dir)?;
+/// Zip a beatmap set folder (audio, images, .osu files, samples, subfolders) into an .osz archive
+pub fn export_osz(folder: &Path, out_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let result = write_osz(folder, out_path);
+    if result.is_err() {
+        // Don't leave a truncated archive behind
+        let _ = fs::remove_file(out_path);
     }
+    result
+}
+
+fn write_osz(folder: &Path, out_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut zip = zip::ZipWriter::new(fs::File::create(out_path)?);
+    let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    let mut pending = vec![folder.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            // Editor backups are local only
+            if path.extension().and_then(|e| e.to_str()) == Some("bak") {
+                continue;
+            }
+
+            // Archive paths always use forward slashes
+            let name = path.strip_prefix(folder)?
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            zip.start_file(name, options)?;
+            std::io::copy(&mut fs::File::open(&path)?, &mut zip)?;
+        }
+    }
+
+    zip.finish()?;
     Ok(())
 }
 
-pub struct BeatmapInfo {
-    pub path: PathBuf,
-    pub version: String,
+/// Decode any image the `image` crate understands (png, jpg, bmp, webp, first frame of gif...)
+pub fn load_image_texture(path: &Path) -> Option<Texture2D> {
+    match image::open(path) {
+        Ok(img) => {
+            let rgba = img.to_rgba8();
+            Some(Texture2D::from_rgba8(rgba.width() as u16, rgba.height() as u16, rgba.as_raw()))
+        }
+        Err(e) => {
+            eprintln!("Failed to load image {}: {}", path.display(), e);
+            None
+        }
+    }
 }
 
-pub fn get_difficulties(folder_path: &PathBuf) -> Result<Vec<BeatmapInfo>, Box<dyn std::error::Error>> {
-    let mut beatmaps = Vec::new();
+/// Like `load_image_texture`, but blurred by `blur` (0-100) for use behind the playfield
+fn load_background_texture(path: &Path, blur: i32) -> Option<Texture2D> {
+    if blur <= 0 {
+        return load_image_texture(path);
+    }
+
+    match image::open(path) {
+        Ok(img) => {
+            // Blur at quarter resolution; it gets upscaled with linear filtering anyway
+            // and a full-size gaussian on a 1080p image takes seconds
+            let small = img.resize(
+                (img.width() / 4).max(1),
+                (img.height() / 4).max(1),
+                image::imageops::FilterType::Triangle,
+            );
+            let blurred = small.blur(blur as f32 / 10.0).to_rgba8();
+            Some(Texture2D::from_rgba8(blurred.width() as u16, blurred.height() as u16, blurred.as_raw()))
+        }
+        Err(e) => {
+            eprintln!("Failed to load background image {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Parse one `[TimingPoints]` line: time,beatLength,meter,sampleSet,sampleIndex,volume,uninherited,effects.
+/// Inherited (negative beat length) points take beat length and meter from the previous uninherited point.
+fn parse_timing_point(line: &str, previous: &[TimingPoint]) -> Option<TimingPoint> {
+    let p: Vec<&str> = line.split(',').collect();
+    if p.len() < 2 {
+        return None;
+    }
     
-    for entry in fs::read_dir(folder_path)? {
-        let entry = entry?;
-        let path = entry.path();
+    let time: f32 = p[0].trim().parse().unwrap_or(0.0);
+    let val: f32 = p[1].trim().parse().unwrap_or(500.0);
+    let meter: i32 = p.get(2).and_then(|v| v.trim().parse().ok()).filter(|m| *m > 0).unwrap_or(4);
+    let sample_set: i32 = p.get(3).and_then(|v| v.trim().parse().ok()).unwrap_or(0);
+    let sample_index: i32 = p.get(4).and_then(|v| v.trim().parse().ok()).unwrap_or(0);
+    let effects: i32 = p.get(7).and_then(|v| v.trim().parse().ok()).unwrap_or(0);
+    let volume: f32 = p.get(5).and_then(|v| v.trim().parse().ok()).unwrap_or(100.0);
+    // Old maps omit the uninherited field and rely on the sign of the beat length
+    let uninherited = p.get(6).map(|v| v.trim() != "0").unwrap_or(true) && val > 0.0;
+    
+    if uninherited {
+        Some(TimingPoint { time, beat_length: val, velocity_mult: 1.0, meter, sample_set, sample_index, volume, uninherited, effects })
+    } else {
+        let parent = previous.iter().rev().find(|tp| tp.uninherited);
+        Some(TimingPoint {
+            time,
+            beat_length: parent.map(|tp| tp.beat_length).unwrap_or(500.0),
+            velocity_mult: if val < 0.0 { -100.0 / val } else { 1.0 },
+            meter: parent.map(|tp| tp.meter).unwrap_or(4),
+            sample_set,
+            sample_index,
+            volume,
+            uninherited,
+            effects,
+        })
+    }
+}
+
+/// Parse, sort and (if missing) default the `[TimingPoints]` section
+pub fn parse_timing_points(content: &str) -> Vec<TimingPoint> {
+    let mut timing_points: Vec<TimingPoint> = Vec::new();
+    let mut section = "";
+    
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") { continue; }
+        if line.starts_with("[") { section = line; continue; }
         
-        if path.extension().and_then(|s| s.to_str()) == Some("osu") {
-            let mut content = String::new();
-            fs::File::open(&path)?.read_to_string(&mut content)?;
-            
-            let mut version = String::from("Unknown");
-            
-            for line in content.lines() {
-                let line = line.trim();
-                if line.starts_with("Version:") {
-                    version = line.split(':').nth(1).unwrap_or("Unknown").trim().to_string();
-                    break;
-                }
+        if section == "[TimingPoints]" {
+            if let Some(point) = parse_timing_point(line, &timing_points) {
+                timing_points.push(point);
             }
-            
-            beatmaps.push(BeatmapInfo { path, version });
         }
     }
     
-    Ok(beatmaps)
+    timing_points.sort_by(|a, b| a.time.total_cmp(&b.time));
+    if timing_points.is_empty() {
+        timing_points.push(TimingPoint {
+            time: 0.0,
+            beat_length: 500.0,
+            velocity_mult: 1.0,
+            meter: 4,
+            sample_set: 0,
+            sample_index: 0,
+            volume: 100.0,
+            uninherited: true,
+            effects: 0,
+        });
+    }
+    timing_points
 }
 
-pub async fn load_map(osu_path: PathBuf, stream: &OutputStreamHandle, force_key_count: usize) -> Result<(GameState, Sink), Box<dyn std::error::Error>> {
-    let mut osu_content = String::new();
-    fs::File::open(&osu_path)?.read_to_string(&mut osu_content)?;
+/// A `[HitObjects]` entry reduced to what a mania chart needs (times in ms)
+#[derive(Debug, Clone, PartialEq)]
+pub struct HitObject {
+    pub time: f32,
+    pub end_time: Option<f32>, // hold end, or computed slider end
+    pub lane: usize,
+    pub hitsound: i32,
+    pub sample: String, // hitSample field, kept verbatim so saving doesn't drop custom samples
+}
 
-    let mut audio_filename = String::new();
-    let mut slider_multiplier = 1.4;
-    let mut bg_texture: Option<Texture2D> = None;
-    let mut section = "";
-    
-    for line in osu_content.lines() {
+/// Length of a slider in ms: `slides` passes over `pixel_length` at the slider velocity in effect
+pub fn slider_duration(time_ms: f32, slides: f32, pixel_length: f32, timing_points: &[TimingPoint], slider_multiplier: f32) -> f32 {
+    let (beat_length, velocity_mult) = timing_points.iter()
+        .rev()
+        .find(|tp| tp.time <= time_ms)
+        .map(|tp| (tp.beat_length, tp.velocity_mult))
+        .unwrap_or((500.0, 1.0));
+
+    let base_velocity = slider_multiplier * 100.0 * velocity_mult;
+    (pixel_length / base_velocity) * beat_length * slides
+}
+
+/// Parse `[HitObjects]`, mapping x to one of `key_count` lanes. Circles are taps and holds are LNs.
+/// Like osu!'s converter for mania maps, a spinner is an LN in the lane under its x and a slider
+/// an LN over its length; a slider with repeats becomes a tap on each of its nodes instead.
+/// Lines that can't be played are skipped and reported in the returned warnings.
+pub fn parse_hit_objects(content: &str, key_count: usize, timing_points: &[TimingPoint], slider_multiplier: f32) -> (Vec<HitObject>, Vec<String>) {
+    let mut objects = Vec::new();
+    let mut warnings = Vec::new();
+    let mut in_hit_objects = false;
+
+    for (number, line) in content.lines().enumerate() {
+        if line.contains("[HitObjects]") { in_hit_objects = true; continue; }
         let line = line.trim();
-        if line.starts_with("[") { section = line; continue; }
+        if in_hit_objects && line.starts_with('[') { break; }
+        if !in_hit_objects || line.is_empty() { continue; }
+        let mut warn = |problem: &str| warnings.push(format!("line {}: {} ({})", number + 1, problem, line));
 
-        if section == "[Events]" && line.starts_with("0,0,\"") {
-            let start = line.find('"').unwrap() + 1;
-            let end = line[start..].find('"').unwrap() + start;
-            let bg_file = line[start..end].to_string();
-            let bg_path = osu_path.parent().unwrap().join(bg_file);
-
-            // Try to load texture - only PNG/JPG supported by macroquad
-            if bg_path.exists() {
-                // Check file extension first
-                let ext = bg_path.extension()
-                    .and_then(|e| e.to_str())
-                    .map(|e| e.to_lowercase());
-                
-                match ext.as_deref() {
-                    Some("png") | Some("jpg") | Some("jpeg") => {
-                        // Try loading the texture
-                        match load_texture(bg_path.to_str().unwrap()).await {
-                            Ok(tex) => {
-                                bg_texture = Some(tex);
-                            },
-                            Err(e) => {
-                                eprintln!("Failed to load background image {}: {}", bg_path.display(), e);
-                                bg_texture = None;
-                            }
-                        }
-                    }
-                    Some(other) => {
-                        eprintln!("Background image format '{}' not supported (only PNG/JPG/JPEG work). Using black background.", other);
-                        bg_texture = None;
+        let p: Vec<&str> = line.split(',').collect();
+        if p.len() < 4 {
+            warn("too few fields");
+            continue;
+        }
+        let (Ok(x), Ok(time_ms), Ok(obj_type)) = (p[0].parse::<f32>(), p[2].parse::<f32>(), p[3].parse::<i32>()) else {
+            warn("unreadable position, time or type");
+            continue;
+        };
+        let hitsound: i32 = p.get(4).and_then(|v| v.parse().ok()).unwrap_or(0);
+
+        let lane = ((x * key_count as f32) / 512.0).floor() as usize;
+        let lane = lane.clamp(0, key_count - 1);
+
+        let sample_at = |index: usize| {
+            p.get(index).filter(|s| s.contains(':')).map(|s| s.to_string()).unwrap_or_else(|| String::from("0:0:0:0:"))
+        };
+
+        // Bit 2 (new combo) and bits 4-6 (combo colour skip) only matter in osu!standard
+        match obj_type & (1 | 2 | 8 | 128) {
+            1 => objects.push(HitObject { time: time_ms, end_time: None, lane, hitsound, sample: sample_at(5) }),
+            128 => {
+                // endTime:hitSample
+                let Some((end, sample)) = p.get(5).map(|f| f.split_once(':').unwrap_or((f, ""))) else {
+                    warn("hold without an end time");
+                    continue;
+                };
+                match end.parse::<f32>() {
+                    Ok(end) if end > time_ms => {
+                        let sample = if sample.contains(':') { sample.to_string() } else { String::from("0:0:0:0:") };
+                        objects.push(HitObject { time: time_ms, end_time: Some(end), lane, hitsound, sample });
                     }
-                    None => {
-                        eprintln!("Background image has no extension. Skipping.");
-                        bg_texture = None;
+                    Ok(_) => warn("hold ends before it starts"),
+                    Err(_) => warn("unreadable hold end time"),
+                }
+            }
+            2 => {
+                let (Some(Ok(slides)), Some(Ok(pixel_length))) = (p.get(6).map(|v| v.parse::<i32>()), p.get(7).map(|v| v.parse::<f32>())) else {
+                    warn("slider without a repeat count or length");
+                    continue;
+                };
+                if slides < 1 || pixel_length <= 0.0 {
+                    warn("slider with no length");
+                    continue;
+                }
+                let span = slider_duration(time_ms, 1.0, pixel_length, timing_points, slider_multiplier);
+                let sample = sample_at(10);
+                if slides == 1 {
+                    objects.push(HitObject { time: time_ms, end_time: Some(time_ms + span), lane, hitsound, sample });
+                } else {
+                    // Head, repeats and tail each get a tap with that node's hitsound
+                    let edge_sounds: Vec<i32> = p.get(8).map(|e| e.split('|').filter_map(|s| s.parse().ok()).collect()).unwrap_or_default();
+                    for node in 0..=slides as usize {
+                        let hitsound = edge_sounds.get(node).copied().unwrap_or(hitsound);
+                        objects.push(HitObject { time: time_ms + span * node as f32, end_time: None, lane, hitsound, sample: sample.clone() });
                     }
                 }
-            } else {
-                eprintln!("Background image file not found: {}", bg_path.display());
             }
-            break;
+            8 => match p.get(5).map(|v| v.parse::<f32>()) {
+                Some(Ok(end)) if end > time_ms => {
+                    objects.push(HitObject { time: time_ms, end_time: Some(end), lane, hitsound, sample: sample_at(6) });
+                }
+                Some(Ok(_)) => warn("spinner ends before it starts"),
+                _ => warn("spinner without an end time"),
+            },
+            _ => warn(&format!("unknown object type {}", obj_type)),
         }
     }
 
-    let mut timing_points: Vec<(f32, f32, f32)> = Vec::new();
+    objects.sort_by(|a, b| a.time.total_cmp(&b.time));
+    (objects, warnings)
+}
 
-    section = "";
-    for line in osu_content.lines() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with("//") { continue; }
-        if line.starts_with("[") { section = line; continue; }
+/// x coordinate osu! uses for a mania lane (the center of the lane's column)
+pub fn lane_x(lane: usize, key_count: usize) -> i32 {
+    ((512 * lane + 256) / key_count) as i32
+}
+
+/// First `Key: value` line anywhere in the file
+pub fn find_value(content: &str, key: &str) -> Option<String> {
+    content.lines()
+        .map(|l| l.trim())
+        .find(|l| l.split(':').next().map(|k| k.trim()) == Some(key))
+        .and_then(|l| l.split_once(':'))
+        .map(|(_, v)| v.trim().to_string())
+}
+
+/// Everything about a mania chart that survives a trip through `writer::write_osu`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Beatmap {
+    // [General]
+    pub audio_filename: String,
+    pub audio_lead_in: i32,
+    pub preview_time: i32,
+    pub sample_set: String,
+    // [Metadata]
+    pub title: String,
+    pub title_unicode: String,
+    pub artist: String,
+    pub artist_unicode: String,
+    pub creator: String,
+    pub version: String,
+    pub source: String,
+    pub tags: String,
+    pub beatmap_id: i32,
+    pub beatmap_set_id: i32,
+    // [Difficulty]
+    pub hp_drain_rate: f32,
+    pub key_count: usize,
+    pub overall_difficulty: f32,
+    pub slider_multiplier: f32,
+    pub slider_tick_rate: f32,
+    /// `[Events]` lines kept verbatim (background, video, storyboard)
+    pub events: Vec<String>,
+    pub timing_points: Vec<TimingPoint>,
+    pub hit_objects: Vec<HitObject>,
+    /// Skipped `[HitObjects]` lines and why; not written back
+    pub warnings: Vec<String>,
+}
+
+impl Default for Beatmap {
+    fn default() -> Self {
+        Self {
+            audio_filename: String::from("audio.mp3"),
+            audio_lead_in: 0,
+            preview_time: -1,
+            sample_set: String::from("Normal"),
+            title: String::new(),
+            title_unicode: String::new(),
+            artist: String::new(),
+            artist_unicode: String::new(),
+            creator: String::new(),
+            version: String::new(),
+            source: String::new(),
+            tags: String::new(),
+            beatmap_id: 0,
+            beatmap_set_id: -1,
+            hp_drain_rate: 8.0,
+            key_count: 4,
+            overall_difficulty: 8.0,
+            slider_multiplier: 1.4,
+            slider_tick_rate: 1.0,
+            events: Vec::new(),
+            timing_points: Vec::new(),
+            hit_objects: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+}
+
+/// Parse a whole .osu file. Non-mania maps are converted to `fallback_keys` lanes.
+pub fn parse_beatmap(content: &str, fallback_keys: usize) -> Beatmap {
+    let defaults = Beatmap::default();
+    let text = |key: &str, default: &str| find_value(content, key).unwrap_or_else(|| default.to_string());
+    let number = |key: &str| find_value(content, key).and_then(|v| v.parse::<f32>().ok());
+
+    // 0 standard, 1 taiko, 2 catch, 3 mania
+    let mode = find_value(content, "Mode").and_then(|m| m.parse::<i32>().ok()).unwrap_or(0);
+    let key_count = if mode == 3 {
+        number("CircleSize").map(|cs| (cs.round() as usize).clamp(1, 10)).unwrap_or(fallback_keys)
+    } else {
+        fallback_keys
+    };
+    let slider_multiplier = number("SliderMultiplier").unwrap_or(defaults.slider_multiplier);
+
+    let mut events = Vec::new();
+    let mut in_events = false;
+    for line in content.lines() {
+        let trimmed = line.trim_end();
+        if trimmed.trim_start().starts_with('[') {
+            in_events = trimmed.trim_start() == "[Events]";
+            continue;
+        }
+        if in_events && !trimmed.trim().is_empty() && !trimmed.trim_start().starts_with("//") {
+            events.push(trimmed.to_string());
+        }
+    }
+
+    let timing_points = parse_timing_points(content);
+    let (hit_objects, warnings) = match mode {
+        3 => parse_hit_objects(content, key_count, &timing_points, slider_multiplier),
+        1 => (convert::convert_taiko(content, key_count, &timing_points, slider_multiplier), Vec::new()),
+        _ => (convert::convert_standard(content, key_count, &timing_points, slider_multiplier), Vec::new()),
+    };
+
+    Beatmap {
+        audio_filename: text("AudioFilename", &defaults.audio_filename),
+        audio_lead_in: number("AudioLeadIn").map(|v| v as i32).unwrap_or(defaults.audio_lead_in),
+        preview_time: number("PreviewTime").map(|v| v as i32).unwrap_or(defaults.preview_time),
+        sample_set: text("SampleSet", &defaults.sample_set),
+        title: text("Title", ""),
+        title_unicode: text("TitleUnicode", ""),
+        artist: text("Artist", ""),
+        artist_unicode: text("ArtistUnicode", ""),
+        creator: text("Creator", ""),
+        version: text("Version", ""),
+        source: text("Source", ""),
+        tags: text("Tags", ""),
+        beatmap_id: number("BeatmapID").map(|v| v as i32).unwrap_or(defaults.beatmap_id),
+        beatmap_set_id: number("BeatmapSetID").map(|v| v as i32).unwrap_or(defaults.beatmap_set_id),
+        hp_drain_rate: number("HPDrainRate").unwrap_or(defaults.hp_drain_rate),
+        key_count,
+        overall_difficulty: number("OverallDifficulty").unwrap_or(defaults.overall_difficulty),
+        slider_multiplier,
+        slider_tick_rate: number("SliderTickRate").unwrap_or(defaults.slider_tick_rate),
+        events,
+        timing_points,
+        hit_objects,
+        warnings,
+    }
+}
+
+pub struct BeatmapInfo {
+    pub path: PathBuf,
+    pub version: String,
+    pub chart: Option<usize>, // chart index for .sm/.ssc files, None for .osu
+}
+
+/// Chart file formats that can be played
+#[derive(Clone, Copy, PartialEq)]
+pub enum ChartFormat {
+    Osu,
+    StepMania { ssc: bool },
+    Quaver,
+    Bms,
+}
+
+impl ChartFormat {
+    pub fn of(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "osu" => Some(ChartFormat::Osu),
+            "sm" => Some(ChartFormat::StepMania { ssc: false }),
+            "ssc" => Some(ChartFormat::StepMania { ssc: true }),
+            "qua" => Some(ChartFormat::Quaver),
+            "bms" | "bme" | "bml" => Some(ChartFormat::Bms),
+            _ => None,
+        }
+    }
+}
+
+pub fn get_difficulties(folder_path: &PathBuf) -> Result<Vec<BeatmapInfo>, Box<dyn std::error::Error>> {
+    let mut beatmaps = Vec::new();
+    
+    for entry in fs::read_dir(folder_path)? {
+        let entry = entry?;
+        let path = entry.path();
         
-        match section {
-            "[General]" => if line.starts_with("AudioFilename:") {
-                audio_filename = line.split(':').nth(1).unwrap().trim().to_string();
-            },
-            "[Difficulty]" => {
-                if line.starts_with("SliderMultiplier:") {
-                    slider_multiplier = line.split(':').nth(1).unwrap().trim().parse().unwrap_or(1.4);
-                }
-            },
-            "[TimingPoints]" => {
-                let p: Vec<&str> = line.split(',').collect();
-                if p.len() >= 2 {
-                    let time: f32 = p[0].parse().unwrap_or(0.0);
-                    let val: f32 = p[1].parse().unwrap_or(500.0);
-                    
-                    if val > 0.0 {
-                        timing_points.push((time, val, 1.0));
-                    } else {
-                        let velocity_mult = -100.0 / val;
-                        let beat_length = timing_points.iter()
-                            .rev()
-                            .find(|(_, bl, _)| *bl > 0.0)
-                            .map(|(_, bl, _)| *bl)
-                            .unwrap_or(500.0);
-                        timing_points.push((time, beat_length, velocity_mult));
+        match ChartFormat::of(&path) {
+            Some(ChartFormat::Osu) => {
+                let mut content = String::new();
+                fs::File::open(&path)?.read_to_string(&mut content)?;
+                
+                let mut version = String::from("Unknown");
+                
+                for line in content.lines() {
+                    let line = line.trim();
+                    if line.starts_with("Version:") {
+                        version = line.split(':').nth(1).unwrap_or("Unknown").trim().to_string();
+                        break;
                     }
                 }
-            },
-            _ => {}
+                
+                beatmaps.push(BeatmapInfo { path, version, chart: None });
+            }
+            Some(ChartFormat::StepMania { ssc }) => {
+                let content = String::from_utf8_lossy(&fs::read(&path)?).to_string();
+                for (label, index) in stepmania::list_charts(&content, ssc) {
+                    beatmaps.push(BeatmapInfo { path: path.clone(), version: format!("[SM] {}", label), chart: Some(index) });
+                }
+            }
+            Some(ChartFormat::Quaver) => {
+                let content = fs::read_to_string(&path)?;
+                let version = format!("[Quaver] {}", quaver::difficulty_name(&content));
+                beatmaps.push(BeatmapInfo { path, version, chart: None });
+            }
+            Some(ChartFormat::Bms) => {
+                // BMS files are usually Shift-JIS; only the ASCII parts matter for playing
+                let content = String::from_utf8_lossy(&fs::read(&path)?).to_string();
+                let version = format!("[BMS] {}", bms::chart_label(&content));
+                beatmaps.push(BeatmapInfo { path, version, chart: None });
+            }
+            None => {}
         }
     }
-    
-    if timing_points.is_empty() {
-        timing_points.push((0.0, 500.0, 1.0));
+    Ok(beatmaps)
+}
+
+pub async fn load_map(info: &BeatmapInfo, stream: Option<&OutputStreamHandle>, force_key_count: usize, options: &GameOptions) -> Result<(GameState, Sink, Vec<String>), Box<dyn std::error::Error>> {
+    let osu_path = &info.path;
+    let osu_content = String::from_utf8_lossy(&fs::read(osu_path)?).to_string();
+
+    // Simfiles are converted to the same beatmap model, so everything below is shared
+    let format = ChartFormat::of(osu_path).unwrap_or(ChartFormat::Osu);
+    let beatmap = match format {
+        ChartFormat::StepMania { ssc } => stepmania::parse_chart(&osu_content, ssc, info.chart.unwrap_or(0), force_key_count)?,
+        ChartFormat::Quaver => quaver::parse_qua(&osu_content, force_key_count)?,
+        ChartFormat::Bms => bms::parse_bms(&osu_content, force_key_count)?,
+        ChartFormat::Osu => {
+            let mut beatmap = parse_beatmap(&osu_content, force_key_count);
+            // Lanes always follow the selected key mode, whatever the map's own key count
+            if beatmap.key_count != force_key_count {
+                (beatmap.hit_objects, beatmap.warnings) = parse_hit_objects(&osu_content, force_key_count, &beatmap.timing_points, beatmap.slider_multiplier);
+            }
+            beatmap
+        }
+    };
+
+    // Background event: 0,0,"file",x,y (the type may also be spelled out)
+    let mut bg_texture: Option<Texture2D> = None;
+    let background = beatmap.events.iter()
+        .map(|line| line.trim())
+        .find(|line| line.starts_with("0,") || line.starts_with("Background,"))
+        .and_then(|line| line.split(',').nth(2))
+        .map(|file| file.trim().trim_matches('"').replace('\\', "/"));
+    if let Some(bg_file) = background {
+        let bg_path = osu_path.parent().unwrap().join(bg_file);
+        if bg_path.exists() {
+            bg_texture = load_background_texture(&bg_path, options.background_blur);
+        } else {
+            eprintln!("Background image file not found: {}", bg_path.display());
+        }
     }
 
+    let timing_points = &beatmap.timing_points;
+
     let folder_path = osu_path.parent().unwrap();
-    let audio_path = folder_path.join(audio_filename);
-    let source = Decoder::new(BufReader::new(fs::File::open(audio_path)?))?;
-    let (sr, ch) = (source.sample_rate(), source.channels());
-    
-    // Collect samples to calculate duration
-    let samples: Vec<f32> = source.convert_samples().collect();
-    let song_duration = samples.len() as f32 / (sr as f32 * ch as f32);
+    // Keysounded charts (BMS) have no music file; the sink then plays silence
+    let (samples, sr, ch): (Vec<f32>, u32, u16) = if beatmap.audio_filename.is_empty() {
+        (Vec::new(), 44100, 2)
+    } else {
+        let audio_path = folder_path.join(&beatmap.audio_filename);
+        let source = Decoder::new(BufReader::new(fs::File::open(audio_path)?))?;
+        let (sr, ch) = (source.sample_rate(), source.channels());
+        // Collect samples to calculate duration
+        (source.convert_samples().collect(), sr, ch)
+    };
+    let mut song_duration = samples.len() as f32 / (sr as f32 * ch as f32);
     
-    // Create a new source from the samples and play it through a Sink
-    let sink = Sink::try_new(stream)?;
+    // Create a new source from the samples and play it through a Sink.
+    // Without an output device the sink is idle and nothing is heard.
+    let sink = match stream {
+        Some(handle) => Sink::try_new(handle)?,
+        None => Sink::new_idle().0,
+    };
     sink.append(rodio::buffer::SamplesBuffer::new(ch, sr, samples));
 
+    let mut audio_system = AudioSystem::new(stream);
+    let mut keysounds = HashMap::new();
+
     let mut notes = Vec::new();
-    let mut in_hit_objects = false;
 
-    for line in osu_content.lines() {
-        if line.contains("[HitObjects]") { in_hit_objects = true; continue; }
-        if in_hit_objects && !line.trim().is_empty() {
-            let p: Vec<&str> = line.split(',').collect();
-            if p.len() < 4 { continue; }
-
-            let x: f32 = p[0].parse().unwrap_or(0.0);
-            let time_ms: f32 = p[2].parse().unwrap_or(0.0);
-            let obj_type: i32 = p[3].parse().unwrap_or(0);
-            
-            let lane = ((x * force_key_count as f32) / 512.0).floor() as usize;
-            let lane = lane.clamp(0, force_key_count - 1);
-
-            let mut end_time_ms = 0.0;
-            
-            let is_hold = (obj_type & 128) != 0;
-            let is_slider = (obj_type & 2) != 0;
-
-            if is_hold && p.len() > 5 {
-                let end_str = p[5].split(':').next().unwrap_or("0");
-                end_time_ms = end_str.parse::<f32>().unwrap_or(0.0);
-            } else if is_slider && p.len() >= 8 {
-                let slides: f32 = p[6].parse().unwrap_or(1.0);
-                let pixel_length: f32 = p[7].parse().unwrap_or(0.0);
-                
-                let (beat_length, velocity_mult) = timing_points.iter()
-                    .rev()
-                    .find(|(time, _, _)| *time <= time_ms)
-                    .map(|(_, bl, vm)| (*bl, *vm))
-                    .unwrap_or((500.0, 1.0));
-                
-                let base_velocity = slider_multiplier * 100.0 * velocity_mult;
-                let duration_ms = (pixel_length / base_velocity) * beat_length * slides;
-                end_time_ms = time_ms + duration_ms;
-            }
+    for object in &beatmap.hit_objects {
+        // Hitsound volume comes from whichever timing point is active at the note
+        let hitsound_volume = timing_points.iter()
+            .rev()
+            .find(|tp| tp.time <= object.time)
+            .or(timing_points.first())
+            .map(|tp| (tp.volume / 100.0).clamp(0.0, 1.0))
+            .unwrap_or(1.0);
+
+        // hitSample is normalSet:additionSet:index:volume:filename; a volume of 0 means the timing point's
+        let sample: Vec<&str> = object.sample.split(':').collect();
+        let hitsound_volume = match sample.get(3).and_then(|v| v.parse::<f32>().ok()) {
+            Some(volume) if volume > 0.0 => (volume / 100.0).clamp(0.0, 1.0),
+            _ => hitsound_volume,
+        };
+        let keysound = sample.get(4).and_then(|file| load_keysound(&mut audio_system, &mut keysounds, folder_path, file));
+
+        let start_time = object.time / 1000.0;
+        let end_time = object.end_time.unwrap_or(0.0) / 1000.0;
+        let duration = end_time - start_time;
+        let is_ln = duration >= MIN_LN_DURATION;
+
+        notes.push(Note { 
+            start_time, 
+            end_time, 
+            lane: object.lane, 
+            hit: false, 
+            missed: false,
+            is_ln,
+            ln_head_hit: false,
+            ln_hold_broken: false,
+            ln_completed: false,
+            ln_head_judgment: None,
+            ln_tail_judgment: None,
+            ln_head_offset: 0.0,
+            ln_ticks: 0,
+            slider_sound_playing: false,
+            hitsound_volume,
+            keysound,
+            start_pos: 0.0,
+            end_pos: 0.0,
+        });
+    }
+    
+    let lane_count = notes.iter().map(|n| n.lane + 1).max().unwrap_or(0).max(force_key_count);
+    let lane_notes = lane_queues(&notes, lane_count);
 
-            let start_time = time_ms / 1000.0;
-            let end_time = end_time_ms / 1000.0;
-            let duration = end_time - start_time;
-            let is_ln = duration >= MIN_LN_DURATION;
-
-            notes.push(Note { 
-                start_time, 
-                end_time, 
-                lane, 
-                hit: false, 
-                missed: false,
-                is_ln,
-                ln_head_hit: false,
-                ln_hold_broken: false,
-                ln_completed: false,
-                ln_head_judgment: None,
-                ln_tail_judgment: None,
-                slider_sound_playing: false,
-            });
+    // Sample events: Sample,time,layer,"file",volume
+    let mut sample_events = Vec::new();
+    for line in &beatmap.events {
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        if fields.len() < 4 || (fields[0] != "Sample" && fields[0] != "5") {
+            continue;
+        }
+        let Ok(time) = fields[1].parse::<f32>() else { continue; };
+        let volume = fields.get(4).and_then(|v| v.parse::<f32>().ok()).unwrap_or(100.0);
+        if let Some(keysound) = load_keysound(&mut audio_system, &mut keysounds, folder_path, fields[3].trim_matches('"')) {
+            sample_events.push((time / 1000.0, keysound, (volume / 100.0).clamp(0.0, 1.0)));
         }
     }
+    sample_events.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    // Without a music file the song lasts as long as its notes and samples
+    let chart_end = notes.iter().map(|n| n.end_time.max(n.start_time))
+        .chain(sample_events.iter().map(|e| e.0))
+        .fold(0.0, f32::max);
+    song_duration = song_duration.max(chart_end);
+
+    // Scroll positions only depend on the map, so bake them into the notes once
+    let song_end_ms = notes.iter().map(|n| n.end_time.max(n.start_time)).fold(0.0, f32::max) * 1000.0;
+    let scroll_map = ScrollMap::build(timing_points, options.use_sv, options.bpm_scaled_scroll, song_end_ms);
+    for note in notes.iter_mut() {
+        note.start_pos = scroll_map.position_at(note.start_time);
+        note.end_pos = scroll_map.position_at(note.end_time.max(note.start_time));
+    }
+    let beat_lines = BeatLine::generate(timing_points, song_end_ms.max(song_duration * 1000.0), &scroll_map);
     
-    // Initialize audio system
-    let audio_system = AudioSystem::new(stream.clone());
+    // Background video and storyboard sprites
+    let storyboard = if format == ChartFormat::Osu {
+        Storyboard::load(folder_path, &osu_content)
+    } else {
+        Storyboard::default()
+    };
 
     let game_state = GameState {
         notes, 
         score: 0, 
         combo: 0, 
+        max_combo: 0,
+        combo_breaks: Vec::new(),
+        milestone_time: -10.0,
         last_judgment: "", 
         judgment_color: WHITE, 
         judgment_time: -1.0, 
@@ -256,7 +669,45 @@ pub async fn load_map(osu_path: PathBuf, stream: &OutputStreamHandle, force_key_
         speed_change_time: -10.0,
         speed_display_text: String::new(),
         audio: Some(audio_system),
+        storyboard,
+        scroll_map,
+        beat_lines,
+        sample_events,
+        next_sample_event: 0,
+        lane_heads: vec![0; lane_count],
+        holding: vec![None; lane_count],
+        lane_cursors: vec![0; lane_count],
+        lane_notes,
     };
     
-    Ok((game_state, sink))
-}
\ No newline at end of file
+    Ok((game_state, sink, beatmap.warnings))
+}
+
+/// Note indices per lane sorted by start time, for input resolution and the per-frame window
+pub fn lane_queues(notes: &[Note], lane_count: usize) -> Vec<Vec<usize>> {
+    let mut lanes = vec![Vec::new(); lane_count];
+    for (idx, note) in notes.iter().enumerate() {
+        lanes[note.lane].push(idx);
+    }
+    for queue in &mut lanes {
+        queue.sort_by(|&a, &b| notes[a].start_time.total_cmp(&notes[b].start_time));
+    }
+    lanes
+}
+/// Decode each keysound file once. BMS charts often name `.wav` files that ship as `.ogg`,
+/// so the other common extensions are tried as well.
+fn load_keysound(audio: &mut AudioSystem, loaded: &mut HashMap<String, Option<usize>>, folder: &Path, file: &str) -> Option<usize> {
+    if file.is_empty() {
+        return None;
+    }
+    if let Some(index) = loaded.get(file) {
+        return *index;
+    }
+    let path = folder.join(file.replace('\\', "/"));
+    let index = std::iter::once(path.clone())
+        .chain(["wav", "ogg", "flac", "mp3"].iter().map(|ext| path.with_extension(ext)))
+        .filter(|candidate| candidate.exists())
+        .find_map(|candidate| audio.load_keysound(&candidate));
+    loaded.insert(file.to_string(), index);
+    index
+}
@@ -0,0 +1,304 @@
+use crate::models::TimingPoint;
+use crate::parser::{Beatmap, HitObject};
+
+/// A `- Key: value` list entry, fields in file order
+type Item = Vec<(String, String)>;
+
+/// The subset of a .qua file we play: metadata plus the three object lists
+struct Qua {
+    fields: Vec<(String, String)>,
+    timing_points: Vec<Item>,
+    slider_velocities: Vec<Item>,
+    hit_objects: Vec<Item>,
+}
+
+impl Qua {
+    fn field(&self, key: &str) -> Option<&str> {
+        self.fields.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+}
+
+fn item_number(item: &Item, key: &str) -> Option<f32> {
+    item.iter().find(|(k, _)| k == key).and_then(|(_, v)| v.parse().ok())
+}
+
+/// Quaver omits zero values, so a missing StartTime means 0
+fn start_time(item: &Item) -> f32 {
+    item_number(item, "StartTime").unwrap_or(0.0)
+}
+
+/// Key count of a chart (`Mode: Keys4` / `Keys7`, plus one lane for the scratch key)
+fn chart_keys(qua: &Qua) -> usize {
+    let keys = qua.field("Mode")
+        .and_then(|m| m.trim_start_matches("Keys").parse().ok())
+        .unwrap_or(4);
+    if qua.field("HasScratchKey") == Some("true") { keys + 1 } else { keys }
+}
+
+/// Difficulty name for the difficulty list
+pub fn difficulty_name(content: &str) -> String {
+    parse_yaml(content).field("DifficultyName").unwrap_or("Unknown").to_string()
+}
+
+/// Convert a .qua chart into a beatmap with `key_count` lanes
+pub fn parse_qua(content: &str, key_count: usize) -> Result<Beatmap, Box<dyn std::error::Error>> {
+    let qua = parse_yaml(content);
+    let chart_keys = chart_keys(&qua);
+    if qua.timing_points.is_empty() {
+        return Err("chart has no timing points".into());
+    }
+
+    let point = |time: f32, beat_length: f32, velocity_mult: f32, uninherited: bool| TimingPoint {
+        time,
+        beat_length,
+        velocity_mult,
+        meter: 4,
+        sample_set: 0,
+        sample_index: 0,
+        volume: 100.0,
+        uninherited,
+        effects: 0,
+    };
+
+    // Scroll velocity changes, including the one in effect from the start
+    let initial_sv = qua.field("InitialScrollVelocity").and_then(|v| v.parse().ok()).unwrap_or(1.0);
+    let mut velocities: Vec<(f32, f32)> = qua.slider_velocities.iter()
+        .map(|sv| (start_time(sv), item_number(sv, "Multiplier").unwrap_or(0.0)))
+        .collect();
+    velocities.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let sv_at = |time: f32| velocities.iter().rev().find(|(t, _)| *t <= time).map(|(_, m)| *m).unwrap_or(initial_sv);
+
+    let mut timing_points = Vec::new();
+    let mut bpm_points: Vec<(f32, f32)> = qua.timing_points.iter()
+        .filter_map(|tp| Some((start_time(tp), item_number(tp, "Bpm").filter(|bpm| *bpm > 0.0)?)))
+        .collect();
+    bpm_points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    for &(time, bpm) in &bpm_points {
+        timing_points.push(point(time, 60000.0 / bpm, 1.0, true));
+        // In Quaver SV carries over BPM changes; in osu! a BPM change resets it
+        let sv = sv_at(time);
+        if sv != 1.0 {
+            timing_points.push(point(time, 60000.0 / bpm, sv, false));
+        }
+    }
+    for &(time, multiplier) in &velocities {
+        let beat_length = bpm_points.iter().rev().find(|(t, _)| *t <= time).or(bpm_points.first()).map(|(_, bpm)| 60000.0 / bpm).unwrap_or(500.0);
+        timing_points.push(point(time, beat_length, multiplier, false));
+    }
+    timing_points.sort_by(|a, b| a.time.total_cmp(&b.time).then(b.uninherited.cmp(&a.uninherited)));
+
+    let mut hit_objects: Vec<HitObject> = qua.hit_objects.iter()
+        .filter_map(|object| {
+            // Lanes are 1-based
+            let lane = item_number(object, "Lane")? as usize;
+            let lane = ((lane.max(1) - 1) * key_count / chart_keys).min(key_count - 1);
+            let time = start_time(object);
+            let end_time = item_number(object, "EndTime").filter(|end| *end > time);
+            Some(HitObject { time, end_time, lane, hitsound: 0, sample: String::from("0:0:0:0:") })
+        })
+        .collect();
+    hit_objects.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    let text = |key: &str| qua.field(key).unwrap_or("").to_string();
+    let events = match qua.field("BackgroundFile") {
+        Some(bg) if !bg.is_empty() => vec![format!("0,0,\"{}\",0,0", bg)],
+        _ => Vec::new(),
+    };
+
+    Ok(Beatmap {
+        audio_filename: text("AudioFile"),
+        preview_time: qua.field("SongPreviewTime").and_then(|v| v.parse().ok()).unwrap_or(-1),
+        title: text("Title"),
+        title_unicode: text("Title"),
+        artist: text("Artist"),
+        artist_unicode: text("Artist"),
+        creator: text("Creator"),
+        version: text("DifficultyName"),
+        source: text("Source"),
+        tags: text("Tags"),
+        key_count,
+        events,
+        timing_points,
+        hit_objects,
+        ..Beatmap::default()
+    })
+}
+
+/// Minimal YAML reader for .qua files: top-level `Key: value` pairs and the block lists
+/// `TimingPoints`, `SliderVelocities` and `HitObjects`. Nested lists inside an item
+/// (like `KeySounds`) are skipped.
+fn parse_yaml(content: &str) -> Qua {
+    let mut qua = Qua {
+        fields: Vec::new(),
+        timing_points: Vec::new(),
+        slider_velocities: Vec::new(),
+        hit_objects: Vec::new(),
+    };
+    let mut list: Option<&str> = None;
+    let mut items: Vec<Item> = Vec::new();
+    let mut item_indent: Option<usize> = None;
+
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+
+        // A top-level key ends any list (lists may be written flush with their key)
+        if indent == 0 && !trimmed.starts_with("- ") {
+            store_list(list, &mut items, &mut qua);
+            list = None;
+            let Some((key, value)) = split_pair(trimmed) else { continue; };
+            if value.is_empty() {
+                list = Some(match key.as_str() {
+                    "TimingPoints" => "TimingPoints",
+                    "SliderVelocities" => "SliderVelocities",
+                    "HitObjects" => "HitObjects",
+                    _ => "",
+                });
+                item_indent = None;
+            } else {
+                qua.fields.push((key, value));
+            }
+            continue;
+        }
+        if list.is_none() {
+            continue;
+        }
+
+        if let Some(rest) = trimmed.strip_prefix("- ") {
+            // The first item fixes the list's indentation; deeper dashes are nested lists
+            if indent == *item_indent.get_or_insert(indent) {
+                items.push(split_pair(rest).into_iter().collect());
+            }
+        } else if item_indent.is_some_and(|base| indent == base + 2) {
+            if let (Some(item), Some(pair)) = (items.last_mut(), split_pair(trimmed)) {
+                item.push(pair);
+            }
+        }
+    }
+    store_list(list, &mut items, &mut qua);
+
+    qua
+}
+
+fn store_list(list: Option<&str>, items: &mut Vec<Item>, qua: &mut Qua) {
+    let items = std::mem::take(items);
+    match list {
+        Some("TimingPoints") => qua.timing_points = items,
+        Some("SliderVelocities") => qua.slider_velocities = items,
+        Some("HitObjects") => qua.hit_objects = items,
+        _ => {}
+    }
+}
+
+fn split_pair(s: &str) -> Option<(String, String)> {
+    let (key, value) = s.split_once(':')?;
+    Some((key.trim().to_string(), unquote(value.trim())))
+}
+
+fn unquote(value: &str) -> String {
+    if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        value[1..value.len() - 1].replace("''", "'")
+    } else if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        value[1..value.len() - 1].replace("\\\"", "\"")
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUA: &str = "AudioFile: audio.mp3\n\
+        SongPreviewTime: 1234\n\
+        BackgroundFile: bg.png\n\
+        MapSetId: 77\n\
+        Mode: Keys7\n\
+        Title: 'It''s a test'\n\
+        Artist: \"Some \\\"One\\\"\"\n\
+        Creator: mapper\n\
+        DifficultyName: Hard\n\
+        Description:\n\
+        # comment\n\
+        TimingPoints:\n\
+        - Bpm: 120\n\
+        - StartTime: 2000\n  Bpm: 240\n\
+        SliderVelocities:\n\
+        \x20 - StartTime: 1000\n    Multiplier: 0.5\n\
+        HitObjects:\n\
+        - Lane: 1\n  KeySounds: []\n\
+        - StartTime: 500\n  Lane: 4\n  EndTime: 1500\n  KeySounds:\n  - Sample: 1\n    Volume: 100\n\
+        - StartTime: 750\n  Lane: 7\n  EndTime: 750\n";
+
+    /// (time, end time, lane) of every object
+    fn objects(beatmap: &Beatmap) -> Vec<(f32, Option<f32>, usize)> {
+        beatmap.hit_objects.iter().map(|o| (o.time, o.end_time, o.lane)).collect()
+    }
+
+    #[test]
+    fn yaml_reader_handles_quotes_lists_and_nesting() {
+        let qua = parse_yaml(QUA);
+        assert_eq!(qua.field("Title"), Some("It's a test"));
+        assert_eq!(qua.field("Artist"), Some("Some \"One\""));
+        assert_eq!(qua.field("MapSetId"), Some("77"));
+        assert_eq!(qua.timing_points.len(), 2);
+        // Indented and flush lists read the same
+        assert_eq!(qua.slider_velocities, vec![vec![
+            (String::from("StartTime"), String::from("1000")),
+            (String::from("Multiplier"), String::from("0.5")),
+        ]]);
+        // Nested KeySounds entries don't leak into the hit object
+        assert_eq!(qua.hit_objects.len(), 3);
+        assert!(qua.hit_objects[1].iter().all(|(k, _)| k != "Sample" && k != "Volume"));
+        assert_eq!(difficulty_name(QUA), "Hard");
+    }
+
+    #[test]
+    fn scroll_velocity_carries_across_bpm_changes() {
+        let beatmap = parse_qua(QUA, 4).unwrap();
+        let points: Vec<(f32, f32, f32, bool)> = beatmap.timing_points.iter()
+            .map(|tp| (tp.time, tp.beat_length, tp.velocity_mult, tp.uninherited))
+            .collect();
+        assert_eq!(points, vec![
+            (0.0, 500.0, 1.0, true),
+            (1000.0, 500.0, 0.5, false),
+            (2000.0, 250.0, 1.0, true),
+            (2000.0, 250.0, 0.5, false),
+        ]);
+
+        let with_initial = format!("InitialScrollVelocity: 2\n{}", QUA);
+        let first = &parse_qua(&with_initial, 4).unwrap().timing_points[1];
+        assert_eq!((first.time, first.velocity_mult, first.uninherited), (0.0, 2.0, false));
+    }
+
+    #[test]
+    fn lanes_fold_onto_the_key_count() {
+        let beatmap = parse_qua(QUA, 4).unwrap();
+        // Missing StartTime is 0, an EndTime at the start time is a tap
+        assert_eq!(objects(&beatmap), vec![(0.0, None, 0), (500.0, Some(1500.0), 1), (750.0, None, 3)]);
+        assert_eq!(beatmap.audio_filename, "audio.mp3");
+        assert_eq!(beatmap.preview_time, 1234);
+        assert_eq!(beatmap.events, vec![String::from("0,0,\"bg.png\",0,0")]);
+
+        let lanes: Vec<usize> = parse_qua(QUA, 7).unwrap().hit_objects.iter().map(|o| o.lane).collect();
+        assert_eq!(lanes, vec![0, 3, 6]);
+    }
+
+    #[test]
+    fn scratch_key_is_an_extra_lane() {
+        let content = "Mode: Keys4\nHasScratchKey: true\nTimingPoints:\n- Bpm: 100\nHitObjects:\n- Lane: 5\n- StartTime: 10\n  Lane: 1\n";
+        let lanes = |key_count| -> Vec<usize> {
+            parse_qua(content, key_count).unwrap().hit_objects.iter().map(|o| o.lane).collect()
+        };
+        assert_eq!(lanes(5), vec![4, 0]);
+        assert_eq!(lanes(4), vec![3, 0]);
+    }
+
+    #[test]
+    fn chart_without_timing_points_is_rejected() {
+        assert!(parse_qua("Mode: Keys4\nHitObjects:\n- Lane: 1\n", 4).is_err());
+    }
+}
@@ -0,0 +1,428 @@
+use crate::models::TimingPoint;
+use crate::parser::{Beatmap, HitObject};
+use std::collections::HashSet;
+
+/// One `#NOTES` block. Timing tags are only set when a .ssc chart overrides the song's timing.
+struct Chart {
+    steps_type: String,
+    difficulty: String,
+    meter: String,
+    bpms: Option<String>,
+    stops: Option<String>,
+    delays: Option<String>,
+    offset: Option<String>,
+    notes: String,
+}
+
+struct Simfile {
+    title: String,
+    artist: String,
+    music: String,
+    background: String,
+    bpms: String,
+    stops: String,
+    delays: String,
+    offset: String,
+    charts: Vec<Chart>,
+}
+
+/// Beat to song time (ms) conversion from BPM changes and stops. Also used by the BMS loader.
+pub struct Timing {
+    pub offset: f32,             // seconds, StepMania's #OFFSET (time of beat 0 is -offset)
+    pub bpms: Vec<(f32, f32)>,   // (beat, bpm)
+    pub stops: Vec<(f32, f32)>,  // (beat, seconds); the pause comes after notes on that beat
+    pub delays: Vec<(f32, f32)>, // (beat, seconds); the pause comes before notes on that beat
+}
+
+impl Timing {
+    pub fn time_at(&self, beat: f32) -> f32 {
+        let mut seconds = -self.offset;
+        for (i, &(start, bpm)) in self.bpms.iter().enumerate() {
+            if beat <= start && i > 0 {
+                break;
+            }
+            let end = self.bpms.get(i + 1).map(|&(b, _)| b).unwrap_or(f32::MAX).min(beat);
+            seconds += (end - start.min(end)) * 60.0 / bpm;
+        }
+        // Before the first BPM entry the first tempo applies
+        if let Some(&(first, bpm)) = self.bpms.first() {
+            if beat < first {
+                seconds -= (first - beat) * 60.0 / bpm;
+            }
+        }
+        seconds += self.stops.iter().filter(|(b, _)| *b < beat).map(|(_, s)| s).sum::<f32>();
+        seconds += self.delays.iter().filter(|(b, _)| *b <= beat).map(|(_, s)| s).sum::<f32>();
+        seconds * 1000.0
+    }
+
+    pub fn bpm_at(&self, beat: f32) -> f32 {
+        self.bpms.iter().rev().find(|(b, _)| *b <= beat).or(self.bpms.first()).map(|(_, bpm)| *bpm).unwrap_or(120.0)
+    }
+
+    /// osu! timing points: one uninherited point per BPM change, and for every stop/delay
+    /// an inherited point with zero velocity (a freeze, see `ScrollMap`) followed by an
+    /// uninherited point where scrolling resumes
+    pub fn timing_points(&self) -> Vec<TimingPoint> {
+        let point = |time: f32, bpm: f32, velocity_mult: f32, uninherited: bool| TimingPoint {
+            time,
+            beat_length: 60000.0 / bpm,
+            velocity_mult,
+            meter: 4,
+            sample_set: 0,
+            sample_index: 0,
+            volume: 100.0,
+            uninherited,
+            effects: 0,
+        };
+
+        let mut points = Vec::new();
+        for &(beat, bpm) in &self.bpms {
+            points.push(point(self.time_at(beat), bpm, 1.0, true));
+        }
+        for &(beat, seconds) in &self.stops {
+            let start = self.time_at(beat);
+            points.push(point(start, self.bpm_at(beat), 0.0, false));
+            points.push(point(start + seconds * 1000.0, self.bpm_at(beat), 1.0, true));
+        }
+        for &(beat, seconds) in &self.delays {
+            let end = self.time_at(beat);
+            points.push(point(end - seconds * 1000.0, self.bpm_at(beat), 0.0, false));
+            points.push(point(end, self.bpm_at(beat), 1.0, true));
+        }
+        points.sort_by(|a, b| a.time.total_cmp(&b.time).then(b.uninherited.cmp(&a.uninherited)));
+        points
+    }
+}
+
+/// `(label, chart index)` for every chart in a .sm/.ssc file, for the difficulty list
+pub fn list_charts(content: &str, is_ssc: bool) -> Vec<(String, usize)> {
+    parse_simfile(content, is_ssc).charts.iter().enumerate()
+        .map(|(i, chart)| (format!("{} {} ({})", chart.difficulty, chart.meter, chart.steps_type), i))
+        .collect()
+}
+
+/// Convert one chart of a simfile into a beatmap with `key_count` lanes
+pub fn parse_chart(content: &str, is_ssc: bool, index: usize, key_count: usize) -> Result<Beatmap, Box<dyn std::error::Error>> {
+    let simfile = parse_simfile(content, is_ssc);
+    let chart = simfile.charts.get(index).ok_or("chart not found in simfile")?;
+
+    let timing = Timing {
+        offset: chart.offset.as_deref().unwrap_or(&simfile.offset).trim().parse().unwrap_or(0.0),
+        bpms: parse_pairs(chart.bpms.as_deref().unwrap_or(&simfile.bpms)).into_iter().filter(|(_, bpm)| *bpm > 0.0).collect(),
+        stops: parse_pairs(chart.stops.as_deref().unwrap_or(&simfile.stops)).into_iter().filter(|(_, s)| *s > 0.0).collect(),
+        delays: parse_pairs(chart.delays.as_deref().unwrap_or(&simfile.delays)).into_iter().filter(|(_, s)| *s > 0.0).collect(),
+    };
+    if timing.bpms.is_empty() {
+        return Err("simfile has no BPMs".into());
+    }
+
+    let hit_objects = parse_note_data(&chart.notes, &timing, key_count);
+    let events = if simfile.background.is_empty() {
+        Vec::new()
+    } else {
+        vec![format!("0,0,\"{}\",0,0", simfile.background)]
+    };
+
+    Ok(Beatmap {
+        audio_filename: simfile.music,
+        title: simfile.title.clone(),
+        title_unicode: simfile.title,
+        artist: simfile.artist.clone(),
+        artist_unicode: simfile.artist,
+        version: format!("{} {}", chart.difficulty, chart.meter),
+        key_count,
+        events,
+        timing_points: timing.timing_points(),
+        hit_objects,
+        ..Beatmap::default()
+    })
+}
+
+/// Measures are separated by `,`; each measure's rows split its four beats evenly.
+/// 1 tap, 2 hold head, 4 roll head, 3 hold/roll tail, L lift (played as a tap).
+/// Mines, fakes and keysound-only rows are skipped.
+fn parse_note_data(notes: &str, timing: &Timing, key_count: usize) -> Vec<HitObject> {
+    let mut objects: Vec<HitObject> = Vec::new();
+    let mut open_holds: Vec<Option<usize>> = Vec::new(); // column -> index into objects
+    let mut taken: HashSet<(usize, i64)> = HashSet::new();
+
+    for (measure, measure_text) in notes.split(',').enumerate() {
+        let rows: Vec<String> = measure_text.lines()
+            .map(strip_row_extras)
+            .filter(|row| !row.is_empty())
+            .collect();
+
+        for (i, row) in rows.iter().enumerate() {
+            let beat = measure as f32 * 4.0 + 4.0 * i as f32 / rows.len() as f32;
+            let columns = row.chars().count();
+            if open_holds.len() < columns {
+                open_holds.resize(columns, None);
+            }
+
+            for (column, c) in row.chars().enumerate() {
+                let time = timing.time_at(beat);
+                match c {
+                    '1' | '2' | '4' | 'L' => {
+                        // Wider charts fold onto fewer lanes; drop notes that land on the same spot
+                        let lane = (column * key_count / columns).min(key_count - 1);
+                        if !taken.insert((lane, time.round() as i64)) {
+                            continue;
+                        }
+                        objects.push(HitObject { time, end_time: None, lane, hitsound: 0, sample: String::from("0:0:0:0:") });
+                        if c == '2' || c == '4' {
+                            open_holds[column] = Some(objects.len() - 1);
+                        }
+                    }
+                    '3' => {
+                        if let Some(index) = open_holds[column].take() {
+                            objects[index].end_time = Some(time);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    objects.sort_by(|a, b| a.time.total_cmp(&b.time));
+    objects
+}
+
+/// .ssc rows can carry `{attack}` / `[keysound]` annotations after a note; keep the note characters
+fn strip_row_extras(line: &str) -> String {
+    let mut row = String::new();
+    let mut depth = 0;
+    for c in line.trim().chars() {
+        match c {
+            '{' | '[' => depth += 1,
+            '}' | ']' => depth -= 1,
+            _ if depth == 0 && !c.is_whitespace() => row.push(c),
+            _ => {}
+        }
+    }
+    row
+}
+
+/// `beat=value,beat=value`
+fn parse_pairs(s: &str) -> Vec<(f32, f32)> {
+    let mut pairs: Vec<(f32, f32)> = s.split(',')
+        .filter_map(|pair| {
+            let (beat, value) = pair.split_once('=')?;
+            Some((beat.trim().parse().ok()?, value.trim().parse().ok()?))
+        })
+        .collect();
+    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+    pairs
+}
+
+/// Split a simfile into `#TAG:value;` pairs. Some files miss the `;` before the next tag,
+/// so a `#` at the start of a line also ends a value.
+fn tags(content: &str) -> Vec<(String, String)> {
+    let without_comments: String = content.lines()
+        .map(|line| line.split("//").next().unwrap_or(""))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut tags = Vec::new();
+    let mut rest = without_comments.as_str();
+    while let Some(start) = rest.find('#') {
+        rest = &rest[start + 1..];
+        let Some(colon) = rest.find(':') else { break; };
+        let tag = rest[..colon].trim().to_uppercase();
+        rest = &rest[colon + 1..];
+
+        let end = match (rest.find(';'), rest.find("\n#")) {
+            (Some(semi), Some(hash)) => semi.min(hash),
+            (Some(semi), None) => semi,
+            (None, Some(hash)) => hash,
+            (None, None) => rest.len(),
+        };
+        tags.push((tag, rest[..end].to_string()));
+        rest = &rest[end..];
+    }
+    tags
+}
+
+fn parse_simfile(content: &str, is_ssc: bool) -> Simfile {
+    let mut simfile = Simfile {
+        title: String::new(),
+        artist: String::new(),
+        music: String::new(),
+        background: String::new(),
+        bpms: String::new(),
+        stops: String::new(),
+        delays: String::new(),
+        offset: String::new(),
+        charts: Vec::new(),
+    };
+    // .ssc charts are a run of tags starting at #NOTEDATA
+    let mut current: Option<Chart> = None;
+
+    for (tag, value) in tags(content) {
+        let text = value.trim().to_string();
+
+        if let Some(chart) = current.as_mut() {
+            match tag.as_str() {
+                "STEPSTYPE" => chart.steps_type = text,
+                "DIFFICULTY" => chart.difficulty = text,
+                "METER" => chart.meter = text,
+                "BPMS" => chart.bpms = Some(text),
+                "STOPS" | "FREEZES" => chart.stops = Some(text),
+                "DELAYS" => chart.delays = Some(text),
+                "OFFSET" => chart.offset = Some(text),
+                "NOTES" => {
+                    chart.notes = text;
+                    simfile.charts.extend(current.take());
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        match tag.as_str() {
+            "TITLE" => simfile.title = text,
+            "ARTIST" => simfile.artist = text,
+            "MUSIC" => simfile.music = text,
+            "BACKGROUND" => simfile.background = text,
+            "BPMS" => simfile.bpms = text,
+            "STOPS" | "FREEZES" => simfile.stops = text,
+            "DELAYS" => simfile.delays = text,
+            "OFFSET" => simfile.offset = text,
+            "NOTEDATA" if is_ssc => current = Some(empty_chart()),
+            // .sm: #NOTES:type:description:difficulty:meter:radar:data;
+            "NOTES" if !is_ssc => {
+                let fields: Vec<&str> = value.splitn(6, ':').collect();
+                if fields.len() == 6 {
+                    simfile.charts.push(Chart {
+                        steps_type: fields[0].trim().to_string(),
+                        difficulty: fields[2].trim().to_string(),
+                        meter: fields[3].trim().to_string(),
+                        notes: fields[5].to_string(),
+                        ..empty_chart()
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    simfile
+}
+
+fn empty_chart() -> Chart {
+    Chart {
+        steps_type: String::new(),
+        difficulty: String::new(),
+        meter: String::new(),
+        bpms: None,
+        stops: None,
+        delays: None,
+        offset: None,
+        notes: String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ScrollMap;
+
+    const SM: &str = "#TITLE:Song;\n#ARTIST:Someone;\n#MUSIC:song.ogg;\n#OFFSET:0;\n#BPMS:0=120;\n#STOPS:;\n\
+        #NOTES:\n     dance-single:\n     :\n     Hard:\n     8:\n     0,0,0,0,0:\n\
+        1000\n0200\n0040\n0000\n,\n0030\n0300\n0000\n0000\n;\n\
+        #NOTES:\n     dance-single:\n     :\n     Challenge:\n     10:\n     0,0,0,0,0:\n\
+        0001\n0000\n,\n1000\n0000\n;\n";
+
+    const SSC: &str = "#VERSION:0.83;\n#TITLE:Song;\n#MUSIC:song.ogg;\n#OFFSET:0;\n#BPMS:0=120;\n\
+        #NOTEDATA:;\n#STEPSTYPE:dance-single;\n#DIFFICULTY:Easy;\n#METER:3;\n\
+        #NOTES:\n1000\n0000\n0000\n0000\n,\n0001\n0000\n0000\n0000\n;\n\
+        #NOTEDATA:;\n#STEPSTYPE:dance-single;\n#DIFFICULTY:Hard;\n#METER:9;\n#BPMS:0=240;\n#STOPS:4=0.5;\n\
+        #NOTES:\n0100[1]\n0000\n,\n0010\n0000\n0001\n0000\n;\n";
+
+    fn timing(bpms: Vec<(f32, f32)>, stops: Vec<(f32, f32)>, delays: Vec<(f32, f32)>) -> Timing {
+        Timing { offset: 0.0, bpms, stops, delays }
+    }
+
+    /// (time, end time, lane) of every object
+    fn objects(beatmap: &Beatmap) -> Vec<(f32, Option<f32>, usize)> {
+        beatmap.hit_objects.iter().map(|o| (o.time, o.end_time, o.lane)).collect()
+    }
+
+    #[test]
+    fn time_at_follows_bpm_changes() {
+        let timing = timing(vec![(0.0, 120.0), (4.0, 240.0)], Vec::new(), Vec::new());
+        assert_eq!(timing.time_at(0.0), 0.0);
+        assert_eq!(timing.time_at(4.0), 2000.0);
+        assert_eq!(timing.time_at(8.0), 3000.0);
+        // Before the first entry the first tempo applies
+        assert_eq!(timing.time_at(-1.0), -500.0);
+
+        let shifted = Timing { offset: 0.1, ..timing };
+        assert_eq!(shifted.time_at(0.0), -100.0);
+    }
+
+    #[test]
+    fn stops_pause_after_and_delays_before_their_beat() {
+        let stop = timing(vec![(0.0, 120.0)], vec![(4.0, 1.0)], Vec::new());
+        assert_eq!(stop.time_at(4.0), 2000.0);
+        assert_eq!(stop.time_at(5.0), 3500.0);
+
+        let delay = timing(vec![(0.0, 120.0)], Vec::new(), vec![(4.0, 1.0)]);
+        assert_eq!(delay.time_at(4.0), 3000.0);
+        assert_eq!(delay.time_at(5.0), 3500.0);
+    }
+
+    #[test]
+    fn stops_freeze_the_scroll() {
+        let timing = timing(vec![(0.0, 120.0)], vec![(4.0, 1.0)], Vec::new());
+        let points = timing.timing_points();
+        let times: Vec<(f32, f32, bool)> = points.iter().map(|tp| (tp.time, tp.velocity_mult, tp.uninherited)).collect();
+        assert_eq!(times, vec![(0.0, 1.0, true), (2000.0, 0.0, false), (3000.0, 1.0, true)]);
+
+        // Frozen for the whole stop, with or without SV and BPM scaling
+        for (use_sv, bpm_scaled) in [(true, false), (false, false), (true, true)] {
+            let map = ScrollMap::build(&points, use_sv, bpm_scaled, 10000.0);
+            assert_eq!(map.position_at(2.0), 2.0);
+            assert_eq!(map.position_at(2.9), 2.0);
+            assert_eq!(map.position_at(3.5), 2.5);
+        }
+    }
+
+    #[test]
+    fn holds_and_rolls_end_at_their_tails() {
+        let beatmap = parse_chart(SM, false, 0, 4).unwrap();
+        assert_eq!(objects(&beatmap), vec![
+            (0.0, None, 0),
+            (500.0, Some(2500.0), 1),
+            (1000.0, Some(2000.0), 2),
+        ]);
+        assert_eq!(beatmap.audio_filename, "song.ogg");
+        assert_eq!(beatmap.version, "Hard 8");
+    }
+
+    #[test]
+    fn every_chart_is_listed_and_loadable() {
+        let charts = list_charts(SM, false);
+        let labels: Vec<&str> = charts.iter().map(|(label, _)| label.as_str()).collect();
+        assert_eq!(labels, vec!["Hard 8 (dance-single)", "Challenge 10 (dance-single)"]);
+
+        // Two rows per measure: half a measure each
+        let beatmap = parse_chart(SM, false, 1, 4).unwrap();
+        assert_eq!(objects(&beatmap), vec![(0.0, None, 3), (2000.0, None, 0)]);
+        assert!(parse_chart(SM, false, 2, 4).is_err());
+    }
+
+    #[test]
+    fn ssc_charts_override_song_timing() {
+        let charts = list_charts(SSC, true);
+        assert_eq!(charts.len(), 2);
+        assert_eq!(charts[1].0, "Hard 9 (dance-single)");
+
+        let easy = parse_chart(SSC, true, 0, 4).unwrap();
+        assert_eq!(objects(&easy), vec![(0.0, None, 0), (2000.0, None, 3)]);
+
+        // 240 BPM with a half second stop on beat 4; the keysound annotation is dropped
+        let hard = parse_chart(SSC, true, 1, 4).unwrap();
+        assert_eq!(objects(&hard), vec![(0.0, None, 1), (1000.0, None, 2), (2000.0, None, 3)]);
+    }
+}
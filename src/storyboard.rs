@@ -0,0 +1,381 @@
+use image::codecs::gif::GifDecoder;
+use image::AnimationDecoder;
+use macroquad::prelude::*;
+use crate::models::BackgroundFit;
+use crate::parser::load_image_texture;
+use std::collections::HashMap;
+use std::fs;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+// osu! storyboards are authored on a 640x480 canvas
+const STORYBOARD_WIDTH: f32 = 640.0;
+const STORYBOARD_HEIGHT: f32 = 480.0;
+
+// Video frames decoded ahead of the playhead. Only these are in memory at once, and they all
+// go through a single texture, so the length of a GIF doesn't matter.
+const VIDEO_BUFFER_FRAMES: usize = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+enum CommandKind {
+    Fade,
+    Move,
+    MoveX,
+    MoveY,
+    Scale,
+}
+
+/// One storyboard command, times in ms. Easing is ignored (always linear).
+struct Command {
+    kind: CommandKind,
+    start: f32,
+    end: f32,
+    from: (f32, f32),
+    to: (f32, f32),
+}
+
+struct Sprite {
+    texture: Texture2D,
+    origin: (f32, f32), // 0.0-1.0 fraction of the texture size
+    x: f32,
+    y: f32,
+    commands: Vec<Command>,
+    visible_from: f32,
+    visible_to: f32,
+}
+
+/// Background video, decoded on a thread of its own while it plays and streamed into one texture.
+/// Only formats with a pure Rust decoder are supported (animated GIF).
+struct VideoBackground {
+    texture: Texture2D,
+    frames: Receiver<(image::RgbaImage, f32)>, // (frame, delay in ms)
+    offset: f32,
+    frame_end: f32, // video time the shown frame ends at
+    finished: bool, // every frame has been received
+}
+
+#[derive(Default)]
+pub struct Storyboard {
+    video: Option<VideoBackground>,
+    sprites: Vec<Sprite>,
+}
+
+impl Storyboard {
+    /// Collect video and sprite events from the .osu `[Events]` section and any .osb in the folder
+    pub fn load(folder: &Path, osu_content: &str) -> Self {
+        let mut event_lines: Vec<String> = Vec::new();
+        collect_event_lines(osu_content, &mut event_lines);
+
+        if let Ok(entries) = fs::read_dir(folder) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) == Some("osb") {
+                    if let Ok(content) = fs::read_to_string(&path) {
+                        collect_event_lines(&content, &mut event_lines);
+                    }
+                }
+            }
+        }
+
+        let mut storyboard = Storyboard::default();
+        let mut textures: HashMap<String, Option<Texture2D>> = HashMap::new();
+        // Sprite currently receiving commands, None if the last object was skipped
+        let mut current: Option<Sprite> = None;
+
+        for line in &event_lines {
+            let depth = line.chars().take_while(|c| *c == ' ' || *c == '_').count();
+            let trimmed = line.trim_start_matches([' ', '_']).trim_end();
+            let p: Vec<&str> = trimmed.split(',').collect();
+
+            if depth == 0 {
+                if let Some(sprite) = current.take() {
+                    storyboard.push_sprite(sprite);
+                }
+
+                match p[0] {
+                    "Video" | "1" if p.len() >= 3 && storyboard.video.is_none() => {
+                        let offset: f32 = p[1].trim().parse().unwrap_or(0.0);
+                        let file = unquote(p[2]);
+                        storyboard.video = VideoBackground::load(&folder.join(&file), offset);
+                    }
+                    "Sprite" | "4" | "Animation" | "6" if p.len() >= 6 => {
+                        // Fail layer never shows since there is no fail state
+                        if p[1] == "Fail" || p[1] == "1" {
+                            continue;
+                        }
+                        let mut file = unquote(p[3]);
+                        // Animations are drawn as their first frame
+                        if p[0] == "Animation" || p[0] == "6" {
+                            if let Some(dot) = file.rfind('.') {
+                                file.insert(dot, '0');
+                            }
+                        }
+                        let texture = textures
+                            .entry(file.clone())
+                            .or_insert_with(|| load_image_texture(&folder.join(&file)))
+                            .clone();
+
+                        if let Some(texture) = texture {
+                            current = Some(Sprite {
+                                texture,
+                                origin: parse_origin(p[2]),
+                                x: p[4].parse().unwrap_or(320.0),
+                                y: p[5].parse().unwrap_or(240.0),
+                                commands: Vec::new(),
+                                visible_from: f32::MAX,
+                                visible_to: f32::MIN,
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            } else if depth == 1 {
+                // Deeper lines belong to loops/triggers, which aren't supported
+                if let Some(sprite) = current.as_mut() {
+                    if let Some(command) = parse_command(&p) {
+                        sprite.visible_from = sprite.visible_from.min(command.start);
+                        sprite.visible_to = sprite.visible_to.max(command.end);
+                        sprite.commands.push(command);
+                    }
+                }
+            }
+        }
+        if let Some(sprite) = current.take() {
+            storyboard.push_sprite(sprite);
+        }
+
+        storyboard
+    }
+
+    fn push_sprite(&mut self, mut sprite: Sprite) {
+        // Sprites without commands are never shown in osu! either
+        if sprite.commands.is_empty() {
+            return;
+        }
+        sprite.commands.sort_by(|a, b| a.start.total_cmp(&b.start));
+        self.sprites.push(sprite);
+    }
+
+    /// Move the video to the given song time (ms). Call once per frame before drawing.
+    pub fn update(&mut self, time_ms: f32) {
+        if let Some(video) = &mut self.video {
+            video.update(time_ms);
+        }
+    }
+
+    /// True while a video frame covers the static background
+    pub fn video_active(&self, time_ms: f32) -> bool {
+        self.video.as_ref().is_some_and(|v| v.active(time_ms))
+    }
+
+    /// Draw video and sprites for the given song time (ms), behind the playfield
+    pub fn draw(&self, time_ms: f32, fit: BackgroundFit) {
+        if let Some(video) = self.video.as_ref().filter(|v| v.active(time_ms)) {
+            let frame = &video.texture;
+            let (x, y, w, h) = fit.dest_rect(frame.width(), frame.height());
+            draw_texture_ex(
+                frame, x, y, WHITE,
+                DrawTextureParams {
+                    dest_size: Some(vec2(w, h)),
+                    ..Default::default()
+                }
+            );
+        }
+
+        // Fit the 4:3 canvas to the screen height, centered horizontally
+        let scale = screen_height() / STORYBOARD_HEIGHT;
+        let offset_x = (screen_width() - STORYBOARD_WIDTH * scale) / 2.0;
+
+        for sprite in &self.sprites {
+            if time_ms < sprite.visible_from || time_ms > sprite.visible_to {
+                continue;
+            }
+
+            let opacity = sprite.value_at(CommandKind::Fade, time_ms, (1.0, 1.0)).0;
+            if opacity <= 0.0 {
+                continue;
+            }
+            let (mut x, mut y) = sprite.value_at(CommandKind::Move, time_ms, (sprite.x, sprite.y));
+            x = sprite.value_at(CommandKind::MoveX, time_ms, (x, x)).0;
+            y = sprite.value_at(CommandKind::MoveY, time_ms, (y, y)).0;
+            let sprite_scale = sprite.value_at(CommandKind::Scale, time_ms, (1.0, 1.0)).0;
+
+            let w = sprite.texture.width() * sprite_scale * scale;
+            let h = sprite.texture.height() * sprite_scale * scale;
+            let draw_x = offset_x + x * scale - w * sprite.origin.0;
+            let draw_y = y * scale - h * sprite.origin.1;
+
+            draw_texture_ex(
+                &sprite.texture, draw_x, draw_y, Color::new(1.0, 1.0, 1.0, opacity.min(1.0)),
+                DrawTextureParams {
+                    dest_size: Some(vec2(w, h)),
+                    ..Default::default()
+                }
+            );
+        }
+    }
+}
+
+impl Sprite {
+    /// Value of a command kind at `time`: before the first command its start value,
+    /// between commands the last end value, inside a command linearly interpolated.
+    fn value_at(&self, kind: CommandKind, time: f32, default: (f32, f32)) -> (f32, f32) {
+        let mut value: Option<(f32, f32)> = None;
+
+        for c in self.commands.iter().filter(|c| c.kind == kind) {
+            if time < c.start {
+                return value.unwrap_or(c.from);
+            }
+            if time < c.end {
+                let t = (time - c.start) / (c.end - c.start);
+                return (c.from.0 + (c.to.0 - c.from.0) * t, c.from.1 + (c.to.1 - c.from.1) * t);
+            }
+            value = Some(c.to);
+        }
+
+        value.unwrap_or(default)
+    }
+}
+
+impl VideoBackground {
+    fn load(path: &Path, offset: f32) -> Option<Self> {
+        let ext = path.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+
+        if ext.as_deref() != Some("gif") {
+            eprintln!("Video format of {} not supported (only GIF). Using static background.", path.display());
+            return None;
+        }
+
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("Failed to open video {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        let decoder = match GifDecoder::new(BufReader::new(file)) {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                eprintln!("Failed to decode video {}: {}", path.display(), e);
+                return None;
+            }
+        };
+
+        // Blocks once the buffer is full, and stops when the video is dropped
+        let (tx, frames) = mpsc::sync_channel(VIDEO_BUFFER_FRAMES);
+        std::thread::spawn(move || {
+            for frame in decoder.into_frames().flatten() {
+                let (numer, denom) = frame.delay().numer_denom_ms();
+                // GIFs with a zero delay are played at the common browser default of 100ms
+                let delay = if numer == 0 { 100.0 } else { numer as f32 / denom as f32 };
+                if tx.send((frame.into_buffer(), delay)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        // The first frame sizes the texture
+        let Ok((first, delay)) = frames.recv() else {
+            eprintln!("Video {} has no frames. Using static background.", path.display());
+            return None;
+        };
+        let texture = Texture2D::from_rgba8(first.width() as u16, first.height() as u16, first.as_raw());
+        Some(Self { texture, frames, offset, frame_end: delay, finished: false })
+    }
+
+    /// Show the frame for song time `time_ms`. Frames the game is already past are skipped; if
+    /// the decoder falls behind, the current frame stays up until it catches up.
+    fn update(&mut self, time_ms: f32) {
+        let t = time_ms - self.offset;
+        let mut latest = None;
+        while !self.finished && t >= self.frame_end {
+            match self.frames.try_recv() {
+                Ok((frame, delay)) => {
+                    self.frame_end += delay;
+                    latest = Some(frame);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.finished = true,
+            }
+        }
+        if let Some(frame) = latest {
+            self.texture.update_from_bytes(frame.width(), frame.height(), frame.as_raw());
+        }
+    }
+
+    /// False before the video starts and once its last frame is over
+    fn active(&self, time_ms: f32) -> bool {
+        let t = time_ms - self.offset;
+        t >= 0.0 && !(self.finished && t >= self.frame_end)
+    }
+}
+
+fn collect_event_lines(content: &str, out: &mut Vec<String>) {
+    let mut in_events = false;
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            in_events = trimmed == "[Events]";
+            continue;
+        }
+        if in_events && !trimmed.is_empty() && !trimmed.starts_with("//") {
+            out.push(line.trim_end().to_string());
+        }
+    }
+}
+
+fn parse_command(p: &[&str]) -> Option<Command> {
+    if p.len() < 5 {
+        return None;
+    }
+    let kind = match p[0] {
+        "F" => CommandKind::Fade,
+        "M" => CommandKind::Move,
+        "MX" => CommandKind::MoveX,
+        "MY" => CommandKind::MoveY,
+        "S" => CommandKind::Scale,
+        _ => return None,
+    };
+
+    let start: f32 = p[2].parse().ok()?;
+    // Empty end time means an instant command
+    let end: f32 = p[3].parse().unwrap_or(start);
+    let values: Vec<f32> = p[4..].iter().filter_map(|v| v.parse().ok()).collect();
+
+    // Only the first from/to pair is used; chained values are dropped
+    let (from, to) = if kind == CommandKind::Move {
+        if values.len() < 2 {
+            return None;
+        }
+        let from = (values[0], values[1]);
+        let to = if values.len() >= 4 { (values[2], values[3]) } else { from };
+        (from, to)
+    } else {
+        let from = *values.first()?;
+        let to = values.get(1).copied().unwrap_or(from);
+        ((from, from), (to, to))
+    };
+
+    Some(Command { kind, start, end: end.max(start), from, to })
+}
+
+fn parse_origin(s: &str) -> (f32, f32) {
+    match s {
+        "TopLeft" | "0" => (0.0, 0.0),
+        "CentreLeft" | "2" => (0.0, 0.5),
+        "TopRight" | "3" => (1.0, 0.0),
+        "BottomCentre" | "4" => (0.5, 1.0),
+        "TopCentre" | "5" => (0.5, 0.0),
+        "CentreRight" | "7" => (1.0, 0.5),
+        "BottomLeft" | "8" => (0.0, 1.0),
+        "BottomRight" | "9" => (1.0, 1.0),
+        _ => (0.5, 0.5), // Centre
+    }
+}
+
+fn unquote(s: &str) -> String {
+    s.trim().trim_matches('"').replace('\\', "/")
+}
@@ -0,0 +1,45 @@
+use macroquad::prelude::*;
+
+const TOAST_SHOW_TIME: f64 = 4.0; // seconds
+const MAX_TOASTS: usize = 5;
+
+/// Short notifications stacked in the bottom-left corner
+pub struct Toasts {
+    items: Vec<(String, Color, f64)>, // (message, color, time shown)
+}
+
+impl Toasts {
+    pub fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    pub fn success(&mut self, message: String) {
+        self.push(message, GREEN);
+    }
+
+    pub fn error(&mut self, message: String) {
+        eprintln!("{}", message);
+        self.push(message, RED);
+    }
+
+    fn push(&mut self, message: String, color: Color) {
+        self.items.push((message, color, get_time()));
+        if self.items.len() > MAX_TOASTS {
+            self.items.remove(0);
+        }
+    }
+
+    pub fn draw(&mut self) {
+        let now = get_time();
+        self.items.retain(|(_, _, shown)| now - shown < TOAST_SHOW_TIME);
+
+        for (i, (message, color, shown)) in self.items.iter().rev().enumerate() {
+            let alpha = (((TOAST_SHOW_TIME - (now - shown)) / 0.3).min(1.0)) as f32;
+            let y = screen_height() - 50.0 - i as f32 * 40.0;
+            let w = measure_text(message, None, 20, 1.0).width + 20.0;
+            draw_rectangle(20.0, y, w, 32.0, Color::new(0.1, 0.1, 0.1, 0.9 * alpha));
+            draw_rectangle(20.0, y, 4.0, 32.0, Color::new(color.r, color.g, color.b, alpha));
+            draw_text(message, 30.0, y + 22.0, 20.0, Color::new(1.0, 1.0, 1.0, alpha));
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use macroquad::prelude::*;
+use crate::models::GameOptions;
+
+const VOLUME_STEP: i32 = 5;
+const OVERLAY_SHOW_TIME: f64 = 1.5; // seconds after the last change
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum VolumeChannel {
+    Master,
+    Music,
+    Effects,
+}
+
+impl VolumeChannel {
+    fn next(self) -> Self {
+        match self {
+            VolumeChannel::Master => VolumeChannel::Music,
+            VolumeChannel::Music => VolumeChannel::Effects,
+            VolumeChannel::Effects => VolumeChannel::Master,
+        }
+    }
+
+    fn prev(self) -> Self {
+        match self {
+            VolumeChannel::Master => VolumeChannel::Effects,
+            VolumeChannel::Music => VolumeChannel::Master,
+            VolumeChannel::Effects => VolumeChannel::Music,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            VolumeChannel::Master => "MASTER",
+            VolumeChannel::Music => "MUSIC",
+            VolumeChannel::Effects => "EFFECTS",
+        }
+    }
+}
+
+/// Volume popup shared by menus and gameplay.
+/// Hold ALT and use the mouse wheel or UP/DOWN to change volume, LEFT/RIGHT to pick the channel.
+pub struct VolumeOverlay {
+    selected: VolumeChannel,
+    last_change: f64,
+}
+
+impl VolumeOverlay {
+    pub fn new() -> Self {
+        Self {
+            selected: VolumeChannel::Master,
+            last_change: -OVERLAY_SHOW_TIME,
+        }
+    }
+
+    /// Handle volume input; returns true if any volume changed
+    pub fn update(&mut self, options: &mut GameOptions) -> bool {
+        if !is_key_down(KeyCode::LeftAlt) && !is_key_down(KeyCode::RightAlt) {
+            return false;
+        }
+
+        if is_key_pressed(KeyCode::Left) {
+            self.selected = self.selected.prev();
+            self.last_change = get_time();
+        }
+        if is_key_pressed(KeyCode::Right) {
+            self.selected = self.selected.next();
+            self.last_change = get_time();
+        }
+
+        let (_, wheel_y) = mouse_wheel();
+        let delta = if wheel_y > 0.0 || is_key_pressed(KeyCode::Up) {
+            VOLUME_STEP
+        } else if wheel_y < 0.0 || is_key_pressed(KeyCode::Down) {
+            -VOLUME_STEP
+        } else {
+            return false;
+        };
+
+        let volume = Self::channel_volume(options, self.selected);
+        *volume = (*volume + delta).clamp(0, 100);
+        self.last_change = get_time();
+        true
+    }
+
+    fn channel_volume(options: &mut GameOptions, channel: VolumeChannel) -> &mut i32 {
+        match channel {
+            VolumeChannel::Master => &mut options.master_volume,
+            VolumeChannel::Music => &mut options.music_volume,
+            VolumeChannel::Effects => &mut options.effects_volume,
+        }
+    }
+
+    pub fn draw(&self, options: &GameOptions) {
+        let since = get_time() - self.last_change;
+        if since > OVERLAY_SHOW_TIME {
+            return;
+        }
+        let alpha = (((OVERLAY_SHOW_TIME - since) / 0.3).min(1.0)) as f32;
+
+        let w = 220.0;
+        let x = screen_width() - w - 20.0;
+        let y = screen_height() - 140.0;
+        draw_rectangle(x, y, w, 120.0, Color::new(0.1, 0.1, 0.1, 0.85 * alpha));
+
+        let channels = [
+            (VolumeChannel::Master, options.master_volume),
+            (VolumeChannel::Music, options.music_volume),
+            (VolumeChannel::Effects, options.effects_volume),
+        ];
+        for (i, (channel, value)) in channels.iter().enumerate() {
+            let row_y = y + 30.0 + i as f32 * 35.0;
+            let color = if *channel == self.selected {
+                Color::new(0.53, 0.81, 0.92, alpha) // SKYBLUE
+            } else {
+                Color::new(0.6, 0.6, 0.6, alpha)
+            };
+            draw_text(channel.label(), x + 10.0, row_y, 20.0, color);
+            draw_rectangle(x + 95.0, row_y - 12.0, 80.0, 10.0, Color::new(0.3, 0.3, 0.3, alpha));
+            draw_rectangle(x + 95.0, row_y - 12.0, 80.0 * (*value as f32 / 100.0), 10.0, color);
+            draw_text(format!("{}", value), x + 182.0, row_y, 20.0, color);
+        }
+    }
+}
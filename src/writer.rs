@@ -0,0 +1,228 @@
+use crate::models::TimingPoint;
+use crate::parser::{self, Beatmap, HitObject};
+
+// Files are written with CRLF like the ones osu! itself saves
+const NEWLINE: &str = "\r\n";
+
+/// Serialize a beatmap as an osu file format v14 mania chart.
+/// `parser::parse_beatmap` on the result gives back the same beatmap.
+pub fn write_osu(beatmap: &Beatmap) -> String {
+    let mut out = String::new();
+    let mut line = |s: String| {
+        out.push_str(&s);
+        out.push_str(NEWLINE);
+    };
+
+    line(String::from("osu file format v14"));
+    line(String::new());
+
+    line(String::from("[General]"));
+    line(format!("AudioFilename: {}", beatmap.audio_filename));
+    line(format!("AudioLeadIn: {}", beatmap.audio_lead_in));
+    line(format!("PreviewTime: {}", beatmap.preview_time));
+    line(String::from("Countdown: 0"));
+    line(format!("SampleSet: {}", beatmap.sample_set));
+    line(String::from("StackLeniency: 0.7"));
+    line(String::from("Mode: 3"));
+    line(String::from("LetterboxInBreaks: 0"));
+    line(String::from("SpecialStyle: 0"));
+    line(String::from("WidescreenStoryboard: 0"));
+    line(String::new());
+
+    line(String::from("[Metadata]"));
+    line(format!("Title:{}", beatmap.title));
+    line(format!("TitleUnicode:{}", beatmap.title_unicode));
+    line(format!("Artist:{}", beatmap.artist));
+    line(format!("ArtistUnicode:{}", beatmap.artist_unicode));
+    line(format!("Creator:{}", beatmap.creator));
+    line(format!("Version:{}", beatmap.version));
+    line(format!("Source:{}", beatmap.source));
+    line(format!("Tags:{}", beatmap.tags));
+    line(format!("BeatmapID:{}", beatmap.beatmap_id));
+    line(format!("BeatmapSetID:{}", beatmap.beatmap_set_id));
+    line(String::new());
+
+    // Mania uses CircleSize as the key count; ApproachRate has no effect
+    line(String::from("[Difficulty]"));
+    line(format!("HPDrainRate:{}", beatmap.hp_drain_rate));
+    line(format!("CircleSize:{}", beatmap.key_count));
+    line(format!("OverallDifficulty:{}", beatmap.overall_difficulty));
+    line(format!("ApproachRate:{}", beatmap.overall_difficulty));
+    line(format!("SliderMultiplier:{}", beatmap.slider_multiplier));
+    line(format!("SliderTickRate:{}", beatmap.slider_tick_rate));
+    line(String::new());
+
+    line(String::from("[Events]"));
+    line(String::from("//Background and Video events"));
+    for event in &beatmap.events {
+        line(event.clone());
+    }
+    line(String::new());
+
+    line(String::from("[TimingPoints]"));
+    for tp in &beatmap.timing_points {
+        line(timing_point_line(tp));
+    }
+    line(String::new());
+    line(String::new());
+
+    line(String::from("[HitObjects]"));
+    for object in &beatmap.hit_objects {
+        line(hit_object_line(object, beatmap.key_count));
+    }
+
+    out
+}
+
+/// `time,beatLength,meter,sampleSet,sampleIndex,volume,uninherited,effects`.
+/// Inherited points store their velocity as a negative inverse percentage.
+pub fn timing_point_line(tp: &TimingPoint) -> String {
+    let beat_length = if tp.uninherited { tp.beat_length } else { -100.0 / tp.velocity_mult };
+    format!(
+        "{},{},{},{},{},{},{},{}",
+        tp.time, beat_length, tp.meter, tp.sample_set, tp.sample_index,
+        tp.volume.round() as i32, tp.uninherited as i32, tp.effects
+    )
+}
+
+/// Taps are `x,192,time,1,hitSound,hitSample`; holds carry their end time in front of
+/// the hit sample: `x,192,time,128,hitSound,endTime:hitSample`
+pub fn hit_object_line(object: &HitObject, key_count: usize) -> String {
+    let x = parser::lane_x(object.lane, key_count);
+    let time = object.time.round() as i64;
+    match object.end_time.filter(|end| end - object.time >= 1.0) {
+        Some(end) => format!("{},192,{},128,{},{}:{}", x, time, object.hitsound, end.round() as i64, object.sample),
+        None => format!("{},192,{},1,{},{}", x, time, object.hitsound, object.sample),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_beatmap;
+
+    fn point(time: f32, beat_length: f32, velocity_mult: f32, uninherited: bool) -> TimingPoint {
+        TimingPoint {
+            time,
+            beat_length,
+            velocity_mult,
+            meter: 4,
+            sample_set: 1,
+            sample_index: 0,
+            volume: 70.0,
+            uninherited,
+            effects: 0,
+        }
+    }
+
+    fn tap(time: f32, lane: usize) -> HitObject {
+        HitObject { time, end_time: None, lane, hitsound: 0, sample: String::from("0:0:0:0:") }
+    }
+
+    fn hold(time: f32, end: f32, lane: usize) -> HitObject {
+        HitObject { time, end_time: Some(end), lane, hitsound: 2, sample: String::from("1:2:0:80:hit.wav") }
+    }
+
+    fn sample_beatmap(key_count: usize) -> Beatmap {
+        let mut beatmap = Beatmap {
+            audio_filename: String::from("song.ogg"),
+            audio_lead_in: 500,
+            preview_time: 12345,
+            title: String::from("Round Trip"),
+            title_unicode: String::from("ラウンドトリップ"),
+            artist: String::from("Tester"),
+            artist_unicode: String::from("テスター"),
+            creator: String::from("mapper"),
+            version: String::from("Hard: 7K"),
+            source: String::from("none"),
+            tags: String::from("test practice"),
+            beatmap_id: 42,
+            beatmap_set_id: 7,
+            hp_drain_rate: 7.5,
+            key_count,
+            overall_difficulty: 8.2,
+            slider_multiplier: 1.4,
+            slider_tick_rate: 1.0,
+            events: vec![String::from("0,0,\"bg.jpg\",0,0")],
+            timing_points: vec![
+                point(120.0, 500.0, 1.0, true),
+                point(120.0, 500.0, 0.5, false),
+                point(4120.0, 500.0, 1.25, false),
+                point(8120.5, 333.33334, 1.0, true),
+            ],
+            ..Beatmap::default()
+        };
+        for i in 0..40 {
+            let time = 120.0 + i as f32 * 125.0;
+            let lane = i % key_count;
+            if i % 5 == 0 {
+                beatmap.hit_objects.push(hold(time, time + 375.0, lane));
+            } else {
+                beatmap.hit_objects.push(tap(time, lane));
+            }
+        }
+        beatmap
+    }
+
+    /// Velocities go through `-100 / v` twice, so only compare them approximately
+    fn assert_same(a: &Beatmap, b: &Beatmap) {
+        assert_eq!(a.timing_points.len(), b.timing_points.len());
+        for (x, y) in a.timing_points.iter().zip(&b.timing_points) {
+            assert!((x.velocity_mult - y.velocity_mult).abs() < 1e-4, "{:?} != {:?}", x, y);
+            assert_eq!(TimingPoint { velocity_mult: 0.0, ..x.clone() }, TimingPoint { velocity_mult: 0.0, ..y.clone() });
+        }
+        let strip = |m: &Beatmap| Beatmap { timing_points: Vec::new(), ..m.clone() };
+        assert_eq!(strip(a), strip(b));
+    }
+
+    #[test]
+    fn write_then_parse_preserves_beatmap() {
+        let beatmap = sample_beatmap(7);
+        let parsed = parse_beatmap(&write_osu(&beatmap), 4);
+        assert_same(&beatmap, &parsed);
+    }
+
+    #[test]
+    fn parse_write_parse_is_stable() {
+        let first = parse_beatmap(&write_osu(&sample_beatmap(4)), 4);
+        let text = write_osu(&first);
+        let second = parse_beatmap(&text, 4);
+        assert_same(&first, &second);
+        assert_eq!(text, write_osu(&second));
+    }
+
+    #[test]
+    fn lanes_survive_for_every_key_count() {
+        for key_count in 1..=10 {
+            let beatmap = sample_beatmap(key_count);
+            let parsed = parse_beatmap(&write_osu(&beatmap), 4);
+            assert_eq!(parsed.key_count, key_count);
+            let lanes: Vec<usize> = parsed.hit_objects.iter().map(|o| o.lane).collect();
+            let expected: Vec<usize> = beatmap.hit_objects.iter().map(|o| o.lane).collect();
+            assert_eq!(lanes, expected, "{}K", key_count);
+        }
+    }
+
+    #[test]
+    fn hold_end_time_is_in_extras() {
+        let line = hit_object_line(&hold(1000.0, 1500.0, 0), 4);
+        assert_eq!(line, "64,192,1000,128,2,1500:1:2:0:80:hit.wav");
+        let line = hit_object_line(&tap(1000.0, 3), 4);
+        assert_eq!(line, "448,192,1000,1,0,0:0:0:0:");
+    }
+
+    #[test]
+    fn parses_real_osu_file() {
+        let content = "osu file format v14\r\n\r\n[General]\r\nAudioFilename: audio.mp3\r\nMode: 3\r\n\r\n\
+            [Metadata]\r\nTitle:Song\r\nVersion:Easy\r\n\r\n[Difficulty]\r\nCircleSize:4\r\nOverallDifficulty:7\r\n\r\n\
+            [TimingPoints]\r\n0,400,4,1,0,60,1,0\r\n1000,-50,4,1,0,60,0,0\r\n\r\n\
+            [HitObjects]\r\n64,192,500,1,0,0:0:0:0:\r\n192,192,1000,128,0,1600:0:0:0:0:\r\n";
+        let first = parse_beatmap(content, 4);
+        assert_eq!(first.hit_objects.len(), 2);
+        assert_eq!(first.hit_objects[1].end_time, Some(1600.0));
+        assert_eq!(first.timing_points[1].velocity_mult, 2.0);
+
+        let second = parse_beatmap(&write_osu(&first), 4);
+        assert_same(&first, &second);
+    }
+}